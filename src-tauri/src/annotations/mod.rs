@@ -0,0 +1,289 @@
+use crate::error::{Error, Result};
+use crate::{chapter_at, Chapter};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Characters of surrounding text kept on each side of a quote
+const CONTEXT_CHARS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightColor {
+    Yellow,
+    Green,
+    Blue,
+    Pink,
+    Purple,
+}
+
+impl HighlightColor {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HighlightColor::Yellow => "yellow",
+            HighlightColor::Green => "green",
+            HighlightColor::Blue => "blue",
+            HighlightColor::Pink => "pink",
+            HighlightColor::Purple => "purple",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "green" => HighlightColor::Green,
+            "blue" => HighlightColor::Blue,
+            "pink" => HighlightColor::Pink,
+            "purple" => HighlightColor::Purple,
+            _ => HighlightColor::Yellow,
+        }
+    }
+}
+
+/// Where an annotation sits in a document. Offsets alone break when a parser
+/// change shifts `content`, so the quoted text and its surroundings are kept
+/// too and used to find the passage again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextAnchor {
+    pub chapter_id: Option<String>,
+    pub chapter_offset: usize, // start_position relative to the chapter start
+    pub start_position: usize,
+    pub end_position: usize,
+    pub quote: String,
+    pub prefix: String,
+    pub suffix: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: String,
+    pub document_id: String,
+    pub anchor: TextAnchor,
+    pub color: HighlightColor,
+    pub note: Option<String>,
+    pub orphaned: bool, // The quoted text could not be found in the current content
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TextAnchor {
+    pub fn new(content: &str, chapters: &[Chapter], start: usize, end: usize) -> Result<Self> {
+        if start >= end || end > content.len() {
+            return Err(Error::InvalidInput(format!("Invalid selection {}..{}", start, end)));
+        }
+        if !content.is_char_boundary(start) || !content.is_char_boundary(end) {
+            return Err(Error::InvalidInput(format!("Selection {}..{} splits a character", start, end)));
+        }
+
+        let chapter = chapter_at(chapters, start);
+        let prefix_start = content[..start]
+            .char_indices()
+            .rev()
+            .nth(CONTEXT_CHARS - 1)
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        let suffix: String = content[end..].chars().take(CONTEXT_CHARS).collect();
+
+        Ok(Self {
+            chapter_id: chapter.map(|c| c.id.clone()),
+            chapter_offset: start - chapter.map(|c| c.start_position).unwrap_or(0),
+            start_position: start,
+            end_position: end,
+            quote: content[start..end].to_string(),
+            prefix: content[prefix_start..start].to_string(),
+            suffix,
+        })
+    }
+
+    /// Finds the anchored passage in (possibly re-parsed) content. Returns the
+    /// re-attached anchor, or `None` when the quote no longer occurs.
+    pub fn resolve(&self, content: &str, chapters: &[Chapter]) -> Option<TextAnchor> {
+        // Still in place, unless it's a repeat of the quote that moved there
+        let in_place = content.get(self.start_position..self.end_position) == Some(self.quote.as_str());
+        if in_place && self.context_matches(content, self.start_position, self.end_position) == 2 {
+            return Some(self.clone());
+        }
+
+        // Where the passage should be if only its chapter moved
+        let expected = self
+            .chapter_id
+            .as_ref()
+            .and_then(|id| chapters.iter().find(|c| &c.id == id))
+            .map(|c| c.start_position + self.chapter_offset)
+            .unwrap_or(self.start_position);
+
+        let (start, end) = find_ignoring_whitespace(content, &self.quote)
+            .into_iter()
+            .max_by_key(|&(start, end)| {
+                (
+                    self.context_matches(content, start, end),
+                    std::cmp::Reverse(start.abs_diff(expected)),
+                )
+            })?;
+
+        TextAnchor::new(content, chapters, start, end).ok()
+    }
+
+    fn context_matches(&self, content: &str, start: usize, end: usize) -> usize {
+        let before_start = char_boundary(content, start.saturating_sub(self.prefix.len() * 2));
+        let after_end = char_boundary(content, (end + self.suffix.len() * 2).min(content.len()));
+        let before = collapse_whitespace(&content[before_start..start]);
+        let after = collapse_whitespace(&content[end..after_end.max(end)]);
+
+        usize::from(before.ends_with(&collapse_whitespace(&self.prefix)))
+            + usize::from(after.starts_with(&collapse_whitespace(&self.suffix)))
+    }
+}
+
+/// Finds every occurrence of `quote` in `content`, treating any run of
+/// whitespace as equal to any other (parsers differ in how they break lines).
+pub fn find_ignoring_whitespace(content: &str, quote: &str) -> Vec<(usize, usize)> {
+    let words: Vec<&str> = quote.split_whitespace().collect();
+    let Some(first) = words.first() else {
+        return Vec::new();
+    };
+
+    content
+        .match_indices(first)
+        .filter_map(|(start, _)| {
+            let mut position = start + first.len();
+            for word in &words[1..] {
+                let rest = &content[position..];
+                let trimmed = rest.trim_start();
+                if trimmed.len() == rest.len() || !trimmed.starts_with(word) {
+                    return None;
+                }
+                position += rest.len() - trimmed.len() + word.len();
+            }
+            Some((start, position))
+        })
+        .collect()
+}
+
+/// The closest char boundary at or before `index`.
+pub fn char_boundary(content: &str, mut index: usize) -> usize {
+    while !content.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Call me Ishmael. Some years ago, never mind how long precisely, having little money in my purse, I thought I would sail about a little and see the watery part of the world.";
+
+    fn chapter(id: &str, start: usize, end: usize) -> Chapter {
+        Chapter {
+            id: id.to_string(),
+            title: id.to_string(),
+            start_position: start,
+            end_position: end,
+            level: 0,
+            parent_id: None,
+            href: None,
+        }
+    }
+
+    fn anchor(content: &str, quote: &str) -> TextAnchor {
+        let start = content.find(quote).unwrap();
+        TextAnchor::new(content, &[], start, start + quote.len()).unwrap()
+    }
+
+    fn quoted<'a>(content: &'a str, anchor: &TextAnchor) -> &'a str {
+        &content[anchor.start_position..anchor.end_position]
+    }
+
+    #[test]
+    fn anchors_keep_their_surroundings() {
+        let chapters = [chapter("loomings", 0, TEXT.len())];
+        let start = TEXT.find("little money").unwrap();
+        let anchor = TextAnchor::new(TEXT, &chapters, start, start + "little money".len()).unwrap();
+
+        assert_eq!(anchor.quote, "little money");
+        assert_eq!(anchor.prefix, "mind how long precisely, having ");
+        assert_eq!(anchor.suffix, " in my purse, I thought I would ");
+        assert_eq!(anchor.chapter_id.as_deref(), Some("loomings"));
+        assert_eq!(anchor.chapter_offset, start);
+    }
+
+    #[test]
+    fn invalid_selections_are_refused() {
+        let content = "Ça va";
+        assert!(TextAnchor::new(content, &[], 3, 3).is_err());
+        assert!(TextAnchor::new(content, &[], 0, 99).is_err());
+        // Inside the two bytes of "Ç"
+        assert!(TextAnchor::new(content, &[], 1, 4).is_err());
+        assert!(TextAnchor::new(content, &[], 0, 2).is_ok());
+    }
+
+    #[test]
+    fn unchanged_text_resolves_in_place() {
+        let anchor = anchor(TEXT, "watery part");
+        assert_eq!(anchor.resolve(TEXT, &[]), Some(anchor));
+    }
+
+    #[test]
+    fn follows_text_shifted_by_an_insertion() {
+        let anchor = anchor(TEXT, "watery part");
+        let shifted = format!("CHAPTER 1. Loomings.\n\n{}", TEXT);
+
+        let resolved = anchor.resolve(&shifted, &[]).unwrap();
+        assert_eq!(resolved.start_position, anchor.start_position + 22);
+        assert_eq!(quoted(&shifted, &resolved), "watery part");
+    }
+
+    #[test]
+    fn follows_changed_whitespace() {
+        let anchor = anchor(TEXT, "sail about a little");
+        let rewrapped = TEXT.replace("sail about", "sail\n   about").replace("a little", "a\tlittle");
+
+        let resolved = anchor.resolve(&rewrapped, &[]).unwrap();
+        assert_eq!(quoted(&rewrapped, &resolved), "sail\n   about a\tlittle");
+        assert_eq!(resolved.quote, "sail\n   about a\tlittle");
+
+        assert_eq!(find_ignoring_whitespace("a  b a b ab", "a b"), [(0, 4), (5, 8)]);
+        assert!(find_ignoring_whitespace("a b", " ").is_empty());
+    }
+
+    #[test]
+    fn context_picks_between_repeated_quotes() {
+        let content = "The whale was white. The sea was grey. The whale was old.";
+        let second = content.rfind("The whale").unwrap();
+        let anchor = TextAnchor::new(content, &[], second, second + "The whale".len()).unwrap();
+
+        // Both occurrences move; the one after "grey." is still the one meant,
+        // even though the first now sits where the anchor was
+        let edited = format!("{}{}", "x".repeat(second), content);
+        let resolved = anchor.resolve(&edited, &[]).unwrap();
+        assert_eq!(resolved.start_position, second * 2);
+        assert!(edited[..resolved.start_position].ends_with("grey. "));
+    }
+
+    #[test]
+    fn chapters_guide_the_search() {
+        let content = "One. The end. Two. The end.";
+        let chapters = [chapter("one", 0, 14), chapter("two", 14, content.len())];
+        let start = content.rfind("The end").unwrap();
+        let mut anchor = TextAnchor::new(content, &chapters, start, start + 7).unwrap();
+        // Identical context both times, so only the chapter tells them apart
+        anchor.prefix.clear();
+        anchor.suffix.clear();
+
+        let edited = format!("Intro. {}", content);
+        let moved = [chapter("one", 7, 21), chapter("two", 21, edited.len())];
+        let resolved = anchor.resolve(&edited, &moved).unwrap();
+        assert_eq!(resolved.start_position, start + 7);
+        assert_eq!(resolved.chapter_id.as_deref(), Some("two"));
+    }
+
+    #[test]
+    fn removed_quotes_are_orphaned() {
+        let anchor = anchor(TEXT, "watery part");
+        assert_eq!(anchor.resolve(&TEXT.replace("watery", "wet"), &[]), None);
+        assert_eq!(anchor.resolve("", &[]), None);
+    }
+}
@@ -0,0 +1,49 @@
+-- A library.db as written before schema versioning existed
+CREATE TABLE documents (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    author TEXT,
+    file_path TEXT NOT NULL UNIQUE,
+    file_type TEXT NOT NULL,
+    total_pages INTEGER NOT NULL DEFAULT 0,
+    current_position INTEGER NOT NULL DEFAULT 0,
+    last_read DATETIME DEFAULT CURRENT_TIMESTAMP,
+    added_date DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE user_settings (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    theme TEXT NOT NULL DEFAULT 'light',
+    font_family TEXT NOT NULL DEFAULT 'georgia',
+    font_size INTEGER NOT NULL DEFAULT 18,
+    line_height REAL NOT NULL DEFAULT 1.6,
+    letter_spacing REAL NOT NULL DEFAULT 0.0,
+    words_per_page INTEGER NOT NULL DEFAULT 400,
+    page_margin TEXT NOT NULL DEFAULT 'normal',
+    justify_text BOOLEAN NOT NULL DEFAULT TRUE,
+    hyphenation BOOLEAN NOT NULL DEFAULT TRUE,
+    animation_speed TEXT NOT NULL DEFAULT 'normal',
+    page_curl BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+CREATE TABLE chapters (
+    id TEXT PRIMARY KEY,
+    document_id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    start_position INTEGER NOT NULL,
+    end_position INTEGER NOT NULL,
+    chapter_order INTEGER NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_chapters_document_id ON chapters (document_id);
+
+INSERT INTO documents (id, title, author, file_path, file_type, total_pages, current_position, last_read, added_date)
+VALUES ('0b6f6a52-6a3e-4f1c-9d55-3c1e8f0c2a11', 'Moby Dick', 'Herman Melville', '/books/moby-dick.epub', 'epub', 120, 4200,
+        '2024-03-01T20:15:00Z', '2024-02-11T09:00:00Z');
+
+INSERT INTO user_settings (id, theme, font_size) VALUES (1, 'dark', 22);
+
+INSERT INTO chapters (id, document_id, title, start_position, end_position, chapter_order)
+VALUES ('chapter_0', '0b6f6a52-6a3e-4f1c-9d55-3c1e8f0c2a11', 'Loomings', 0, 9000, 0);
@@ -0,0 +1,296 @@
+use crate::error::{Error, Result};
+use sqlx::SqlitePool;
+
+struct Migration {
+    version: i64,
+    description: &'static str,
+    sql: &'static str,
+}
+
+// Applied in order, each once. Never edit a migration that has shipped; add a
+// new one instead.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        sql: include_str!("migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "document content hash",
+        sql: include_str!("migrations/0002_content_hash.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "nested chapters",
+        sql: include_str!("migrations/0003_nested_chapters.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "annotations",
+        sql: include_str!("migrations/0004_annotations.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "bookmarks",
+        sql: include_str!("migrations/0005_bookmarks.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "full-text search",
+        sql: include_str!("migrations/0006_full_text_search.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "page labels in the search index",
+        sql: include_str!("migrations/0007_search_page_labels.sql"),
+    },
+    Migration {
+        version: 8,
+        description: "text encodings",
+        sql: include_str!("migrations/0008_text_encoding.sql"),
+    },
+    Migration {
+        version: 9,
+        description: "plain-text chapter detection settings",
+        sql: include_str!("migrations/0009_chapter_detection.sql"),
+    },
+    Migration {
+        version: 10,
+        description: "re-parse for Project Gutenberg boilerplate",
+        sql: include_str!("migrations/0010_gutenberg_boilerplate.sql"),
+    },
+    Migration {
+        version: 11,
+        description: "missing file flag",
+        sql: include_str!("migrations/0011_missing_files.sql"),
+    },
+    Migration {
+        version: 12,
+        description: "collections and tags",
+        sql: include_str!("migrations/0012_collections_tags.sql"),
+    },
+    Migration {
+        version: 13,
+        description: "smart shelves",
+        sql: include_str!("migrations/0013_smart_shelves.sql"),
+    },
+    Migration {
+        version: 14,
+        description: "library folders",
+        sql: include_str!("migrations/0014_library_folders.sql"),
+    },
+    Migration {
+        version: 15,
+        description: "import jobs",
+        sql: include_str!("migrations/0015_import_jobs.sql"),
+    },
+    Migration {
+        version: 16,
+        description: "metadata overrides",
+        sql: include_str!("migrations/0016_metadata_overrides.sql"),
+    },
+    Migration {
+        version: 17,
+        description: "library sort keys",
+        sql: include_str!("migrations/0017_library_sort.sql"),
+    },
+];
+
+/// Schema version this build writes.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Brings the database up to the latest schema. Fails without touching
+/// anything if the database was written by a newer version of the app.
+pub async fn run(pool: &SqlitePool) -> Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    let mut current = current_version(pool).await?;
+
+    if current > latest_version() {
+        return Err(Error::DatabaseTooNew {
+            found: current,
+            supported: latest_version(),
+        });
+    }
+
+    // Databases from before versioning existed have the initial schema
+    if current == 0 && table_exists(pool, "documents").await? {
+        record(pool, &MIGRATIONS[0]).await?;
+        current = MIGRATIONS[0].version;
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(migration.sql)
+            .execute(&mut *tx)
+            .await
+            .map_err(|source| Error::MigrationFailed {
+                version: migration.version,
+                description: migration.description,
+                source,
+            })?;
+
+        sqlx::query("INSERT INTO schema_migrations (version, description) VALUES (?, ?)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+pub async fn current_version(pool: &SqlitePool) -> Result<i64> {
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await?;
+
+    Ok(version.unwrap_or(0))
+}
+
+async fn record(pool: &SqlitePool, migration: &Migration) -> Result<()> {
+    sqlx::query("INSERT INTO schema_migrations (version, description) VALUES (?, ?)")
+        .bind(migration.version)
+        .bind(migration.description)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn table_exists(pool: &SqlitePool, table: &str) -> Result<bool> {
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table)
+            .fetch_one(pool)
+            .await?;
+
+    Ok(count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing::TempDatabase;
+    use crate::database::{Bookmark, Database};
+    use chrono::Utc;
+    use sqlx::Row;
+
+    async fn column_names(pool: &SqlitePool, table: &str) -> Vec<String> {
+        sqlx::query(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .fetch_all(pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("name"))
+            .collect()
+    }
+
+    #[test]
+    fn migrations_are_numbered_in_order() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, index as i64 + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn new_database_gets_latest_schema() {
+        let temp = TempDatabase::new();
+        let db = Database::open(&temp.url()).await.unwrap();
+
+        assert_eq!(current_version(&db.pool).await.unwrap(), latest_version());
+        assert_eq!(db.get_settings().await.unwrap().theme, "light");
+    }
+
+    #[tokio::test]
+    async fn legacy_database_is_upgraded() {
+        let temp = TempDatabase::new();
+        temp.load_fixture(include_str!("fixtures/legacy_library.sql")).await;
+
+        let db = Database::open(&temp.url()).await.unwrap();
+        assert_eq!(current_version(&db.pool).await.unwrap(), latest_version());
+
+        // Existing rows survive with the new columns empty
+        let document = db
+            .get_document("0b6f6a52-6a3e-4f1c-9d55-3c1e8f0c2a11")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(document.title, "Moby Dick");
+        assert_eq!(document.current_position, 4200);
+        assert_eq!(document.content_hash, None);
+        assert!(!document.file_missing);
+
+        let settings = db.get_settings().await.unwrap();
+        assert_eq!(settings.theme, "dark");
+        assert_eq!(settings.font_size, 22);
+
+        // The chapter cache is rebuilt in the new shape
+        assert!(column_names(&db.pool, "chapters").await.contains(&"level".to_string()));
+        assert!(db.get_cached_chapters(&document.id).await.unwrap().is_none());
+
+        // Its saved position predates stripped boilerplate, and is moved once
+        assert!(db.take_position_repair(&document.id).await.unwrap());
+        assert!(!db.take_position_repair(&document.id).await.unwrap());
+
+        // Tables added since the first release are usable
+        db.save_bookmark(&Bookmark {
+            id: "bookmark".to_string(),
+            document_id: document.id.clone(),
+            name: "Call me Ishmael".to_string(),
+            position: 0,
+            chapter_id: None,
+            chapter_title: None,
+            snippet: String::new(),
+            created_at: Utc::now(),
+        })
+        .await
+        .unwrap();
+        assert!(db.get_annotations(&document.id).await.unwrap().is_empty());
+        assert_eq!(db.get_unindexed_documents().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn reopening_does_not_rerun_migrations() {
+        let temp = TempDatabase::new();
+        let db = Database::open(&temp.url()).await.unwrap();
+        db.pool.close().await;
+
+        let db = Database::open(&temp.url()).await.unwrap();
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_migrations")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+
+    #[tokio::test]
+    async fn newer_database_is_refused() {
+        let temp = TempDatabase::new();
+        let db = Database::open(&temp.url()).await.unwrap();
+        sqlx::query("INSERT INTO schema_migrations (version, description) VALUES (?, 'from the future')")
+            .bind(latest_version() + 1)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        db.pool.close().await;
+
+        assert!(Database::open(&temp.url()).await.is_err());
+    }
+}
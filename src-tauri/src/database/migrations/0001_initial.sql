@@ -0,0 +1,42 @@
+CREATE TABLE IF NOT EXISTS documents (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    author TEXT,
+    file_path TEXT NOT NULL UNIQUE,
+    file_type TEXT NOT NULL,
+    total_pages INTEGER NOT NULL DEFAULT 0,
+    current_position INTEGER NOT NULL DEFAULT 0,
+    last_read DATETIME DEFAULT CURRENT_TIMESTAMP,
+    added_date DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS user_settings (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    theme TEXT NOT NULL DEFAULT 'light',
+    font_family TEXT NOT NULL DEFAULT 'georgia',
+    font_size INTEGER NOT NULL DEFAULT 18,
+    line_height REAL NOT NULL DEFAULT 1.6,
+    letter_spacing REAL NOT NULL DEFAULT 0.0,
+    words_per_page INTEGER NOT NULL DEFAULT 400,
+    page_margin TEXT NOT NULL DEFAULT 'normal',
+    justify_text BOOLEAN NOT NULL DEFAULT TRUE,
+    hyphenation BOOLEAN NOT NULL DEFAULT TRUE,
+    animation_speed TEXT NOT NULL DEFAULT 'normal',
+    page_curl BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+INSERT OR IGNORE INTO user_settings (id) VALUES (1);
+
+-- Chapter cache
+CREATE TABLE IF NOT EXISTS chapters (
+    id TEXT PRIMARY KEY,
+    document_id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    start_position INTEGER NOT NULL,
+    end_position INTEGER NOT NULL,
+    chapter_order INTEGER NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_chapters_document_id ON chapters (document_id);
@@ -0,0 +1,21 @@
+-- The chapters table is only a parse cache, so it is rebuilt rather than
+-- migrated. This also drops rows left behind by older versions, which
+-- replaced documents under a fresh id on every open.
+DROP TABLE chapters;
+
+CREATE TABLE chapters (
+    id TEXT NOT NULL,
+    document_id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    start_position INTEGER NOT NULL,
+    end_position INTEGER NOT NULL,
+    level INTEGER NOT NULL DEFAULT 0,
+    parent_id TEXT,
+    href TEXT,
+    chapter_order INTEGER NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (document_id, id),
+    FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_chapters_document_id ON chapters (document_id);
@@ -0,0 +1,13 @@
+CREATE TABLE bookmarks (
+    id TEXT PRIMARY KEY,
+    document_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    chapter_id TEXT,
+    chapter_title TEXT,
+    snippet TEXT NOT NULL DEFAULT '',
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_bookmarks_document_id ON bookmarks (document_id);
@@ -0,0 +1,14 @@
+-- Full-text index over passages of every imported document. FTS5 tables
+-- can't carry foreign keys, so rows are removed explicitly when their
+-- document goes away.
+CREATE VIRTUAL TABLE document_text USING fts5(
+    document_id UNINDEXED,
+    chapter_id UNINDEXED,
+    chapter_title UNINDEXED,
+    start_position UNINDEXED,
+    body,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+-- Content hash the document was last indexed at
+ALTER TABLE documents ADD COLUMN indexed_hash TEXT;
@@ -0,0 +1,15 @@
+-- FTS5 tables can't be altered, so the index is rebuilt with the new column
+-- and every document is queued for re-indexing
+DROP TABLE document_text;
+
+CREATE VIRTUAL TABLE document_text USING fts5(
+    document_id UNINDEXED,
+    chapter_id UNINDEXED,
+    chapter_title UNINDEXED,
+    start_position UNINDEXED,
+    body,
+    page_label UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+UPDATE documents SET indexed_hash = NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE documents ADD COLUMN encoding TEXT;
+
+ALTER TABLE documents ADD COLUMN encoding_override TEXT;
@@ -0,0 +1,7 @@
+ALTER TABLE user_settings ADD COLUMN detect_numbered_chapters BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE user_settings ADD COLUMN detect_part_headings BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE user_settings ADD COLUMN detect_caps_headings BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE user_settings ADD COLUMN detect_markdown_headings BOOLEAN NOT NULL DEFAULT TRUE;
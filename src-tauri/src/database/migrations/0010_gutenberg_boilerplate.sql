@@ -0,0 +1,19 @@
+-- Project Gutenberg books lose their header and licence from the content,
+-- which moves every offset, so cached chapters and the index are rebuilt
+DELETE FROM chapters;
+
+-- Reading positions and bookmarks saved until now count the header, so books
+-- that were stripped have them moved onto the stripped text the next time
+-- they're opened. Only books with something to move are flagged, and of those
+-- already indexed, only the ones whose text has a Project Gutenberg marker.
+ALTER TABLE documents ADD COLUMN reanchor_positions BOOLEAN NOT NULL DEFAULT 0;
+
+UPDATE documents SET reanchor_positions = 1
+WHERE file_type IN ('txt', 'epub')
+  AND (current_position > 0 OR id IN (SELECT document_id FROM bookmarks))
+  AND (indexed_hash IS NULL OR id IN (
+      SELECT document_id FROM document_text
+      WHERE body LIKE '%START OF%PROJECT GUTENBERG%' OR body LIKE '%END*THE SMALL PRINT%'
+  ));
+
+UPDATE documents SET indexed_hash = NULL;
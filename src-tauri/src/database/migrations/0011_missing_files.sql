@@ -0,0 +1,2 @@
+-- Set by the library health check when a document's file isn't at file_path
+ALTER TABLE documents ADD COLUMN file_missing BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+CREATE TABLE smart_shelves (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    filter TEXT NOT NULL, -- JSON, see database::shelves::ShelfFilter
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
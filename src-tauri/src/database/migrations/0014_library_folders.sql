@@ -0,0 +1,5 @@
+-- Folders whose books are imported automatically, and watched for changes
+CREATE TABLE library_folders (
+    path TEXT PRIMARY KEY,
+    added_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,18 @@
+-- Bulk imports, saved file by file so one cut short can be resumed
+CREATE TABLE import_jobs (
+    id TEXT PRIMARY KEY,
+    status TEXT NOT NULL DEFAULT 'running', -- running, cancelled or finished
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE import_job_files (
+    job_id TEXT NOT NULL,
+    file_path TEXT NOT NULL,
+    position INTEGER NOT NULL, -- Order the files were given in
+    status TEXT NOT NULL DEFAULT 'pending', -- pending, imported, relocated, skipped or failed
+    error_kind TEXT,
+    error_message TEXT,
+    PRIMARY KEY (job_id, file_path),
+    FOREIGN KEY (job_id) REFERENCES import_jobs (id) ON DELETE CASCADE
+);
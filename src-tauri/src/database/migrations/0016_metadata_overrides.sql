@@ -0,0 +1,29 @@
+-- Metadata edited by the user. Each column replaces what the parser found
+-- when set; the parsed values stay in documents, so re-reading a file never
+-- undoes an edit.
+CREATE TABLE document_metadata (
+    document_id TEXT PRIMARY KEY,
+    title TEXT,
+    authors TEXT, -- JSON array of names
+    series TEXT,
+    series_index REAL,
+    publisher TEXT,
+    language TEXT,
+    published_date TEXT, -- YYYY, YYYY-MM or YYYY-MM-DD
+    isbn TEXT, -- ISBN-10 or ISBN-13, without hyphens
+    description TEXT,
+    title_sort TEXT,
+    author_sort TEXT,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
+);
+
+-- Documents as the library shows them, with the user's title and authors
+CREATE VIEW library_documents AS
+SELECT d.id,
+       COALESCE(m.title, d.title) AS title,
+       COALESCE((SELECT group_concat(a.value, ', ') FROM json_each(m.authors) a), d.author) AS author,
+       d.file_path, d.file_type, d.content_hash, d.encoding, d.encoding_override, d.total_pages,
+       d.current_position, d.last_read, d.added_date, d.file_missing
+FROM documents d
+LEFT JOIN document_metadata m ON m.document_id = d.id;
@@ -0,0 +1,14 @@
+-- The library sorts by the user's sort keys, falling back to the title and
+-- authors as shown
+DROP VIEW library_documents;
+
+CREATE VIEW library_documents AS
+SELECT d.id,
+       COALESCE(m.title, d.title) AS title,
+       COALESCE((SELECT group_concat(a.value, ', ') FROM json_each(m.authors) a), d.author) AS author,
+       d.file_path, d.file_type, d.content_hash, d.encoding, d.encoding_override, d.total_pages,
+       d.current_position, d.last_read, d.added_date, d.file_missing,
+       COALESCE(m.title_sort, m.title, d.title) AS title_sort,
+       COALESCE(m.author_sort, (SELECT group_concat(a.value, ', ') FROM json_each(m.authors) a), d.author) AS author_sort
+FROM documents d
+LEFT JOIN document_metadata m ON m.document_id = d.id;
@@ -1,9 +1,20 @@
-use anyhow::Result;
+use crate::annotations::{Annotation, HighlightColor, TextAnchor};
+use crate::error::{Error, Result};
+use crate::library::metadata::MetadataOverrides;
+use crate::search::{self, LibrarySearchHit, Passage};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{migrate::MigrateDatabase, Row, Sqlite, SqlitePool};
+use sqlx::{migrate::MigrateDatabase, sqlite::SqliteRow, Row, Sqlite, SqlitePool};
 use std::path::PathBuf;
 
+mod migrations;
+mod shelves;
+#[cfg(test)]
+pub(crate) mod testing;
+
+pub use shelves::ShelfFilter;
+use shelves::{bind_params, Param};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredDocument {
     pub id: String,
@@ -11,10 +22,172 @@ pub struct StoredDocument {
     pub author: Option<String>,
     pub file_path: String,
     pub file_type: String,
+    pub content_hash: Option<String>, // None for rows imported before hashing existed
+    pub encoding: Option<String>, // Text encoding used on the last open (TXT)
+    pub encoding_override: Option<String>, // Encoding chosen by the user, replacing detection
     pub total_pages: i32,
     pub current_position: i32,
     pub last_read: DateTime<Utc>,
     pub added_date: DateTime<Utc>,
+    pub file_missing: bool, // No file at file_path when last checked
+}
+
+/// A named position in a document. `position` is an offset into the
+/// document's text, like chapter and annotation positions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: String,
+    pub document_id: String,
+    pub name: String,
+    pub position: usize,
+    pub chapter_id: Option<String>,
+    pub chapter_title: Option<String>,
+    pub snippet: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The two ways of grouping documents by hand. They behave the same; a
+/// collection is a project or reading list, a tag a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupKind {
+    Collection,
+    Tag,
+}
+
+impl GroupKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GroupKind::Collection => "collection",
+            GroupKind::Tag => "tag",
+        }
+    }
+
+    fn table(self) -> &'static str {
+        match self {
+            GroupKind::Collection => "collections",
+            GroupKind::Tag => "tags",
+        }
+    }
+
+    // Table linking the groups to documents, and its column naming the group
+    fn links(self) -> (&'static str, &'static str) {
+        match self {
+            GroupKind::Collection => ("document_collections", "collection_id"),
+            GroupKind::Tag => ("document_tags", "tag_id"),
+        }
+    }
+}
+
+/// The order the library is listed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LibrarySort {
+    #[default]
+    Recent, // Most recently read first
+    Title,  // By title_sort, then the title
+    Author, // By author_sort, then the authors; books without one last
+}
+
+impl LibrarySort {
+    fn order_by(self) -> &'static str {
+        match self {
+            LibrarySort::Recent => "last_read DESC",
+            LibrarySort::Title => "title_sort COLLATE NOCASE, last_read DESC",
+            LibrarySort::Author => {
+                "author_sort IS NULL, author_sort COLLATE NOCASE, title_sort COLLATE NOCASE"
+            }
+        }
+    }
+}
+
+/// A collection or tag, with how many documents it holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub id: String,
+    pub kind: GroupKind,
+    pub name: String,
+    pub document_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A saved query over the library. Its documents are found afresh on every
+/// read, so it always reflects the library as it is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartShelf {
+    pub id: String,
+    pub name: String,
+    pub filter: ShelfFilter,
+    pub document_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where a bulk import stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportJobStatus {
+    Running,
+    Interrupted, // Saved as running, but the app closed before it finished
+    Cancelled,
+    Finished,
+}
+
+impl ImportJobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImportJobStatus::Running => "running",
+            ImportJobStatus::Interrupted => "interrupted",
+            ImportJobStatus::Cancelled => "cancelled",
+            ImportJobStatus::Finished => "finished",
+        }
+    }
+
+    fn from_str(status: &str) -> Self {
+        match status {
+            "interrupted" => ImportJobStatus::Interrupted,
+            "cancelled" => ImportJobStatus::Cancelled,
+            "finished" => ImportJobStatus::Finished,
+            _ => ImportJobStatus::Running,
+        }
+    }
+}
+
+/// What became of one file offered to the library.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFileStatus {
+    Pending,
+    Imported,
+    Relocated, // Already in the library under another path, which has gone
+    Skipped,   // Already in the library, or not a book
+    Failed,
+}
+
+impl ImportFileStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImportFileStatus::Pending => "pending",
+            ImportFileStatus::Imported => "imported",
+            ImportFileStatus::Relocated => "relocated",
+            ImportFileStatus::Skipped => "skipped",
+            ImportFileStatus::Failed => "failed",
+        }
+    }
+}
+
+/// A bulk import, with how many of its files have reached each status.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportJob {
+    pub id: String,
+    pub status: ImportJobStatus,
+    pub total: i64,
+    pub pending: i64,
+    pub imported: i64,
+    pub relocated: i64,
+    pub skipped: i64,
+    pub failed: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +203,11 @@ pub struct UserSettings {
     pub hyphenation: bool,
     pub animation_speed: String,
     pub page_curl: bool,
+    // Plain-text chapter detection, see parsers::txt_chapters
+    pub detect_numbered_chapters: bool,
+    pub detect_part_headings: bool,
+    pub detect_caps_headings: bool,
+    pub detect_markdown_headings: bool,
 }
 
 impl Default for UserSettings {
@@ -46,6 +224,10 @@ impl Default for UserSettings {
             hyphenation: true,
             animation_speed: "normal".to_string(),
             page_curl: true,
+            detect_numbered_chapters: true,
+            detect_part_headings: true,
+            detect_caps_headings: true,
+            detect_markdown_headings: true,
         }
     }
 }
@@ -54,105 +236,147 @@ pub struct Database {
     pool: SqlitePool,
 }
 
+// Read from the library_documents view, where the user's edits to the title
+// and author replace the parsed ones
+const DOCUMENT_COLUMNS: &str =
+    "id, title, author, file_path, file_type, content_hash, encoding, encoding_override, total_pages, current_position, last_read, added_date, file_missing";
+
+fn document_from_row(row: SqliteRow) -> StoredDocument {
+    StoredDocument {
+        id: row.get("id"),
+        title: row.get("title"),
+        author: row.get("author"),
+        file_path: row.get("file_path"),
+        file_type: row.get("file_type"),
+        content_hash: row.get("content_hash"),
+        encoding: row.get("encoding"),
+        encoding_override: row.get("encoding_override"),
+        total_pages: row.get("total_pages"),
+        current_position: row.get("current_position"),
+        last_read: row.get("last_read"),
+        added_date: row.get("added_date"),
+        file_missing: row.get("file_missing"),
+    }
+}
+
+const IMPORT_JOB_QUERY: &str = "SELECT j.id, j.status, j.created_at, j.updated_at,
+        COUNT(f.file_path) AS total,
+        COALESCE(SUM(f.status = 'pending'), 0) AS pending,
+        COALESCE(SUM(f.status = 'imported'), 0) AS imported,
+        COALESCE(SUM(f.status = 'relocated'), 0) AS relocated,
+        COALESCE(SUM(f.status = 'skipped'), 0) AS skipped,
+        COALESCE(SUM(f.status = 'failed'), 0) AS failed
+    FROM import_jobs j
+    LEFT JOIN import_job_files f ON f.job_id = j.id";
+
+fn import_job_from_row(row: SqliteRow) -> ImportJob {
+    ImportJob {
+        id: row.get("id"),
+        status: ImportJobStatus::from_str(row.get("status")),
+        total: row.get("total"),
+        pending: row.get("pending"),
+        imported: row.get("imported"),
+        relocated: row.get("relocated"),
+        skipped: row.get("skipped"),
+        failed: row.get("failed"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+const BOOKMARK_COLUMNS: &str =
+    "id, document_id, name, position, chapter_id, chapter_title, snippet, created_at";
+
+fn bookmark_from_row(row: SqliteRow) -> Bookmark {
+    Bookmark {
+        id: row.get("id"),
+        document_id: row.get("document_id"),
+        name: row.get("name"),
+        position: row.get::<i64, _>("position") as usize,
+        chapter_id: row.get("chapter_id"),
+        chapter_title: row.get("chapter_title"),
+        snippet: row.get("snippet"),
+        created_at: row.get("created_at"),
+    }
+}
+
 impl Database {
     pub async fn new() -> Result<Self> {
         let app_dir = dirs::data_dir()
             .unwrap_or_else(|| dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")))
             .join("MindfulReader");
 
-        std::fs::create_dir_all(&app_dir)?;
+        std::fs::create_dir_all(&app_dir).map_err(|e| Error::io(&app_dir, e))?;
         let db_path = app_dir.join("library.db");
         let db_url = format!("sqlite://{}", db_path.to_string_lossy());
 
-        if !Sqlite::database_exists(&db_url).await.unwrap_or(false) {
-            Sqlite::create_database(&db_url).await?;
-        }
-
-        let pool = SqlitePool::connect(&db_url).await?;
-        let database = Self { pool };
-        database.initialize_tables().await?;
-
-        Ok(database)
+        Self::open(&db_url).await
     }
 
-    async fn initialize_tables(&self) -> Result<()> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS documents (
-                id TEXT PRIMARY KEY,
-                title TEXT NOT NULL,
-                author TEXT,
-                file_path TEXT NOT NULL UNIQUE,
-                file_type TEXT NOT NULL,
-                total_pages INTEGER NOT NULL DEFAULT 0,
-                current_position INTEGER NOT NULL DEFAULT 0,
-                last_read DATETIME DEFAULT CURRENT_TIMESTAMP,
-                added_date DATETIME DEFAULT CURRENT_TIMESTAMP
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS user_settings (
-                id INTEGER PRIMARY KEY CHECK (id = 1),
-                theme TEXT NOT NULL DEFAULT 'light',
-                font_family TEXT NOT NULL DEFAULT 'georgia',
-                font_size INTEGER NOT NULL DEFAULT 18,
-                line_height REAL NOT NULL DEFAULT 1.6,
-                letter_spacing REAL NOT NULL DEFAULT 0.0,
-                words_per_page INTEGER NOT NULL DEFAULT 400,
-                page_margin TEXT NOT NULL DEFAULT 'normal',
-                justify_text BOOLEAN NOT NULL DEFAULT TRUE,
-                hyphenation BOOLEAN NOT NULL DEFAULT TRUE,
-                animation_speed TEXT NOT NULL DEFAULT 'normal',
-                page_curl BOOLEAN NOT NULL DEFAULT TRUE
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+    /// Opens (creating if needed) the database at `db_url` and migrates it to
+    /// the current schema.
+    pub async fn open(db_url: &str) -> Result<Self> {
+        if !Sqlite::database_exists(db_url).await.unwrap_or(false) {
+            Sqlite::create_database(db_url).await?;
+        }
 
-        sqlx::query("INSERT OR IGNORE INTO user_settings (id) VALUES (1)")
-            .execute(&self.pool)
-            .await?;
+        let pool = SqlitePool::connect(db_url).await?;
+        migrations::run(&pool).await?;
 
-        // Create chapters table for caching
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS chapters (
-                id TEXT PRIMARY KEY,
-                document_id TEXT NOT NULL,
-                title TEXT NOT NULL,
-                start_position INTEGER NOT NULL,
-                end_position INTEGER NOT NULL,
-                chapter_order INTEGER NOT NULL,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                FOREIGN KEY (document_id) REFERENCES documents (id) ON DELETE CASCADE
-            )
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
+        Ok(Self { pool })
+    }
 
-        // Create index for faster chapter lookups
-        sqlx::query(
-            "CREATE INDEX IF NOT EXISTS idx_chapters_document_id ON chapters (document_id)"
-        )
-        .execute(&self.pool)
+    /// Finds the stored copy of a book, preferring a content match (the file may
+    /// have moved) over a path match (the file may have been edited in place).
+    pub async fn find_document(&self, content_hash: &str, file_path: &str) -> Result<Option<StoredDocument>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM library_documents WHERE content_hash = ? OR file_path = ? ORDER BY content_hash = ? DESC LIMIT 1",
+            DOCUMENT_COLUMNS
+        ))
+        .bind(content_hash)
+        .bind(file_path)
+        .bind(content_hash)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(row.map(document_from_row))
     }
 
+    /// Inserts or updates a document. Reading progress and the added date of an
+    /// existing row are kept; only a new row takes them from `doc`. Fails,
+    /// changing nothing, when another document is stored at the same path; its
+    /// reading progress and notes aren't thrown away without the user knowing.
     pub async fn save_document(&self, doc: &StoredDocument) -> Result<()> {
+        let other: Option<String> =
+            sqlx::query_scalar("SELECT title FROM library_documents WHERE file_path = ? AND id != ?")
+                .bind(&doc.file_path)
+                .bind(&doc.id)
+                .fetch_optional(&self.pool)
+                .await?;
+        if let Some(title) = other {
+            return Err(Error::InvalidInput(format!(
+                "The library has another book, \"{}\", stored at {}. Remove it from the library to open this file in its place.",
+                title, doc.file_path
+            )));
+        }
+
         sqlx::query(
             r#"
-            INSERT OR REPLACE INTO documents 
-            (id, title, author, file_path, file_type, total_pages, current_position, last_read, added_date)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO documents
+            (id, title, author, file_path, file_type, content_hash, encoding, encoding_override,
+             total_pages, current_position, last_read, added_date, file_missing)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                title = excluded.title,
+                author = excluded.author,
+                file_path = excluded.file_path,
+                file_type = excluded.file_type,
+                content_hash = excluded.content_hash,
+                encoding = excluded.encoding,
+                encoding_override = excluded.encoding_override,
+                total_pages = excluded.total_pages,
+                last_read = excluded.last_read,
+                file_missing = excluded.file_missing
             "#,
         )
         .bind(&doc.id)
@@ -160,39 +384,79 @@ impl Database {
         .bind(&doc.author)
         .bind(&doc.file_path)
         .bind(&doc.file_type)
+        .bind(&doc.content_hash)
+        .bind(&doc.encoding)
+        .bind(&doc.encoding_override)
         .bind(doc.total_pages)
         .bind(doc.current_position)
         .bind(doc.last_read)
         .bind(doc.added_date)
+        .bind(doc.file_missing)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
+    pub async fn get_document(&self, document_id: &str) -> Result<Option<StoredDocument>> {
+        let row = sqlx::query(&format!("SELECT {} FROM library_documents WHERE id = ?", DOCUMENT_COLUMNS))
+            .bind(document_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(document_from_row))
+    }
+
+    /// Documents in the given collection, with the given tag and on the given
+    /// shelf, when set, in `sort` order.
+    pub async fn get_library(
+        &self,
+        collection_id: Option<&str>,
+        tag_id: Option<&str>,
+        shelf: Option<&ShelfFilter>,
+        sort: LibrarySort,
+    ) -> Result<Vec<StoredDocument>> {
+        let mut filters = Vec::new();
+        let mut params = Vec::new();
+        if let Some(collection_id) = collection_id {
+            filters.push("id IN (SELECT document_id FROM document_collections WHERE collection_id = ?)".to_string());
+            params.push(Param::Text(collection_id.to_string()));
+        }
+        if let Some(tag_id) = tag_id {
+            filters.push("id IN (SELECT document_id FROM document_tags WHERE tag_id = ?)".to_string());
+            params.push(Param::Text(tag_id.to_string()));
+        }
+        if let Some(shelf) = shelf {
+            let (condition, shelf_params) = shelf.compile()?;
+            filters.push(condition);
+            params.extend(shelf_params);
+        }
+        let where_clause = if filters.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", filters.join(" AND "))
+        };
+
+        let sql = format!(
+            "SELECT {} FROM library_documents {} ORDER BY {}",
+            DOCUMENT_COLUMNS,
+            where_clause,
+            sort.order_by()
+        );
+        let rows = bind_params(sqlx::query(&sql), params).fetch_all(&self.pool).await?;
+
+        Ok(rows.into_iter().map(document_from_row).collect())
+    }
+
     pub async fn get_all_documents(&self) -> Result<Vec<StoredDocument>> {
-        let rows = sqlx::query(
-            "SELECT id, title, author, file_path, file_type, total_pages, current_position, last_read, added_date FROM documents ORDER BY last_read DESC"
-        )
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM library_documents ORDER BY last_read DESC",
+            DOCUMENT_COLUMNS
+        ))
         .fetch_all(&self.pool)
         .await?;
 
-        let documents = rows
-            .into_iter()
-            .map(|row| StoredDocument {
-                id: row.get("id"),
-                title: row.get("title"),
-                author: row.get("author"),
-                file_path: row.get("file_path"),
-                file_type: row.get("file_type"),
-                total_pages: row.get("total_pages"),
-                current_position: row.get("current_position"),
-                last_read: row.get("last_read"),
-                added_date: row.get("added_date"),
-            })
-            .collect();
-
-        Ok(documents)
+        Ok(rows.into_iter().map(document_from_row).collect())
     }
 
     pub async fn update_reading_progress(&self, document_id: &str, position: i32) -> Result<()> {
@@ -207,13 +471,38 @@ impl Database {
         Ok(())
     }
 
+    /// Whether the document's positions still need moving onto stripped
+    /// text (see migration 0010). Clears the flag, so only one caller acts.
+    pub async fn take_position_repair(&self, document_id: &str) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE documents SET reanchor_positions = 0 WHERE id = ? AND reanchor_positions = 1",
+        )
+        .bind(document_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Moves the reading position without counting as a read.
+    pub async fn set_current_position(&self, document_id: &str, position: i32) -> Result<()> {
+        sqlx::query("UPDATE documents SET current_position = ? WHERE id = ?")
+            .bind(position)
+            .bind(document_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn save_settings(&self, settings: &UserSettings) -> Result<()> {
         sqlx::query(
             r#"
             UPDATE user_settings SET
                 theme = ?, font_family = ?, font_size = ?, line_height = ?, letter_spacing = ?,
                 words_per_page = ?, page_margin = ?, justify_text = ?, hyphenation = ?,
-                animation_speed = ?, page_curl = ?
+                animation_speed = ?, page_curl = ?, detect_numbered_chapters = ?,
+                detect_part_headings = ?, detect_caps_headings = ?, detect_markdown_headings = ?
             WHERE id = 1
             "#,
         )
@@ -228,13 +517,52 @@ impl Database {
         .bind(settings.hyphenation)
         .bind(&settings.animation_speed)
         .bind(settings.page_curl)
+        .bind(settings.detect_numbered_chapters)
+        .bind(settings.detect_part_headings)
+        .bind(settings.detect_caps_headings)
+        .bind(settings.detect_markdown_headings)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
+    pub async fn set_file_missing(&self, document_id: &str, missing: bool) -> Result<()> {
+        sqlx::query("UPDATE documents SET file_missing = ? WHERE id = ?")
+            .bind(missing)
+            .bind(document_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Points a document at the new location of its file. Returns false,
+    /// changing nothing, when another document is stored at that path; its
+    /// reading progress and notes aren't thrown away without the user knowing.
+    pub async fn relocate_document(&self, document_id: &str, file_path: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE documents SET file_path = ?, file_missing = 0
+            WHERE id = ? AND NOT EXISTS (SELECT 1 FROM documents WHERE file_path = ? AND id != ?)
+            "#,
+        )
+        .bind(file_path)
+        .bind(document_id)
+        .bind(file_path)
+        .bind(document_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn delete_document(&self, document_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM document_text WHERE document_id = ?")
+            .bind(document_id)
+            .execute(&self.pool)
+            .await?;
+
         sqlx::query("DELETE FROM documents WHERE id = ?")
             .bind(document_id)
             .execute(&self.pool)
@@ -243,12 +571,139 @@ impl Database {
         Ok(())
     }
 
+    // Full-text search methods
+    pub async fn is_text_indexed(&self, document_id: &str, content_hash: &str) -> Result<bool> {
+        let indexed: Option<Option<String>> =
+            sqlx::query_scalar("SELECT indexed_hash FROM documents WHERE id = ?")
+                .bind(document_id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(indexed.flatten().as_deref() == Some(content_hash))
+    }
+
+    pub async fn index_document_text(
+        &self,
+        document_id: &str,
+        content_hash: &str,
+        passages: &[Passage<'_>],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        // Written first to take the write lock. FTS5 reads before it writes,
+        // and SQLite fails a transaction holding a read lock at once, without
+        // waiting, when it needs the write lock another import holds.
+        sqlx::query("UPDATE documents SET indexed_hash = ? WHERE id = ?")
+            .bind(content_hash)
+            .bind(document_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM document_text WHERE document_id = ?")
+            .bind(document_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for passage in passages {
+            sqlx::query(
+                r#"
+                INSERT INTO document_text (document_id, chapter_id, chapter_title, start_position, body, page_label)
+                VALUES (?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(document_id)
+            .bind(&passage.chapter_id)
+            .bind(&passage.chapter_title)
+            .bind(passage.start_position as i64)
+            .bind(passage.text)
+            .bind(&passage.page_label)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Ids and paths of documents whose index is missing or out of date.
+    pub async fn get_unindexed_documents(&self) -> Result<Vec<(String, String)>> {
+        let rows = sqlx::query(
+            "SELECT id, file_path FROM documents WHERE indexed_hash IS NULL OR indexed_hash IS NOT content_hash"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|row| (row.get("id"), row.get("file_path"))).collect())
+    }
+
+    /// Runs an FTS5 MATCH expression (see `search::match_query`) over the
+    /// library, or over `document_ids` only, best matches first.
+    pub async fn search_text(
+        &self,
+        match_query: &str,
+        document_ids: Option<&[String]>,
+        limit: usize,
+    ) -> Result<Vec<LibrarySearchHit>> {
+        let mut sql = String::from(
+            r#"
+            SELECT t.document_id, d.title, t.chapter_id, t.chapter_title, t.page_label, t.start_position,
+                   snippet(document_text, 4, char(2), char(3), '…', 16) AS snippet,
+                   highlight(document_text, 4, char(2), char(3)) AS marked,
+                   bm25(document_text) AS rank
+            FROM document_text t
+            JOIN library_documents d ON d.id = t.document_id
+            WHERE document_text MATCH ?
+            "#,
+        );
+        if let Some(ids) = document_ids {
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+            sql.push_str(&format!(" AND t.document_id IN ({})", vec!["?"; ids.len()].join(", ")));
+        }
+        sql.push_str(" ORDER BY rank LIMIT ?");
+
+        let mut query = sqlx::query(&sql).bind(match_query);
+        for id in document_ids.unwrap_or_default() {
+            query = query.bind(id);
+        }
+        let rows = query.bind(limit as i64).fetch_all(&self.pool).await?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let start_position = row.get::<i64, _>("start_position") as usize;
+                let (snippet, highlights) =
+                    search::split_highlights(row.get("snippet"), '\u{2}', '\u{3}');
+
+                // The first marked range in the full passage locates the hit
+                let marked: String = row.get("marked");
+                let (_, matches) = search::split_highlights(&marked, '\u{2}', '\u{3}');
+                let (match_start, match_end) = matches.first().copied().unwrap_or((0, 0));
+
+                LibrarySearchHit {
+                    document_id: row.get("document_id"),
+                    document_title: row.get("title"),
+                    chapter_id: row.get("chapter_id"),
+                    chapter_title: row.get("chapter_title"),
+                    page_label: row.get("page_label"),
+                    start_position: start_position + match_start,
+                    end_position: start_position + match_end,
+                    snippet,
+                    highlights,
+                    rank: row.get("rank"),
+                }
+            })
+            .collect())
+    }
+
     pub async fn get_settings(&self) -> Result<UserSettings> {
         let row = sqlx::query(
             r#"
             SELECT theme, font_family, font_size, line_height, letter_spacing,
                    words_per_page, page_margin, justify_text, hyphenation,
-                   animation_speed, page_curl
+                   animation_speed, page_curl, detect_numbered_chapters, detect_part_headings,
+                   detect_caps_headings, detect_markdown_headings
             FROM user_settings WHERE id = 1
             "#,
         )
@@ -268,6 +723,10 @@ impl Database {
                 hyphenation: row.get("hyphenation"),
                 animation_speed: row.get("animation_speed"),
                 page_curl: row.get("page_curl"),
+                detect_numbered_chapters: row.get("detect_numbered_chapters"),
+                detect_part_headings: row.get("detect_part_headings"),
+                detect_caps_headings: row.get("detect_caps_headings"),
+                detect_markdown_headings: row.get("detect_markdown_headings"),
             })
         } else {
             Ok(UserSettings::default())
@@ -287,8 +746,8 @@ impl Database {
             sqlx::query(
                 r#"
                 INSERT INTO chapters 
-                (id, document_id, title, start_position, end_position, chapter_order)
-                VALUES (?, ?, ?, ?, ?, ?)
+                (id, document_id, title, start_position, end_position, level, parent_id, href, chapter_order)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
                 "#,
             )
             .bind(&chapter.id)
@@ -296,6 +755,9 @@ impl Database {
             .bind(&chapter.title)
             .bind(chapter.start_position as i32)
             .bind(chapter.end_position as i32)
+            .bind(chapter.level as i32)
+            .bind(&chapter.parent_id)
+            .bind(&chapter.href)
             .bind(order as i32)
             .execute(&self.pool)
             .await?;
@@ -306,7 +768,7 @@ impl Database {
 
     pub async fn get_cached_chapters(&self, document_id: &str) -> Result<Option<Vec<crate::Chapter>>> {
         let rows = sqlx::query(
-            "SELECT id, title, start_position, end_position FROM chapters WHERE document_id = ? ORDER BY chapter_order"
+            "SELECT id, title, start_position, end_position, level, parent_id, href FROM chapters WHERE document_id = ? ORDER BY chapter_order"
         )
         .bind(document_id)
         .fetch_all(&self.pool)
@@ -323,6 +785,9 @@ impl Database {
                 title: row.get("title"),
                 start_position: row.get::<i32, _>("start_position") as usize,
                 end_position: row.get::<i32, _>("end_position") as usize,
+                level: row.get::<i32, _>("level") as usize,
+                parent_id: row.get("parent_id"),
+                href: row.get("href"),
             })
             .collect();
 
@@ -334,6 +799,968 @@ impl Database {
             .bind(document_id)
             .execute(&self.pool)
             .await?;
+
+        sqlx::query("UPDATE documents SET indexed_hash = NULL WHERE id = ?")
+            .bind(document_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Highlights and notes
+    pub async fn save_annotation(&self, annotation: &Annotation) -> Result<()> {
+        let anchor = &annotation.anchor;
+        sqlx::query(
+            r#"
+            INSERT INTO annotations
+            (id, document_id, chapter_id, chapter_offset, start_position, end_position,
+             quote, prefix, suffix, color, note, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&annotation.id)
+        .bind(&annotation.document_id)
+        .bind(&anchor.chapter_id)
+        .bind(anchor.chapter_offset as i64)
+        .bind(anchor.start_position as i64)
+        .bind(anchor.end_position as i64)
+        .bind(&anchor.quote)
+        .bind(&anchor.prefix)
+        .bind(&anchor.suffix)
+        .bind(annotation.color.as_str())
+        .bind(&annotation.note)
+        .bind(annotation.created_at)
+        .bind(annotation.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_annotations(&self, document_id: &str) -> Result<Vec<Annotation>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, document_id, chapter_id, chapter_offset, start_position, end_position,
+                   quote, prefix, suffix, color, note, created_at, updated_at
+            FROM annotations WHERE document_id = ? ORDER BY start_position
+            "#,
+        )
+        .bind(document_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let annotations = rows
+            .into_iter()
+            .map(|row| Annotation {
+                id: row.get("id"),
+                document_id: row.get("document_id"),
+                anchor: TextAnchor {
+                    chapter_id: row.get("chapter_id"),
+                    chapter_offset: row.get::<i64, _>("chapter_offset") as usize,
+                    start_position: row.get::<i64, _>("start_position") as usize,
+                    end_position: row.get::<i64, _>("end_position") as usize,
+                    quote: row.get("quote"),
+                    prefix: row.get("prefix"),
+                    suffix: row.get("suffix"),
+                },
+                color: HighlightColor::from_name(row.get("color")),
+                note: row.get("note"),
+                orphaned: false,
+                created_at: row.get("created_at"),
+                updated_at: row.get("updated_at"),
+            })
+            .collect();
+
+        Ok(annotations)
+    }
+
+    /// Stores an anchor that was re-attached after the document was re-parsed.
+    pub async fn update_annotation_anchor(&self, annotation_id: &str, anchor: &TextAnchor) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE annotations SET
+                chapter_id = ?, chapter_offset = ?, start_position = ?, end_position = ?,
+                quote = ?, prefix = ?, suffix = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(&anchor.chapter_id)
+        .bind(anchor.chapter_offset as i64)
+        .bind(anchor.start_position as i64)
+        .bind(anchor.end_position as i64)
+        .bind(&anchor.quote)
+        .bind(&anchor.prefix)
+        .bind(&anchor.suffix)
+        .bind(annotation_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_annotation(
+        &self,
+        annotation_id: &str,
+        color: HighlightColor,
+        note: Option<&str>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE annotations SET color = ?, note = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(color.as_str())
+        .bind(note)
+        .bind(annotation_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_annotation(&self, annotation_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM annotations WHERE id = ?")
+            .bind(annotation_id)
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
+
+    // Named bookmarks
+    pub async fn save_bookmark(&self, bookmark: &Bookmark) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO bookmarks
+            (id, document_id, name, position, chapter_id, chapter_title, snippet, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&bookmark.id)
+        .bind(&bookmark.document_id)
+        .bind(&bookmark.name)
+        .bind(bookmark.position as i64)
+        .bind(&bookmark.chapter_id)
+        .bind(&bookmark.chapter_title)
+        .bind(&bookmark.snippet)
+        .bind(bookmark.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_bookmarks(&self, document_id: &str) -> Result<Vec<Bookmark>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM bookmarks WHERE document_id = ? ORDER BY position, created_at",
+            BOOKMARK_COLUMNS
+        ))
+        .bind(document_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(bookmark_from_row).collect())
+    }
+
+    pub async fn get_bookmark(&self, bookmark_id: &str) -> Result<Option<Bookmark>> {
+        let row = sqlx::query(&format!("SELECT {} FROM bookmarks WHERE id = ?", BOOKMARK_COLUMNS))
+            .bind(bookmark_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(bookmark_from_row))
+    }
+
+    /// Stores a bookmark's position after the document's text moved.
+    pub async fn update_bookmark_position(
+        &self,
+        bookmark_id: &str,
+        position: usize,
+        chapter: Option<&crate::Chapter>,
+    ) -> Result<()> {
+        sqlx::query("UPDATE bookmarks SET position = ?, chapter_id = ?, chapter_title = ? WHERE id = ?")
+            .bind(position as i64)
+            .bind(chapter.map(|c| &c.id))
+            .bind(chapter.map(|c| &c.title))
+            .bind(bookmark_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn rename_bookmark(&self, bookmark_id: &str, name: &str) -> Result<bool> {
+        let result = sqlx::query("UPDATE bookmarks SET name = ? WHERE id = ?")
+            .bind(name)
+            .bind(bookmark_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_bookmark(&self, bookmark_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM bookmarks WHERE id = ?")
+            .bind(bookmark_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Metadata overrides
+    pub async fn get_document_metadata(&self, document_id: &str) -> Result<MetadataOverrides> {
+        let row = sqlx::query(
+            r#"
+            SELECT title, authors, series, series_index, publisher, language, published_date,
+                   isbn, description, title_sort, author_sort
+            FROM document_metadata WHERE document_id = ?
+            "#,
+        )
+        .bind(document_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(MetadataOverrides::default());
+        };
+        let authors = row
+            .get::<Option<String>, _>("authors")
+            .map(|authors| serde_json::from_str(&authors))
+            .transpose()
+            .map_err(|e| Error::Internal(format!("Stored authors are unreadable: {}", e)))?;
+
+        Ok(MetadataOverrides {
+            title: row.get("title"),
+            authors,
+            series: row.get("series"),
+            series_index: row.get("series_index"),
+            publisher: row.get("publisher"),
+            language: row.get("language"),
+            published_date: row.get("published_date"),
+            isbn: row.get("isbn"),
+            description: row.get("description"),
+            title_sort: row.get("title_sort"),
+            author_sort: row.get("author_sort"),
+        })
+    }
+
+    /// Replaces a document's metadata overrides; fields left `None` go back to
+    /// the parsed values. Returns false if the document doesn't exist.
+    pub async fn set_document_metadata(&self, document_id: &str, overrides: &MetadataOverrides) -> Result<bool> {
+        if overrides.is_empty() {
+            sqlx::query("DELETE FROM document_metadata WHERE document_id = ?")
+                .bind(document_id)
+                .execute(&self.pool)
+                .await?;
+            return Ok(self.get_document(document_id).await?.is_some());
+        }
+
+        let authors = overrides
+            .authors
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()
+            .map_err(|e| Error::Internal(format!("Failed to store authors: {}", e)))?;
+        let result = sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO document_metadata
+            (document_id, title, authors, series, series_index, publisher, language, published_date,
+             isbn, description, title_sort, author_sort, updated_at)
+            SELECT id, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ? FROM documents WHERE id = ?
+            "#,
+        )
+        .bind(&overrides.title)
+        .bind(authors)
+        .bind(&overrides.series)
+        .bind(overrides.series_index)
+        .bind(&overrides.publisher)
+        .bind(&overrides.language)
+        .bind(&overrides.published_date)
+        .bind(&overrides.isbn)
+        .bind(&overrides.description)
+        .bind(&overrides.title_sort)
+        .bind(&overrides.author_sort)
+        .bind(Utc::now())
+        .bind(document_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Gives a document exactly the tags named, creating any that don't exist
+    /// yet. Names match existing tags ignoring case.
+    pub async fn set_document_tags(&self, document_id: &str, names: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM document_tags WHERE document_id = ?")
+            .bind(document_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for name in names {
+            sqlx::query("INSERT OR IGNORE INTO tags (id, name, created_at) VALUES (?, ?, ?)")
+                .bind(uuid::Uuid::new_v4().to_string())
+                .bind(name)
+                .bind(Utc::now())
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "INSERT OR IGNORE INTO document_tags (tag_id, document_id) SELECT id, ? FROM tags WHERE name = ?",
+            )
+            .bind(document_id)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // Collections and tags
+    pub async fn save_group(&self, group: &Group) -> Result<()> {
+        sqlx::query(&format!("INSERT INTO {} (id, name, created_at) VALUES (?, ?, ?)", group.kind.table()))
+            .bind(&group.id)
+            .bind(&group.name)
+            .bind(group.created_at)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| name_taken(group.kind.as_str(), &group.name, e))?;
+
+        Ok(())
+    }
+
+    pub async fn get_groups(&self, kind: GroupKind) -> Result<Vec<Group>> {
+        let (link_table, link_column) = kind.links();
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT g.id, g.name, g.created_at, COUNT(l.document_id) AS document_count
+            FROM {} g LEFT JOIN {} l ON l.{} = g.id
+            GROUP BY g.id
+            ORDER BY g.name COLLATE NOCASE
+            "#,
+            kind.table(),
+            link_table,
+            link_column
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| group_from_row(kind, row)).collect())
+    }
+
+    /// The collections or tags a document belongs to.
+    pub async fn get_document_groups(&self, kind: GroupKind, document_id: &str) -> Result<Vec<Group>> {
+        let (link_table, link_column) = kind.links();
+        let rows = sqlx::query(&format!(
+            r#"
+            SELECT g.id, g.name, g.created_at,
+                (SELECT COUNT(*) FROM {link_table} c WHERE c.{link_column} = g.id) AS document_count
+            FROM {table} g JOIN {link_table} l ON l.{link_column} = g.id
+            WHERE l.document_id = ?
+            ORDER BY g.name COLLATE NOCASE
+            "#,
+            table = kind.table(),
+            link_table = link_table,
+            link_column = link_column
+        ))
+        .bind(document_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| group_from_row(kind, row)).collect())
+    }
+
+    pub async fn rename_group(&self, kind: GroupKind, group_id: &str, name: &str) -> Result<bool> {
+        let result = sqlx::query(&format!("UPDATE {} SET name = ? WHERE id = ?", kind.table()))
+            .bind(name)
+            .bind(group_id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| name_taken(kind.as_str(), name, e))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes a collection or tag. The documents in it stay in the library.
+    pub async fn delete_group(&self, kind: GroupKind, group_id: &str) -> Result<bool> {
+        let result = sqlx::query(&format!("DELETE FROM {} WHERE id = ?", kind.table()))
+            .bind(group_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Adds documents to a collection or tag. Returns false if it doesn't exist.
+    pub async fn add_to_group(&self, kind: GroupKind, group_id: &str, document_ids: &[String]) -> Result<bool> {
+        let (link_table, link_column) = kind.links();
+        let mut tx = self.pool.begin().await?;
+
+        let exists: Option<String> = sqlx::query_scalar(&format!("SELECT id FROM {} WHERE id = ?", kind.table()))
+            .bind(group_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            return Ok(false);
+        }
+
+        // Ids no longer in the library are skipped rather than failing the batch
+        let sql = format!(
+            "INSERT OR IGNORE INTO {} ({}, document_id) SELECT ?, id FROM documents WHERE id = ?",
+            link_table, link_column
+        );
+        for document_id in document_ids {
+            sqlx::query(&sql)
+                .bind(group_id)
+                .bind(document_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn remove_from_group(&self, kind: GroupKind, group_id: &str, document_ids: &[String]) -> Result<()> {
+        let (link_table, link_column) = kind.links();
+        let sql = format!("DELETE FROM {} WHERE {} = ? AND document_id = ?", link_table, link_column);
+
+        let mut tx = self.pool.begin().await?;
+        for document_id in document_ids {
+            sqlx::query(&sql)
+                .bind(group_id)
+                .bind(document_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    // Smart shelves
+    /// Inserts a shelf, or updates the name and filter of an existing one.
+    pub async fn save_shelf(&self, shelf: &SmartShelf) -> Result<()> {
+        let filter = serde_json::to_string(&shelf.filter)
+            .map_err(|e| Error::Internal(format!("Failed to store shelf filter: {}", e)))?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO smart_shelves (id, name, filter, created_at)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name,
+                filter = excluded.filter
+            "#,
+        )
+        .bind(&shelf.id)
+        .bind(&shelf.name)
+        .bind(filter)
+        .bind(shelf.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| name_taken("shelf", &shelf.name, e))?;
+
+        Ok(())
+    }
+
+    /// Every shelf, with its documents counted as of now.
+    pub async fn get_shelves(&self) -> Result<Vec<SmartShelf>> {
+        let rows = sqlx::query("SELECT id, name, filter, created_at FROM smart_shelves ORDER BY name COLLATE NOCASE")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut shelves = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut shelf = shelf_from_row(row)?;
+            shelf.document_count = self.count_shelf(&shelf.filter).await?;
+            shelves.push(shelf);
+        }
+
+        Ok(shelves)
+    }
+
+    pub async fn get_shelf(&self, shelf_id: &str) -> Result<Option<SmartShelf>> {
+        let row = sqlx::query("SELECT id, name, filter, created_at FROM smart_shelves WHERE id = ?")
+            .bind(shelf_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut shelf = shelf_from_row(row)?;
+        shelf.document_count = self.count_shelf(&shelf.filter).await?;
+
+        Ok(Some(shelf))
+    }
+
+    async fn count_shelf(&self, filter: &ShelfFilter) -> Result<i64> {
+        let (condition, params) = filter.compile()?;
+        let sql = format!("SELECT COUNT(*) AS count FROM library_documents WHERE {}", condition);
+        let row = bind_params(sqlx::query(&sql), params).fetch_one(&self.pool).await?;
+
+        Ok(row.get("count"))
+    }
+
+    pub async fn delete_shelf(&self, shelf_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM smart_shelves WHERE id = ?")
+            .bind(shelf_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Watched library folders
+    pub async fn add_library_folder(&self, path: &str) -> Result<()> {
+        sqlx::query("INSERT OR IGNORE INTO library_folders (path) VALUES (?)")
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_library_folders(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT path FROM library_folders ORDER BY added_at, path")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("path")).collect())
+    }
+
+    pub async fn remove_library_folder(&self, path: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM library_folders WHERE path = ?")
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Bulk import jobs
+    /// Saves a new job with all its files pending. Finished jobs more than a
+    /// month old are cleared out at the same time.
+    pub async fn create_import_job(&self, job_id: &str, file_paths: &[String]) -> Result<()> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM import_jobs WHERE status = 'finished' AND julianday(updated_at) < julianday('now', '-30 days')")
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("INSERT INTO import_jobs (id, status, created_at, updated_at) VALUES (?, ?, ?, ?)")
+            .bind(job_id)
+            .bind(ImportJobStatus::Running.as_str())
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        for (position, file_path) in file_paths.iter().enumerate() {
+            sqlx::query("INSERT OR IGNORE INTO import_job_files (job_id, file_path, position) VALUES (?, ?, ?)")
+                .bind(job_id)
+                .bind(file_path)
+                .bind(position as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_import_job(&self, job_id: &str) -> Result<Option<ImportJob>> {
+        let row = sqlx::query(&format!("{} WHERE j.id = ? GROUP BY j.id", IMPORT_JOB_QUERY))
+            .bind(job_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(import_job_from_row))
+    }
+
+    pub async fn get_import_jobs(&self) -> Result<Vec<ImportJob>> {
+        let rows = sqlx::query(&format!("{} GROUP BY j.id ORDER BY j.created_at DESC", IMPORT_JOB_QUERY))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(import_job_from_row).collect())
+    }
+
+    /// Files of a job still to be imported, in the order they were given.
+    pub async fn get_pending_import_files(&self, job_id: &str) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            "SELECT file_path FROM import_job_files WHERE job_id = ? AND status = 'pending' ORDER BY position"
+        )
+        .bind(job_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| row.get("file_path")).collect())
+    }
+
+    /// Failed files of a job, as (file path, error kind, error message).
+    pub async fn get_import_job_failures(&self, job_id: &str) -> Result<Vec<(String, String, String)>> {
+        let rows = sqlx::query(
+            "SELECT file_path, error_kind, error_message FROM import_job_files
+             WHERE job_id = ? AND status = 'failed' ORDER BY position"
+        )
+        .bind(job_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("file_path"), row.get("error_kind"), row.get("error_message")))
+            .collect())
+    }
+
+    pub async fn set_import_file_status(
+        &self,
+        job_id: &str,
+        file_path: &str,
+        status: ImportFileStatus,
+        error: Option<&Error>,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE import_job_files SET status = ?, error_kind = ?, error_message = ? WHERE job_id = ? AND file_path = ?"
+        )
+        .bind(status.as_str())
+        .bind(error.map(|e| e.kind()))
+        .bind(error.map(|e| e.to_string()))
+        .bind(job_id)
+        .bind(file_path)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_import_job_status(&self, job_id: &str, status: ImportJobStatus) -> Result<bool> {
+        let result = sqlx::query("UPDATE import_jobs SET status = ?, updated_at = ? WHERE id = ?")
+            .bind(status.as_str())
+            .bind(Utc::now())
+            .bind(job_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+fn group_from_row(kind: GroupKind, row: SqliteRow) -> Group {
+    Group {
+        id: row.get("id"),
+        kind,
+        name: row.get("name"),
+        document_count: row.get("document_count"),
+        created_at: row.get("created_at"),
+    }
+}
+
+fn shelf_from_row(row: SqliteRow) -> Result<SmartShelf> {
+    let filter: String = row.get("filter");
+    let filter = serde_json::from_str(&filter)
+        .map_err(|e| Error::Internal(format!("Stored shelf filter is unreadable: {}", e)))?;
+
+    Ok(SmartShelf {
+        id: row.get("id"),
+        name: row.get("name"),
+        filter,
+        document_count: 0,
+        created_at: row.get("created_at"),
+    })
+}
+
+// Collection, tag and shelf names are unique, ignoring case
+fn name_taken(what: &str, name: &str, error: sqlx::Error) -> Error {
+    match error.as_database_error() {
+        Some(e) if e.is_unique_violation() => {
+            Error::InvalidInput(format!("There is already a {} called \"{}\"", what, name))
+        }
+        _ => error.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{document, TempDatabase};
+
+    #[tokio::test]
+    async fn books_are_found_by_content_before_path() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let (emma, other) = (document("emma", "Emma", "epub"), document("other", "Persuasion", "epub"));
+        db.save_document(&emma).await.unwrap();
+        db.save_document(&other).await.unwrap();
+
+        // Moved: the content is known at another path
+        let found = db.find_document("hash-emma", "/elsewhere/emma.epub").await.unwrap();
+        assert_eq!(found.map(|doc| doc.id).as_deref(), Some("emma"));
+        // Edited in place: new content at a known path
+        let found = db.find_document("hash-edited", &emma.file_path).await.unwrap();
+        assert_eq!(found.map(|doc| doc.id).as_deref(), Some("emma"));
+        // A copy of one book placed at the other's path is the copied book
+        let found = db.find_document("hash-emma", &other.file_path).await.unwrap();
+        assert_eq!(found.map(|doc| doc.id).as_deref(), Some("emma"));
+
+        assert!(db.find_document("hash-new", "/books/new.epub").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn reopening_from_a_new_path_keeps_the_reading_position() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let emma = document("emma", "Emma", "epub");
+        db.save_document(&emma).await.unwrap();
+        db.update_reading_progress(&emma.id, 42).await.unwrap();
+        let added = db.get_document(&emma.id).await.unwrap().unwrap().added_date;
+
+        let mut moved = emma.clone();
+        moved.file_path = "/elsewhere/emma.epub".to_string();
+        moved.added_date = Utc::now() + chrono::Duration::days(1);
+        db.save_document(&moved).await.unwrap();
+
+        let stored = db.get_document(&emma.id).await.unwrap().unwrap();
+        assert_eq!(stored.file_path, "/elsewhere/emma.epub");
+        assert_eq!(stored.current_position, 42);
+        assert_eq!(stored.added_date, added);
+        assert_eq!(db.get_all_documents().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn saving_onto_another_books_path_changes_nothing() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let (emma, other) = (document("emma", "Emma", "epub"), document("other", "Persuasion", "epub"));
+        db.save_document(&emma).await.unwrap();
+        db.save_document(&other).await.unwrap();
+        db.update_reading_progress(&other.id, 7).await.unwrap();
+
+        let mut copied = emma.clone();
+        copied.file_path = other.file_path.clone();
+        let result = db.save_document(&copied).await;
+        assert!(matches!(result, Err(Error::InvalidInput(message)) if message.contains("Persuasion")));
+
+        let stored = db.get_document(&other.id).await.unwrap().unwrap();
+        assert_eq!(stored.current_position, 7);
+        assert_eq!(db.get_document(&emma.id).await.unwrap().unwrap().file_path, emma.file_path);
+    }
+
+    fn bookmark(id: &str, document_id: &str, position: usize) -> Bookmark {
+        Bookmark {
+            id: id.to_string(),
+            document_id: document_id.to_string(),
+            name: id.to_string(),
+            position,
+            chapter_id: None,
+            chapter_title: None,
+            snippet: String::new(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn bookmarks_are_listed_by_position() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let emma = document("emma", "Emma", "epub");
+        db.save_document(&emma).await.unwrap();
+
+        for (id, position) in [("later", 900), ("start", 0), ("middle", 450)] {
+            db.save_bookmark(&bookmark(id, &emma.id, position)).await.unwrap();
+        }
+        let mut same_place = bookmark("same-place", &emma.id, 450);
+        same_place.created_at += chrono::Duration::seconds(1);
+        db.save_bookmark(&same_place).await.unwrap();
+
+        let ids: Vec<_> = db.get_bookmarks(&emma.id).await.unwrap().into_iter().map(|b| b.id).collect();
+        assert_eq!(ids, ["start", "middle", "same-place", "later"]);
+        assert!(db.get_bookmarks("unknown").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bookmarks_are_renamed_and_deleted_by_id() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let emma = document("emma", "Emma", "epub");
+        db.save_document(&emma).await.unwrap();
+        db.save_bookmark(&bookmark("ball", &emma.id, 120)).await.unwrap();
+
+        assert!(db.rename_bookmark("ball", "The Crown ball").await.unwrap());
+        let stored = db.get_bookmark("ball").await.unwrap().unwrap();
+        assert_eq!((stored.name.as_str(), stored.position), ("The Crown ball", 120));
+
+        // Unknown ids change nothing, and the commands report them as not found
+        assert!(!db.rename_bookmark("unknown", "Anything").await.unwrap());
+        assert!(!db.delete_bookmark("unknown").await.unwrap());
+        assert!(db.get_bookmark("unknown").await.unwrap().is_none());
+
+        assert!(db.delete_bookmark("ball").await.unwrap());
+        assert!(db.get_bookmark("ball").await.unwrap().is_none());
+        assert!(!db.delete_bookmark("ball").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn bookmarks_go_with_their_document() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let (emma, other) = (document("emma", "Emma", "epub"), document("other", "Persuasion", "epub"));
+        db.save_document(&emma).await.unwrap();
+        db.save_document(&other).await.unwrap();
+        db.save_bookmark(&bookmark("ball", &emma.id, 120)).await.unwrap();
+        db.save_bookmark(&bookmark("bath", &other.id, 80)).await.unwrap();
+
+        db.delete_document(&emma.id).await.unwrap();
+        assert!(db.get_bookmarks(&emma.id).await.unwrap().is_empty());
+        assert!(db.get_bookmark("ball").await.unwrap().is_none());
+        assert_eq!(db.get_bookmarks(&other.id).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn relocating_onto_another_book_changes_nothing() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let (moved, other) = (document("moved", "Emma", "epub"), document("other", "Persuasion", "epub"));
+        db.save_document(&moved).await.unwrap();
+        db.save_document(&other).await.unwrap();
+        db.set_file_missing(&moved.id, true).await.unwrap();
+
+        assert!(!db.relocate_document(&moved.id, &other.file_path).await.unwrap());
+        let stored = db.get_document(&moved.id).await.unwrap().unwrap();
+        assert_eq!((stored.file_path.as_str(), stored.file_missing), (moved.file_path.as_str(), true));
+        assert!(db.get_document(&other.id).await.unwrap().is_some());
+
+        assert!(db.relocate_document(&moved.id, "/elsewhere/emma.epub").await.unwrap());
+        let stored = db.get_document(&moved.id).await.unwrap().unwrap();
+        assert_eq!((stored.file_path.as_str(), stored.file_missing), ("/elsewhere/emma.epub", false));
+    }
+
+    fn group(kind: GroupKind, id: &str, name: &str) -> Group {
+        Group {
+            id: id.to_string(),
+            kind,
+            name: name.to_string(),
+            document_count: 0,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn ids(documents: &[StoredDocument]) -> Vec<&str> {
+        documents.iter().map(|doc| doc.id.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn groups_are_created_and_renamed_to_free_names_only() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        db.save_group(&group(GroupKind::Collection, "c1", "To Read")).await.unwrap();
+        db.save_group(&group(GroupKind::Collection, "c2", "Favourites")).await.unwrap();
+        // Collections and tags have names of their own
+        db.save_group(&group(GroupKind::Tag, "t1", "to read")).await.unwrap();
+
+        let names = |groups: Vec<Group>| groups.into_iter().map(|g| g.name).collect::<Vec<_>>();
+        assert_eq!(names(db.get_groups(GroupKind::Collection).await.unwrap()), ["Favourites", "To Read"]);
+        assert_eq!(names(db.get_groups(GroupKind::Tag).await.unwrap()), ["to read"]);
+
+        let duplicate = db.save_group(&group(GroupKind::Collection, "c3", "to read")).await;
+        assert!(matches!(duplicate, Err(Error::InvalidInput(_))));
+        let taken = db.rename_group(GroupKind::Collection, "c2", "TO READ").await;
+        assert!(matches!(taken, Err(Error::InvalidInput(message)) if message.contains("collection")));
+
+        assert!(db.rename_group(GroupKind::Collection, "c2", "Classics").await.unwrap());
+        assert!(!db.rename_group(GroupKind::Collection, "missing", "Anything").await.unwrap());
+        assert_eq!(names(db.get_groups(GroupKind::Collection).await.unwrap()), ["Classics", "To Read"]);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_group_keeps_its_documents() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let emma = document("emma", "Emma", "epub");
+        db.save_document(&emma).await.unwrap();
+        db.save_group(&group(GroupKind::Collection, "austen", "Austen")).await.unwrap();
+
+        let batch = vec![emma.id.clone(), "deleted-book".to_string()];
+        assert!(db.add_to_group(GroupKind::Collection, "austen", &batch).await.unwrap());
+        assert!(!db.add_to_group(GroupKind::Collection, "missing", &batch).await.unwrap());
+        let groups = db.get_groups(GroupKind::Collection).await.unwrap();
+        assert_eq!(groups[0].document_count, 1);
+
+        assert!(db.delete_group(GroupKind::Collection, "austen").await.unwrap());
+        assert!(!db.delete_group(GroupKind::Collection, "austen").await.unwrap());
+        assert!(db.get_document_groups(GroupKind::Collection, &emma.id).await.unwrap().is_empty());
+        assert!(db.get_document(&emma.id).await.unwrap().is_some());
+
+        // The name is free again, and the new collection starts empty
+        db.save_group(&group(GroupKind::Collection, "austen-2", "Austen")).await.unwrap();
+        assert_eq!(db.get_groups(GroupKind::Collection).await.unwrap()[0].document_count, 0);
+    }
+
+    #[tokio::test]
+    async fn the_library_is_filtered_by_collection_and_tag() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        for (id, title) in [("emma", "Emma"), ("persuasion", "Persuasion"), ("dracula", "Dracula")] {
+            db.save_document(&document(id, title, "epub")).await.unwrap();
+        }
+        db.save_group(&group(GroupKind::Collection, "austen", "Austen")).await.unwrap();
+        let austen = vec!["emma".to_string(), "persuasion".to_string()];
+        db.add_to_group(GroupKind::Collection, "austen", &austen).await.unwrap();
+        db.set_document_tags("emma", &["comedy".to_string()]).await.unwrap();
+        db.set_document_tags("dracula", &["Comedy".to_string()]).await.unwrap();
+
+        let tags = db.get_groups(GroupKind::Tag).await.unwrap();
+        assert_eq!(tags.len(), 1, "tag names match ignoring case");
+        let comedy = tags[0].id.as_str();
+
+        let sort = LibrarySort::Title;
+        let collection = db.get_library(Some("austen"), None, None, sort).await.unwrap();
+        assert_eq!(ids(&collection), ["emma", "persuasion"]);
+        let tagged = db.get_library(None, Some(comedy), None, sort).await.unwrap();
+        assert_eq!(ids(&tagged), ["dracula", "emma"]);
+        let both = db.get_library(Some("austen"), Some(comedy), None, sort).await.unwrap();
+        assert_eq!(ids(&both), ["emma"]);
+
+        db.remove_from_group(GroupKind::Collection, "austen", &["emma".to_string()]).await.unwrap();
+        assert!(db.get_library(Some("austen"), Some(comedy), None, sort).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn edited_metadata_survives_reimport_and_sorts_the_library() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let mut parsed = document("quixote", "el ingenioso hidalgo", "epub");
+        parsed.author = Some("Unknown".to_string());
+        let mut emma = document("emma", "Emma", "epub");
+        emma.author = Some("Jane Austen".to_string());
+        db.save_document(&parsed).await.unwrap();
+        db.save_document(&emma).await.unwrap();
+
+        let overrides = MetadataOverrides {
+            title: Some("Don Quixote".to_string()),
+            authors: Some(vec!["Miguel de Cervantes".to_string()]),
+            title_sort: Some("Quixote, Don".to_string()),
+            author_sort: Some("Cervantes, Miguel de".to_string()),
+            ..Default::default()
+        };
+        assert!(db.set_document_metadata(&parsed.id, &overrides).await.unwrap());
+        // Importing the file again stores what the parser found, as import_document does
+        db.save_document(&parsed).await.unwrap();
+
+        let library = db.get_library(None, None, None, LibrarySort::Title).await.unwrap();
+        let quixote = library.iter().find(|doc| doc.id == parsed.id).unwrap();
+        assert_eq!(quixote.title, "Don Quixote");
+        assert_eq!(quixote.author.as_deref(), Some("Miguel de Cervantes"));
+        assert_eq!(db.get_document_metadata(&parsed.id).await.unwrap(), overrides);
+
+        // "Emma" < "Quixote, Don", though "Don Quixote" < "Emma"
+        assert_eq!(ids(&library), ["emma", "quixote"]);
+        // "Cervantes, Miguel de" < "Jane Austen"
+        let by_author = db.get_library(None, None, None, LibrarySort::Author).await.unwrap();
+        assert_eq!(ids(&by_author), ["quixote", "emma"]);
+    }
 }
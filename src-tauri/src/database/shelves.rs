@@ -0,0 +1,397 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::query::Query;
+use sqlx::sqlite::SqliteArguments;
+use sqlx::Sqlite;
+
+// Limits on a filter's size, so a stored shelf can't turn into a huge query
+const MAX_DEPTH: usize = 8;
+const MAX_RULES: usize = 50;
+
+// Reading state, from the position saved by update_reading_progress. The
+// reader saves where its page starts, in pages from 0, and total_pages once
+// the last page is reached.
+const PROGRESS: &str =
+    "CASE WHEN total_pages > 0 THEN current_position * 100.0 / total_pages ELSE 0 END";
+const UNREAD: &str = "current_position <= 0";
+const IN_PROGRESS: &str = "current_position > 0 AND current_position < total_pages";
+const FINISHED: &str = "current_position > 0 AND current_position >= total_pages";
+
+/// Which documents a smart shelf holds. Stored and sent as JSON, e.g. unread
+/// PDFs added in the last 30 days:
+///
+/// ```json
+/// {"all": [
+///     {"field": "file_type", "op": "eq", "value": "pdf"},
+///     {"field": "status", "op": "eq", "value": "unread"},
+///     {"field": "added_date", "op": "within_days", "value": 30}
+/// ]}
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShelfFilter {
+    All(Vec<ShelfFilter>),
+    Any(Vec<ShelfFilter>),
+    Not(Box<ShelfFilter>),
+    #[serde(untagged)]
+    Rule(Rule),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    pub field: Field,
+    pub op: Op,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Title,
+    Author,
+    FileType,
+    Status, // "unread", "in_progress" or "finished"
+    Progress, // Percent read
+    AddedDate,
+    LastRead,
+    Collection, // By name
+    Tag,        // By name
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Eq,
+    Ne,
+    Contains,
+    Lt,
+    Gt,
+    WithinDays,
+    OlderThanDays,
+}
+
+/// A value bound into a compiled filter.
+#[derive(Debug, PartialEq)]
+pub(super) enum Param {
+    Text(String),
+    Number(f64),
+}
+
+impl Field {
+    fn as_str(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Author => "author",
+            Field::FileType => "file_type",
+            Field::Status => "status",
+            Field::Progress => "progress",
+            Field::AddedDate => "added_date",
+            Field::LastRead => "last_read",
+            Field::Collection => "collection",
+            Field::Tag => "tag",
+        }
+    }
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::Eq => "eq",
+            Op::Ne => "ne",
+            Op::Contains => "contains",
+            Op::Lt => "lt",
+            Op::Gt => "gt",
+            Op::WithinDays => "within_days",
+            Op::OlderThanDays => "older_than_days",
+        }
+    }
+}
+
+impl ShelfFilter {
+    /// Reads and validates a filter sent by the frontend.
+    pub fn from_json(value: Value) -> Result<Self> {
+        let filter: ShelfFilter = serde_json::from_value(value)
+            .map_err(|e| Error::InvalidInput(format!("Invalid shelf filter: {}", e)))?;
+        filter.compile()?;
+        Ok(filter)
+    }
+
+    /// A condition on the `library_documents` view matching the filter, and the
+    /// values to bind to it in order.
+    pub(super) fn compile(&self) -> Result<(String, Vec<Param>)> {
+        let mut params = Vec::new();
+        let mut rules = 0;
+        let sql = self.compile_into(&mut params, &mut rules, 0)?;
+        Ok((sql, params))
+    }
+
+    fn compile_into(&self, params: &mut Vec<Param>, rules: &mut usize, depth: usize) -> Result<String> {
+        if depth > MAX_DEPTH {
+            return Err(invalid(format!("Shelf filters can be nested at most {} deep", MAX_DEPTH)));
+        }
+
+        match self {
+            ShelfFilter::All(filters) | ShelfFilter::Any(filters) => {
+                let (joiner, empty) = match self {
+                    ShelfFilter::All(_) => (" AND ", "1"),
+                    _ => (" OR ", "0"),
+                };
+                if filters.is_empty() {
+                    return Ok(format!("({})", empty));
+                }
+                let parts = filters
+                    .iter()
+                    .map(|filter| filter.compile_into(params, rules, depth + 1))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("({})", parts.join(joiner)))
+            }
+            ShelfFilter::Not(filter) => Ok(format!("(NOT {})", filter.compile_into(params, rules, depth + 1)?)),
+            ShelfFilter::Rule(rule) => {
+                *rules += 1;
+                if *rules > MAX_RULES {
+                    return Err(invalid(format!("Shelf filters can have at most {} rules", MAX_RULES)));
+                }
+                rule.compile(params)
+            }
+        }
+    }
+}
+
+impl Rule {
+    fn compile(&self, params: &mut Vec<Param>) -> Result<String> {
+        let sql = match (self.field, self.op) {
+            (Field::Title | Field::Author | Field::FileType, Op::Eq | Op::Ne | Op::Contains) => {
+                let column = self.field.as_str();
+                let text = self.text()?;
+                match self.op {
+                    Op::Eq => {
+                        params.push(Param::Text(text.to_string()));
+                        format!("{} = ? COLLATE NOCASE", column)
+                    }
+                    Op::Ne => {
+                        params.push(Param::Text(text.to_string()));
+                        format!("{} IS NULL OR {} != ? COLLATE NOCASE", column, column)
+                    }
+                    _ => {
+                        params.push(Param::Text(format!("%{}%", escape_like(text))));
+                        format!("{} LIKE ? ESCAPE '\\'", column)
+                    }
+                }
+            }
+            (Field::Status, Op::Eq | Op::Ne) => {
+                let condition = match self.text()? {
+                    "unread" => UNREAD,
+                    "in_progress" => IN_PROGRESS,
+                    "finished" => FINISHED,
+                    other => {
+                        return Err(invalid(format!(
+                            "Unknown status \"{}\", expected unread, in_progress or finished",
+                            other
+                        )))
+                    }
+                };
+                match self.op {
+                    Op::Eq => condition.to_string(),
+                    _ => format!("NOT ({})", condition),
+                }
+            }
+            (Field::Progress, Op::Eq | Op::Lt | Op::Gt) => {
+                let percent = self
+                    .value
+                    .as_f64()
+                    .filter(|percent| (0.0..=100.0).contains(percent))
+                    .ok_or_else(|| invalid("progress needs a percentage from 0 to 100"))?;
+                params.push(Param::Number(percent));
+                let op = match self.op {
+                    Op::Eq => "=",
+                    Op::Lt => "<",
+                    _ => ">",
+                };
+                format!("{} {} ?", PROGRESS, op)
+            }
+            (Field::AddedDate | Field::LastRead, Op::WithinDays | Op::OlderThanDays) => {
+                let days = self
+                    .value
+                    .as_u64()
+                    .filter(|days| *days <= 36500)
+                    .ok_or_else(|| invalid(format!("{} needs a whole number of days", self.field.as_str())))?;
+                params.push(Param::Text(format!("-{} days", days)));
+                // julianday reads both sqlx's RFC 3339 dates and CURRENT_TIMESTAMP
+                let op = if self.op == Op::WithinDays { ">=" } else { "<" };
+                format!("julianday({}) {} julianday('now', ?)", self.field.as_str(), op)
+            }
+            (Field::Collection | Field::Tag, Op::Eq | Op::Ne) => {
+                params.push(Param::Text(self.text()?.to_string()));
+                let (table, link_table, link_column) = match self.field {
+                    Field::Collection => ("collections", "document_collections", "collection_id"),
+                    _ => ("tags", "document_tags", "tag_id"),
+                };
+                let exists = format!(
+                    "EXISTS (SELECT 1 FROM {} l JOIN {} g ON g.id = l.{} WHERE l.document_id = library_documents.id AND g.name = ? COLLATE NOCASE)",
+                    link_table, table, link_column
+                );
+                match self.op {
+                    Op::Eq => exists,
+                    _ => format!("NOT {}", exists),
+                }
+            }
+            (field, op) => {
+                return Err(invalid(format!(
+                    "{} can't be used with {}",
+                    field.as_str(),
+                    op.as_str()
+                )))
+            }
+        };
+
+        Ok(format!("({})", sql))
+    }
+
+    fn text(&self) -> Result<&str> {
+        self.value
+            .as_str()
+            .ok_or_else(|| invalid(format!("{} needs a text value", self.field.as_str())))
+    }
+}
+
+/// Binds compiled filter values to a query, in order.
+pub(super) fn bind_params<'q>(
+    mut query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    params: Vec<Param>,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    for param in params {
+        query = match param {
+            Param::Text(text) => query.bind(text),
+            Param::Number(number) => query.bind(number),
+        };
+    }
+    query
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidInput(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing::{document, TempDatabase};
+    use crate::database::LibrarySort;
+    use serde_json::json;
+
+    fn rule(field: &str, op: &str, value: Value) -> Value {
+        json!({"field": field, "op": op, "value": value})
+    }
+
+    fn rejected(value: Value) -> String {
+        match ShelfFilter::from_json(value) {
+            Err(Error::InvalidInput(message)) => message,
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert_eq!(rejected(rule("status", "contains", json!("finished"))), "status can't be used with contains");
+        assert_eq!(rejected(rule("title", "within_days", json!(3))), "title can't be used with within_days");
+        assert!(rejected(rule("status", "eq", json!("abandoned"))).starts_with("Unknown status \"abandoned\""));
+        assert!(rejected(rule("progress", "gt", json!(150))).contains("percentage"));
+        assert!(rejected(rule("title", "eq", json!(3))).contains("text value"));
+        assert!(rejected(rule("shelf", "eq", json!("x"))).starts_with("Invalid shelf filter"));
+
+        let mut nested = rule("file_type", "eq", json!("pdf"));
+        for _ in 0..MAX_DEPTH {
+            nested = json!({"not": nested});
+        }
+        assert!(ShelfFilter::from_json(nested.clone()).is_ok());
+        assert!(rejected(json!({"not": nested})).contains("nested at most"));
+
+        let rules = |count| json!({"any": vec![rule("file_type", "eq", json!("pdf")); count]});
+        assert!(ShelfFilter::from_json(rules(MAX_RULES)).is_ok());
+        assert!(rejected(rules(MAX_RULES + 1)).contains("at most 50 rules"));
+    }
+
+    #[test]
+    fn compiles_to_sql_with_params_in_order() {
+        let filter = ShelfFilter::from_json(json!({"all": [
+            rule("title", "contains", json!("50%_off")),
+            {"not": rule("status", "eq", json!("finished"))},
+            {"any": [rule("progress", "gt", json!(25)), rule("added_date", "within_days", json!(7))]},
+            {"any": []},
+        ]}))
+        .unwrap();
+        let (sql, params) = filter.compile().unwrap();
+
+        assert_eq!(
+            sql,
+            format!(
+                "((title LIKE ? ESCAPE '\\') AND (NOT ({})) AND (({} > ?) OR (julianday(added_date) >= julianday('now', ?))) AND (0))",
+                FINISHED, PROGRESS
+            )
+        );
+        assert_eq!(
+            params,
+            [
+                Param::Text("%50\\%\\_off%".to_string()),
+                Param::Number(25.0),
+                Param::Text("-7 days".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn filters_select_from_the_library() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+
+        let unread = document("unread", "Moby-Dick", "epub");
+        let mut reading = document("reading", "Emma", "txt");
+        reading.current_position = 40;
+        // Saved by the reader on reaching the last page
+        let mut finished = document("finished", "Beowulf", "pdf");
+        finished.current_position = finished.total_pages;
+        for doc in [unread, reading, finished] {
+            db.save_document(&doc).await.unwrap();
+        }
+        db.set_document_tags("finished", &["Poetry".to_string()]).await.unwrap();
+
+        let matching = |filter: Value| {
+            let db = &db;
+            async move {
+                let filter = ShelfFilter::from_json(filter).unwrap();
+                let mut ids: Vec<String> = db
+                    .get_library(None, None, Some(&filter), LibrarySort::Recent)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|doc| doc.id)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        assert_eq!(matching(rule("status", "eq", json!("unread"))).await, ["unread"]);
+        assert_eq!(matching(rule("status", "eq", json!("in_progress"))).await, ["reading"]);
+        assert_eq!(matching(rule("status", "eq", json!("finished"))).await, ["finished"]);
+        assert_eq!(matching(rule("status", "ne", json!("finished"))).await, ["reading", "unread"]);
+        assert_eq!(matching(rule("progress", "gt", json!(30))).await, ["finished", "reading"]);
+        assert_eq!(matching(rule("title", "contains", json!("DICK"))).await, ["unread"]);
+        assert_eq!(matching(rule("tag", "eq", json!("poetry"))).await, ["finished"]);
+        assert_eq!(matching(rule("tag", "ne", json!("poetry"))).await, ["reading", "unread"]);
+        assert_eq!(
+            matching(json!({"all": [
+                rule("added_date", "within_days", json!(1)),
+                {"not": rule("file_type", "eq", json!("pdf"))},
+            ]}))
+            .await,
+            ["reading", "unread"]
+        );
+        assert!(matching(rule("last_read", "older_than_days", json!(1))).await.is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+//! Throwaway databases for tests.
+
+use super::{Database, StoredDocument};
+use chrono::Utc;
+use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use std::path::PathBuf;
+
+/// A database file in the temp directory, removed when dropped.
+pub struct TempDatabase {
+    path: PathBuf,
+}
+
+impl TempDatabase {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("library-{}.db", uuid::Uuid::new_v4()));
+        Self { path }
+    }
+
+    pub fn url(&self) -> String {
+        format!("sqlite://{}", self.path.to_string_lossy())
+    }
+
+    /// Opens the database at the latest schema.
+    pub async fn open(&self) -> Database {
+        Database::open(&self.url()).await.unwrap()
+    }
+
+    /// Creates the database from raw SQL, e.g. an old schema.
+    pub async fn load_fixture(&self, sql: &str) {
+        Sqlite::create_database(&self.url()).await.unwrap();
+        let pool = SqlitePool::connect(&self.url()).await.unwrap();
+        sqlx::query(sql).execute(&pool).await.unwrap();
+        pool.close().await;
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// An unread document stored at /books/<id>.<file_type>.
+pub fn document(id: &str, title: &str, file_type: &str) -> StoredDocument {
+    StoredDocument {
+        id: id.to_string(),
+        title: title.to_string(),
+        author: None,
+        file_path: format!("/books/{}.{}", id, file_type),
+        file_type: file_type.to_string(),
+        content_hash: Some(format!("hash-{}", id)),
+        encoding: None,
+        encoding_override: None,
+        total_pages: 100,
+        current_position: 0,
+        last_read: Utc::now(),
+        added_date: Utc::now(),
+        file_missing: false,
+    }
+}
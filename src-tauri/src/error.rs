@@ -0,0 +1,257 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::io::ErrorKind as IoErrorKind;
+use std::path::{Path, PathBuf};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything the backend can fail with. Commands send it to the frontend as
+/// `{kind, message, details}`: `kind` is what the UI branches on, `message`
+/// is fit to show, and `details` holds the underlying cause when there is one.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("File not found: {}", .0.display())]
+    FileNotFound(PathBuf), // Moved, renamed or deleted since it was added
+
+    #[error("Permission denied: {}", .0.display())]
+    PermissionDenied(PathBuf),
+
+    #[error("Failed to read {}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Unsupported file format")]
+    UnsupportedFormat,
+
+    #[error("{0}")]
+    Corrupt(String), // The file is in a known format but can't be read
+
+    #[error("This book is DRM-protected and can't be opened")]
+    DrmProtected,
+
+    #[error("Import cancelled")]
+    Cancelled,
+
+    #[error("{0} not found")]
+    NotFound(&'static str), // A library record, e.g. "Document" or "Bookmark"
+
+    #[error("{0}")]
+    InvalidInput(String),
+
+    #[error("The library database is busy")]
+    DatabaseLocked(#[source] sqlx::Error),
+
+    #[error("Library database error")]
+    Database(#[source] sqlx::Error),
+
+    #[error("The library database uses schema version {found}, but this version of the app only supports up to {supported}. Please update the app.")]
+    DatabaseTooNew { found: i64, supported: i64 },
+
+    #[error("Migration {version} ({description}) failed")]
+    MigrationFailed {
+        version: i64,
+        description: &'static str,
+        #[source]
+        source: sqlx::Error,
+    },
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Error {
+    /// Classifies a failed read of `path`, so a missing file is reported as
+    /// one rather than as a generic I/O error.
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        match source.kind() {
+            IoErrorKind::NotFound => Error::FileNotFound(path.to_path_buf()),
+            IoErrorKind::PermissionDenied => Error::PermissionDenied(path.to_path_buf()),
+            _ => Error::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+
+    pub fn corrupt(message: impl Into<String>) -> Self {
+        Error::Corrupt(message.into())
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::FileNotFound(_) => "file_not_found",
+            Error::PermissionDenied(_) => "permission_denied",
+            Error::Io { .. } => "io",
+            Error::UnsupportedFormat => "unsupported_format",
+            Error::Corrupt(_) => "corrupt_file",
+            Error::DrmProtected => "drm_protected",
+            Error::Cancelled => "cancelled",
+            Error::NotFound(_) => "not_found",
+            Error::InvalidInput(_) => "invalid_input",
+            Error::DatabaseLocked(_) => "database_locked",
+            Error::Database(_) | Error::MigrationFailed { .. } => "database",
+            Error::DatabaseTooNew { .. } => "database_too_new",
+            Error::Internal(_) => "internal",
+        }
+    }
+
+    /// The underlying cause, for logs and an expandable "details" section.
+    pub fn details(&self) -> Option<String> {
+        match self {
+            Error::FileNotFound(path) | Error::PermissionDenied(path) => Some(path.display().to_string()),
+            _ => std::error::Error::source(self).map(|source| source.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        // SQLITE_BUSY and SQLITE_LOCKED, with or without extended codes
+        let locked = error
+            .as_database_error()
+            .and_then(|e| e.code())
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xFF, 5 | 6));
+
+        if locked {
+            Error::DatabaseLocked(error)
+        } else {
+            Error::Database(error)
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(error: tokio::task::JoinError) -> Self {
+        Error::Internal(format!("Background task failed: {}", error))
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut payload = serializer.serialize_struct("Error", 3)?;
+        payload.serialize_field("kind", self.kind())?;
+        payload.serialize_field("message", &self.to_string())?;
+        payload.serialize_field("details", &self.details())?;
+        payload.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use sqlx::sqlite::SqliteConnectOptions;
+    use sqlx::{ConnectOptions, Connection};
+    use std::borrow::Cow;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    /// A database error with only a result code, as SQLite reports it.
+    #[derive(Debug)]
+    struct Code(&'static str);
+
+    impl std::fmt::Display for Code {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "error code {}", self.0)
+        }
+    }
+
+    impl std::error::Error for Code {}
+
+    impl DatabaseError for Code {
+        fn message(&self) -> &str {
+            "error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    #[test]
+    fn errors_serialize_as_kind_message_and_details() {
+        let missing = Error::io(Path::new("/books/gone.epub"), IoErrorKind::NotFound.into());
+        assert_eq!(
+            serde_json::to_value(&missing).unwrap(),
+            json!({
+                "kind": "file_not_found",
+                "message": "File not found: /books/gone.epub",
+                "details": "/books/gone.epub",
+            })
+        );
+
+        let unreadable = Error::io(Path::new("/books/a.txt"), std::io::Error::other("disk on fire"));
+        assert_eq!(
+            serde_json::to_value(&unreadable).unwrap(),
+            json!({"kind": "io", "message": "Failed to read /books/a.txt", "details": "disk on fire"})
+        );
+
+        assert_eq!(
+            serde_json::to_value(Error::NotFound("Bookmark")).unwrap(),
+            json!({"kind": "not_found", "message": "Bookmark not found", "details": null})
+        );
+    }
+
+    #[test]
+    fn busy_and_locked_codes_mean_the_database_is_locked() {
+        // SQLITE_BUSY, SQLITE_LOCKED, SQLITE_LOCKED_SHAREDCACHE, SQLITE_BUSY_SNAPSHOT
+        for code in ["5", "6", "262", "517"] {
+            let error = Error::from(sqlx::Error::Database(Box::new(Code(code))));
+            assert_eq!(error.kind(), "database_locked", "code {}", code);
+        }
+        // SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_UNIQUE
+        for code in ["19", "2067"] {
+            let error = Error::from(sqlx::Error::Database(Box::new(Code(code))));
+            assert_eq!(error.kind(), "database", "code {}", code);
+        }
+        assert_eq!(Error::from(sqlx::Error::RowNotFound).kind(), "database");
+    }
+
+    #[tokio::test]
+    async fn a_write_blocked_by_another_connection_is_database_locked() {
+        let path = std::env::temp_dir().join(format!("locked-{}.db", uuid::Uuid::new_v4()));
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.to_string_lossy()))
+            .unwrap()
+            .create_if_missing(true)
+            .busy_timeout(Duration::ZERO);
+        let mut writer = options.connect().await.unwrap();
+        let mut blocked = options.connect().await.unwrap();
+
+        sqlx::query("CREATE TABLE books (title TEXT)").execute(&mut writer).await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE").execute(&mut writer).await.unwrap();
+        let error = sqlx::query("INSERT INTO books VALUES ('Emma')")
+            .execute(&mut blocked)
+            .await
+            .map_err(Error::from)
+            .unwrap_err();
+        assert_eq!(error.kind(), "database_locked");
+        assert_eq!(error.to_string(), "The library database is busy");
+
+        sqlx::query("COMMIT").execute(&mut writer).await.unwrap();
+        sqlx::query("INSERT INTO books VALUES ('Emma')").execute(&mut blocked).await.unwrap();
+
+        writer.close().await.unwrap();
+        blocked.close().await.unwrap();
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,555 @@
+use crate::database::{Database, ImportFileStatus, ImportJob, ImportJobStatus, StoredDocument};
+use crate::error::{Error, Result};
+use crate::library::list_files;
+use crate::library::watch::FolderChange;
+use crate::parsers::progress::Progress;
+use crate::parsers::{content_hash, registry};
+use crate::{index_document_text, parse_options, Document, DocumentCache};
+use chrono::Utc;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Files an import job works on at once. Parsing happens on the blocking
+// pool, so this bounds the threads one big import keeps busy.
+const JOB_CONCURRENCY: usize = 4;
+
+// Content hashes being imported right now, so two copies of a book imported
+// side by side don't both end up in the library
+static IMPORTING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// What became of the files offered to the library by a folder scan.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ImportSummary {
+    pub imported: Vec<String>,
+    pub relocated: Vec<String>, // Books already in the library whose files moved here
+    pub skipped: Vec<String>,   // Already in the library, or not a book
+    pub failed: Vec<ImportFailure>,
+    pub missing: Vec<String>, // Books whose files were removed from a watched folder
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportFailure {
+    pub file_path: String,
+    pub kind: String, // As in `Error::kind`
+    pub message: String,
+}
+
+impl ImportSummary {
+    /// Whether the library changed, or something went wrong.
+    pub fn has_changes(&self) -> bool {
+        !(self.imported.is_empty() && self.relocated.is_empty() && self.failed.is_empty() && self.missing.is_empty())
+    }
+
+    fn merge(&mut self, other: ImportSummary) {
+        self.imported.extend(other.imported);
+        self.relocated.extend(other.relocated);
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+        self.missing.extend(other.missing);
+    }
+}
+
+impl ImportFailure {
+    fn new(file_path: String, error: &Error) -> Self {
+        Self {
+            file_path,
+            kind: error.kind().to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Sent for each file an import job finishes with.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportJobProgress {
+    pub job_id: String,
+    pub file_path: String,
+    pub status: ImportFileStatus,
+    pub error: Option<ImportFailure>,
+    pub done: usize, // Files finished this run, out of `remaining`
+    pub remaining: usize,
+}
+
+/// Parses a file and saves it to the library. A book the library already
+/// has, by content or by path, keeps its id, reading position and settings.
+pub async fn import_document(
+    path: &Path,
+    progress: &Progress,
+    db: &Database,
+    cache: &DocumentCache,
+) -> Result<Document> {
+    let file_path = path.to_string_lossy().into_owned();
+    let mut options = parse_options(db, None).await;
+    let mut document = registry::parse(path, &options, progress).await?;
+
+    // Re-opening a known book (even from a new path) keeps its id and progress
+    let existing = db.find_document(&document.content_hash, &file_path).await?;
+
+    if let Some(existing) = &existing {
+        // Keep decoding a text file the way the user chose
+        if let Some(encoding) = existing.encoding_override.as_deref() {
+            if document.encoding.is_some() && document.encoding.as_deref() != Some(encoding) {
+                options.encoding = Some(encoding.to_string());
+                document = registry::parse(path, &options, progress).await?;
+            }
+        }
+
+        document.id = existing.id.clone();
+        document.current_position = existing.current_position.max(0) as usize;
+
+        // The file was changed in place, so anything parsed from it is stale
+        if existing.content_hash.as_deref() != Some(document.content_hash.as_str()) {
+            cache.clear(&document.id);
+            db.clear_document_cache(&document.id).await?;
+        }
+    }
+
+    // Nothing is saved for a cancelled import
+    progress.update("Saving", 0.0)?;
+
+    // Save document to database
+    let stored_doc = StoredDocument {
+        id: document.id.clone(),
+        title: document.title.clone(),
+        author: document.author.clone(),
+        file_path,
+        file_type: document.file_type.clone(),
+        content_hash: Some(document.content_hash.clone()),
+        encoding: document.encoding.clone(),
+        encoding_override: existing.as_ref().and_then(|doc| doc.encoding_override.clone()),
+        total_pages: document.total_pages as i32,
+        current_position: document.current_position as i32,
+        last_read: Utc::now(),
+        added_date: existing.map(|doc| doc.added_date).unwrap_or_else(Utc::now),
+        file_missing: false,
+    };
+
+    db.save_document(&stored_doc).await?;
+
+    // The user's edits to a known book win over what the file says
+    db.get_document_metadata(&document.id).await?.apply(&mut document);
+
+    // A failed index only affects library search, so don't fail the import
+    if let Err(e) = index_document_text(&document, db).await {
+        println!("Failed to index document {}: {}", document.id, e);
+    }
+
+    Ok(document)
+}
+
+/// Adds each file the library doesn't have yet. Files it already has are
+/// recognised by content hash without being parsed.
+pub async fn import_files(paths: &[PathBuf], db: &Database, cache: &DocumentCache) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+
+    for path in paths {
+        let file_path = path.to_string_lossy().into_owned();
+        match import_new_file(path, db, cache, &Progress::default()).await {
+            Ok(ImportFileStatus::Imported) => summary.imported.push(file_path),
+            Ok(ImportFileStatus::Relocated) => summary.relocated.push(file_path),
+            Ok(_) => summary.skipped.push(file_path),
+            Err(e) => summary.failed.push(ImportFailure::new(file_path, &e)),
+        }
+    }
+
+    summary
+}
+
+/// Imports every book in each library folder. A folder that can't be read
+/// is reported as a failure rather than stopping the others. Files at the
+/// path of a book the library has and can find are skipped unread.
+pub async fn scan_folders(folders: &[String], db: &Database, cache: &DocumentCache) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+
+    let known: HashSet<String> = match db.get_all_documents().await {
+        Ok(documents) => documents
+            .into_iter()
+            .filter(|doc| !doc.file_missing)
+            .map(|doc| doc.file_path)
+            .collect(),
+        Err(e) => {
+            println!("Checking every file, the library couldn't be read: {}", e);
+            HashSet::new()
+        }
+    };
+
+    for folder in folders {
+        let path = PathBuf::from(folder);
+        match tokio::task::spawn_blocking(move || list_files(&path)).await {
+            Ok(Ok(files)) => {
+                let (present, new): (Vec<_>, Vec<_>) = files
+                    .into_iter()
+                    .partition(|file| known.contains(file.to_string_lossy().as_ref()));
+                summary
+                    .skipped
+                    .extend(present.iter().map(|file| file.to_string_lossy().into_owned()));
+                summary.merge(import_files(&new, db, cache).await);
+            }
+            Ok(Err(e)) => summary.failed.push(ImportFailure::new(folder.clone(), &e)),
+            Err(e) => summary.failed.push(ImportFailure::new(folder.clone(), &e.into())),
+        }
+    }
+
+    summary
+}
+
+/// Brings the library up to date with changes seen in its folders: new files
+/// are imported, and books whose files were removed are flagged as missing.
+pub async fn apply_folder_changes(
+    changes: Vec<FolderChange>,
+    db: &Database,
+    cache: &DocumentCache,
+) -> Result<ImportSummary> {
+    let (mut added, mut removed) = (Vec::new(), Vec::new());
+    for change in changes {
+        match change {
+            FolderChange::Added(path) => added.push(path),
+            FolderChange::Removed(path) => removed.push(path),
+        }
+    }
+
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        for path in added {
+            if path.is_dir() {
+                match list_files(&path) {
+                    Ok(found) => files.extend(found),
+                    Err(e) => println!("Skipping {}: {}", path.display(), e),
+                }
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+        files.sort();
+        files.dedup();
+        files
+    })
+    .await?;
+
+    // Imported first, so a book renamed within its folder is relocated
+    // rather than flagged missing
+    let mut summary = import_files(&files, db, cache).await;
+
+    if !removed.is_empty() {
+        for document in db.get_all_documents().await? {
+            let path = Path::new(&document.file_path);
+            if !document.file_missing && removed.iter().any(|removed| path.starts_with(removed)) && !path.exists() {
+                db.set_file_missing(&document.id, true).await?;
+                summary.missing.push(document.file_path);
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+async fn import_new_file(
+    path: &Path,
+    db: &Database,
+    cache: &DocumentCache,
+    progress: &Progress,
+) -> Result<ImportFileStatus> {
+    // Hidden files are system clutter (.DS_Store and the like), not books
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    if hidden {
+        return Ok(ImportFileStatus::Skipped);
+    }
+
+    let owned_path = path.to_path_buf();
+    let hash = tokio::task::spawn_blocking(move || {
+        registry::detect(&owned_path)?;
+        content_hash(&owned_path)
+    })
+    .await?;
+    let hash = match hash {
+        Ok(hash) => hash,
+        Err(Error::UnsupportedFormat) => return Ok(ImportFileStatus::Skipped),
+        Err(e) => return Err(e),
+    };
+
+    // Another copy of this book is being imported, so this one is a duplicate
+    let Some(_claim) = HashClaim::take(&hash) else {
+        return Ok(ImportFileStatus::Skipped);
+    };
+
+    let file_path = path.to_string_lossy();
+    if let Some(existing) = db.find_document(&hash, &file_path).await? {
+        if existing.content_hash.as_deref() == Some(hash.as_str()) {
+            if existing.file_path == file_path {
+                // The book is back where it was before going missing
+                if existing.file_missing {
+                    db.set_file_missing(&existing.id, false).await?;
+                    return Ok(ImportFileStatus::Relocated);
+                }
+                return Ok(ImportFileStatus::Skipped);
+            }
+            if Path::new(&existing.file_path).exists() {
+                return Ok(ImportFileStatus::Skipped);
+            }
+
+            // The book was moved or renamed
+            if !db.relocate_document(&existing.id, &file_path).await? {
+                return Err(Error::InvalidInput(format!(
+                    "\"{}\" moved here, but the library has another book stored at this path",
+                    existing.title
+                )));
+            }
+            return Ok(ImportFileStatus::Relocated);
+        }
+        // Otherwise the file at this path has changed, so it's parsed again
+    }
+
+    import_document(path, progress, db, cache).await?;
+    Ok(ImportFileStatus::Imported)
+}
+
+/// A content hash marked as being imported, until dropped.
+struct HashClaim(String);
+
+impl HashClaim {
+    fn take(hash: &str) -> Option<Self> {
+        IMPORTING
+            .lock()
+            .unwrap()
+            .insert(hash.to_string())
+            .then(|| HashClaim(hash.to_string()))
+    }
+}
+
+impl Drop for HashClaim {
+    fn drop(&mut self) {
+        IMPORTING.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// The files an import job covers: each path given, with folders replaced
+/// by every file under them. A folder that can't be listed is kept, so the
+/// job reports why.
+pub fn expand_paths(paths: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+
+    for path in paths {
+        let folder = Path::new(path);
+        let found = match folder.is_dir() {
+            true => match list_files(folder) {
+                Ok(found) => found.into_iter().map(|file| file.to_string_lossy().into_owned()).collect(),
+                Err(_) => vec![path.clone()],
+            },
+            false => vec![path.clone()],
+        };
+        files.extend(found.into_iter().filter(|file| seen.insert(file.clone())));
+    }
+
+    files
+}
+
+/// Imports the pending files of a saved job, a few at a time, recording each
+/// result as it comes in. When `progress` is cancelled, files not yet done
+/// stay pending and the job is left cancelled, to be resumed later.
+pub async fn run_job(
+    job_id: &str,
+    db: &Database,
+    cache: &DocumentCache,
+    progress: &Progress,
+    on_file: impl Fn(ImportJobProgress),
+) -> Result<ImportJob> {
+    db.set_import_job_status(job_id, ImportJobStatus::Running).await?;
+    let files = db.get_pending_import_files(job_id).await?;
+    let remaining = files.len();
+
+    // Results are saved inside each file's future: awaiting the database
+    // between polls of the stream would stall imports holding a write lock
+    let mut results = stream::iter(files)
+        .map(|file_path| async move {
+            let result = match progress.check() {
+                Ok(()) => import_new_file(Path::new(&file_path), db, cache, progress).await,
+                Err(e) => Err(e),
+            };
+            let (status, error) = match result {
+                Ok(status) => (status, None),
+                // Left pending for when the job is resumed
+                Err(Error::Cancelled) => return Ok(None),
+                Err(e) => (ImportFileStatus::Failed, Some(e)),
+            };
+            db.set_import_file_status(job_id, &file_path, status, error.as_ref()).await?;
+            Ok::<_, Error>(Some((file_path, status, error)))
+        })
+        .buffer_unordered(JOB_CONCURRENCY);
+
+    let mut done = 0;
+    while let Some(result) = results.next().await {
+        let Some((file_path, status, error)) = result? else {
+            continue;
+        };
+
+        done += 1;
+        on_file(ImportJobProgress {
+            job_id: job_id.to_string(),
+            error: error.map(|e| ImportFailure::new(file_path.clone(), &e)),
+            file_path,
+            status,
+            done,
+            remaining,
+        });
+    }
+
+    let status = match progress.check() {
+        Ok(()) => ImportJobStatus::Finished,
+        Err(_) => ImportJobStatus::Cancelled,
+    };
+    db.set_import_job_status(job_id, status).await?;
+
+    db.get_import_job(job_id).await?.ok_or(Error::NotFound("Import job"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::testing::TempDatabase;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// A folder in the temp directory, removed when dropped.
+    struct TempFolder(PathBuf);
+
+    impl TempFolder {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("import-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        // Each book's text is unique, so tests importing side by side never
+        // share a content hash
+        fn book(&self, name: &str) -> PathBuf {
+            let path = self.0.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, format!("{}\n\nIt was a dark and stormy night. {}\n", name, uuid::Uuid::new_v4())).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempFolder {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn names(paths: &[String]) -> Vec<String> {
+        let mut names: Vec<String> = paths
+            .iter()
+            .map(|path| Path::new(path).file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn folders_expand_to_their_files() {
+        let folder = TempFolder::new();
+        let first = folder.book("a.txt");
+        folder.book("nested/b.txt");
+        let missing = folder.0.join("gone.txt").to_string_lossy().into_owned();
+        let first = first.to_string_lossy().into_owned();
+
+        let files = expand_paths(&[first.clone(), folder.0.to_string_lossy().into_owned(), missing.clone()]);
+
+        // Given files come first and aren't repeated by their folder
+        assert_eq!(files[0], first);
+        assert_eq!(names(&files), ["a.txt", "b.txt", "gone.txt"]);
+        assert_eq!(files.last(), Some(&missing));
+    }
+
+    #[tokio::test]
+    async fn folder_changes_import_and_flag_books() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let cache = DocumentCache::new();
+        let folder = TempFolder::new();
+        let kept = folder.book("kept.txt");
+        let removed = folder.book("nested/removed.txt");
+        folder.book(".DS_Store");
+
+        let summary = apply_folder_changes(vec![FolderChange::Added(folder.0.clone())], &db, &cache)
+            .await
+            .unwrap();
+        assert_eq!(names(&summary.imported), ["kept.txt", "removed.txt"]);
+        assert_eq!(names(&summary.skipped), [".DS_Store"]);
+
+        let contents = std::fs::read(&removed).unwrap();
+        std::fs::remove_file(&removed).unwrap();
+        let summary = apply_folder_changes(vec![FolderChange::Removed(removed.clone())], &db, &cache)
+            .await
+            .unwrap();
+        assert_eq!(names(&summary.missing), ["removed.txt"]);
+        async fn missing(db: &Database, path: &Path) -> bool {
+            let path = path.to_string_lossy();
+            db.get_all_documents()
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|doc| doc.file_path == path)
+                .unwrap()
+                .file_missing
+        }
+        assert!(missing(&db, &removed).await);
+
+        // Put back where it was, it's found again
+        std::fs::write(&removed, contents).unwrap();
+        let summary = apply_folder_changes(vec![FolderChange::Added(removed.clone())], &db, &cache)
+            .await
+            .unwrap();
+        assert_eq!(names(&summary.relocated), ["removed.txt"]);
+        assert!(!missing(&db, &removed).await);
+
+        // A book the library has in place isn't read again on launch
+        std::fs::write(&kept, "Changed without the library noticing").unwrap();
+        let summary = scan_folders(&[folder.0.to_string_lossy().into_owned()], &db, &cache).await;
+        assert!(summary.imported.is_empty());
+        assert_eq!(names(&summary.skipped), [".DS_Store", "kept.txt", "removed.txt"]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_jobs_resume_where_they_stopped() {
+        let temp = TempDatabase::new();
+        let db = temp.open().await;
+        let cache = DocumentCache::new();
+        let folder = TempFolder::new();
+        for i in 0..12 {
+            folder.book(&format!("book-{:02}.txt", i));
+        }
+        let files = expand_paths(&[folder.0.to_string_lossy().into_owned()]);
+        db.create_import_job("job", &files).await.unwrap();
+
+        // Cancelled as soon as the first file is done
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let progress = Progress::new(|_: &str, _: u8| {}, cancelled.clone());
+        let job = run_job("job", &db, &cache, &progress, |_| cancelled.store(true, Ordering::Relaxed))
+            .await
+            .unwrap();
+        assert_eq!(job.status, ImportJobStatus::Cancelled);
+        assert_eq!(job.total, 12);
+        assert!(job.imported >= 1 && job.pending > 0, "{:?}", job);
+        assert_eq!(job.imported + job.pending, 12);
+        let pending = db.get_pending_import_files("job").await.unwrap();
+        assert_eq!(pending.len() as i64, job.pending);
+
+        // The second run only imports what the first left
+        let done = Mutex::new(Vec::new());
+        let job = run_job("job", &db, &cache, &Progress::default(), |file| {
+            done.lock().unwrap().push(file.file_path)
+        })
+        .await
+        .unwrap();
+        assert_eq!(job.status, ImportJobStatus::Finished);
+        assert_eq!((job.imported, job.pending, job.failed), (12, 0, 0));
+        assert_eq!(names(&done.into_inner().unwrap()), names(&pending));
+        assert_eq!(db.get_all_documents().await.unwrap().len(), 12);
+    }
+}
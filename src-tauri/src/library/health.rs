@@ -0,0 +1,84 @@
+use super::list_files;
+use crate::error::Result;
+use crate::parsers::{content_hash, registry};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Searches `directory` and everything under it for files with the wanted
+/// content hashes, given as hash to the file name the book was last seen
+/// under. Returns where each hash was found.
+///
+/// Every candidate has to be read in full to hash it, so files with a wanted
+/// name are tried first: when a folder has simply moved, nothing else is read.
+/// Files that aren't books, by their signature, are never hashed.
+pub fn find_by_content_hash(
+    directory: &Path,
+    wanted: &HashMap<String, String>,
+) -> Result<HashMap<String, PathBuf>> {
+    let mut files = list_files(directory)?;
+
+    let has_wanted_name = |path: &PathBuf| {
+        path.file_name()
+            .is_some_and(|name| wanted.values().any(|wanted| name.to_string_lossy() == *wanted))
+    };
+    files.sort_by_key(|path| !has_wanted_name(path));
+
+    let mut found = HashMap::new();
+    for path in files {
+        if found.len() == wanted.len() {
+            break;
+        }
+        if registry::detect(&path).is_err() {
+            continue;
+        }
+        // A file that can't be read can't be matched either
+        let Ok(hash) = content_hash(&path) else {
+            continue;
+        };
+        if wanted.contains_key(&hash) {
+            found.entry(hash).or_insert(path);
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn finds_moved_books_by_content() {
+        let directory = std::env::temp_dir().join(format!("health-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(directory.join("renamed")).unwrap();
+        let write = |name: &str, bytes: &[u8]| {
+            let path = directory.join(name);
+            fs::write(&path, bytes).unwrap();
+            (content_hash(&path).unwrap(), path)
+        };
+
+        let (moved, moved_path) = write("moby-dick.txt", b"Call me Ishmael.");
+        let (renamed, renamed_path) = write("renamed/book (1).txt", b"It is a truth universally acknowledged.");
+        // Same bytes under a second name; either copy will do
+        write("copy.txt", b"Call me Ishmael.");
+        // Not a book, so never read, even though its hash is wanted
+        let (junk, _) = write("cover.bin", &[0, 159, 146, 150, 0, 0, 1, 2]);
+
+        let wanted: HashMap<String, String> = [
+            (moved.clone(), "moby-dick.txt".to_string()),
+            (renamed.clone(), "pride.txt".to_string()),
+            (junk, "cover.bin".to_string()),
+            ("0".repeat(64), "gone.txt".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let found = find_by_content_hash(&directory, &wanted).unwrap();
+        fs::remove_dir_all(&directory).ok();
+
+        assert_eq!(found.len(), 2);
+        // The file with the name the book had is tried, and taken, first
+        assert_eq!(found[&moved], moved_path);
+        assert_eq!(found[&renamed], renamed_path);
+    }
+}
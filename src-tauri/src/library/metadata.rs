@@ -0,0 +1,204 @@
+use crate::error::{Error, Result};
+use crate::Document;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Metadata the user has edited. Each field set replaces what the parser
+/// found; `None` keeps the book's own value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataOverrides {
+    pub title: Option<String>,
+    pub authors: Option<Vec<String>>,
+    pub series: Option<String>,
+    pub series_index: Option<f64>,
+    pub publisher: Option<String>,
+    pub language: Option<String>,
+    pub published_date: Option<String>, // YYYY, YYYY-MM or YYYY-MM-DD
+    pub isbn: Option<String>, // ISBN-10 or ISBN-13, digits only
+    pub description: Option<String>,
+    pub title_sort: Option<String>,
+    pub author_sort: Option<String>,
+}
+
+/// A document's edited metadata and its tags, as sent to and from the
+/// metadata editor. `tags` left out of an update keeps the current tags.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DocumentMetadata {
+    #[serde(flatten)]
+    pub overrides: MetadataOverrides,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+impl MetadataOverrides {
+    /// Trims every field and checks the ones with a format. Blank fields
+    /// become `None`, going back to the book's own value.
+    pub fn validated(self) -> Result<Self> {
+        let series_index = match self.series_index {
+            Some(index) if !index.is_finite() || index < 0.0 => {
+                return Err(Error::InvalidInput(format!("Series index {} isn't a valid position", index)))
+            }
+            index => index,
+        };
+
+        Ok(Self {
+            title: text(self.title),
+            authors: self
+                .authors
+                .map(|authors| authors.iter().filter_map(|author| text(Some(author.clone()))).collect::<Vec<_>>())
+                .filter(|authors| !authors.is_empty()),
+            series: text(self.series),
+            series_index,
+            publisher: text(self.publisher),
+            language: text(self.language),
+            published_date: text(self.published_date).map(|date| published_date(&date)).transpose()?,
+            isbn: text(self.isbn).map(|isbn| normalize_isbn(&isbn)).transpose()?,
+            description: text(self.description),
+            title_sort: text(self.title_sort),
+            author_sort: text(self.author_sort),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The authors as one line, the way `Document::author` holds them.
+    pub fn author(&self) -> Option<String> {
+        self.authors.as_ref().map(|authors| authors.join(", "))
+    }
+
+    /// Puts the edited values into a parsed document, so the reader shows them.
+    pub fn apply(&self, document: &mut Document) {
+        if let Some(title) = &self.title {
+            document.title = title.clone();
+        }
+        if let Some(author) = self.author() {
+            document.author = Some(author);
+        }
+        if let Some(series) = &self.series {
+            document.series = Some(series.clone());
+        }
+        if let Some(index) = self.series_index {
+            document.series_index = Some(index);
+        }
+        if let Some(description) = &self.description {
+            document.description = Some(description.clone());
+        }
+    }
+}
+
+fn text(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Checks a date of publication, to the year, month or day
+fn published_date(date: &str) -> Result<String> {
+    let invalid = || Error::InvalidInput(format!("\"{}\" isn't a date like 1851, 1851-10 or 1851-10-18", date));
+
+    let parts: Vec<&str> = date.split('-').collect();
+    let lengths_ok = parts.len() <= 3
+        && parts[0].len() == 4
+        && parts[1..].iter().all(|part| part.len() == 2)
+        && parts.iter().all(|part| part.bytes().all(|b| b.is_ascii_digit()));
+    if !lengths_ok {
+        return Err(invalid());
+    }
+
+    let number = |index: usize| parts.get(index).map_or(Ok(1), |part| part.parse::<u32>()).map_err(|_| invalid());
+    let year = number(0)? as i32;
+    NaiveDate::from_ymd_opt(year, number(1)?, number(2)?).ok_or_else(invalid)?;
+
+    Ok(date.to_string())
+}
+
+/// An ISBN without hyphens or spaces, if its check digit is right.
+pub fn normalize_isbn(isbn: &str) -> Result<String> {
+    let digits: String = isbn
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let valid = match digits.len() {
+        10 => {
+            let (body, check) = digits.split_at(9);
+            body.bytes().all(|b| b.is_ascii_digit())
+                && (check == "X" || check.bytes().all(|b| b.is_ascii_digit()))
+                && digits
+                    .bytes()
+                    .enumerate()
+                    .map(|(i, b)| (10 - i as u32) * if b == b'X' { 10 } else { (b - b'0') as u32 })
+                    .sum::<u32>()
+                    % 11
+                    == 0
+        }
+        13 => {
+            digits.bytes().all(|b| b.is_ascii_digit())
+                && digits
+                    .bytes()
+                    .enumerate()
+                    .map(|(i, b)| if i % 2 == 0 { 1 } else { 3 } * (b - b'0') as u32)
+                    .sum::<u32>()
+                    % 10
+                    == 0
+        }
+        _ => false,
+    };
+
+    match valid {
+        true => Ok(digits),
+        false => Err(Error::InvalidInput(format!("\"{}\" isn't a valid ISBN", isbn))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isbns_are_checked_and_normalized() {
+        assert_eq!(normalize_isbn("978-0-14-243724-7").unwrap(), "9780142437247");
+        assert_eq!(normalize_isbn("0 8044 2957 x").unwrap(), "080442957X");
+        assert!(normalize_isbn("978-0-14-243724-8").is_err());
+        assert!(normalize_isbn("0-8044-2957-1").is_err());
+        assert!(normalize_isbn("X804429570").is_err());
+        assert!(normalize_isbn("12345").is_err());
+    }
+
+    #[test]
+    fn blank_fields_fall_back_to_the_book() {
+        let overrides = MetadataOverrides {
+            title: Some("  Moby-Dick ".to_string()),
+            authors: Some(vec![" ".to_string()]),
+            publisher: Some(String::new()),
+            ..Default::default()
+        }
+        .validated()
+        .unwrap();
+
+        assert_eq!(overrides.title.as_deref(), Some("Moby-Dick"));
+        assert_eq!(overrides.authors, None);
+        assert_eq!(overrides.publisher, None);
+        assert!(MetadataOverrides::default().validated().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for date in ["1851", "1851-10", "1851-10-18"] {
+            assert!(published_date(date).is_ok(), "{}", date);
+        }
+        for date in ["51", "1851-13", "1851-02-30", "1851/10/18", "1851-1-8", "October 1851"] {
+            assert!(published_date(date).is_err(), "{}", date);
+        }
+
+        let negative = MetadataOverrides {
+            series_index: Some(-1.0),
+            ..Default::default()
+        };
+        assert!(matches!(negative.validated(), Err(Error::InvalidInput(_))));
+    }
+}
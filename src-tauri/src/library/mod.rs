@@ -1,5 +1,12 @@
+pub mod health;
+pub mod metadata;
+pub mod watch;
+
+use crate::error::{Error, Result};
 use crate::{Document, ReadingProgress};
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use tokio::sync::RwLock;
 
 #[derive(Default)]
@@ -50,9 +57,9 @@ impl Library {
         &self,
         document_id: String,
         query: String,
-    ) -> Result<Vec<(usize, String)>, String> {
+    ) -> Result<Vec<(usize, String)>> {
         let docs = self.documents.read().await;
-        let document = docs.get(&document_id).ok_or("Document not found")?;
+        let document = docs.get(&document_id).ok_or(Error::NotFound("Document"))?;
 
         let mut results = Vec::new();
         let lines: Vec<&str> = document.content.lines().collect();
@@ -74,3 +81,28 @@ impl Library {
         docs.remove(document_id).is_some()
     }
 }
+
+/// Every file in `directory` and its subfolders.
+pub fn list_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_files(directory, &mut files).map_err(|e| Error::io(directory, e))?;
+    Ok(files)
+}
+
+fn collect_files(directory: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Symlinked folders aren't followed, so a link loop can't recurse forever
+        if entry.file_type()?.is_dir() {
+            // An unreadable subfolder shouldn't stop the rest of the listing
+            if let Err(e) = collect_files(&path, files) {
+                println!("Skipping {}: {}", path.display(), e);
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
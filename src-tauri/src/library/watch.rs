@@ -0,0 +1,130 @@
+use crate::error::{Error, Result};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// A change to a watched library folder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FolderChange {
+    Added(PathBuf), // Created, written to or renamed into place; file or folder
+    Removed(PathBuf),
+}
+
+/// Watches library folders and everything under them, sending each change
+/// down a channel.
+pub struct FolderWatcher {
+    watcher: Mutex<RecommendedWatcher>,
+}
+
+impl FolderWatcher {
+    pub fn new(changes: UnboundedSender<FolderChange>) -> Result<Self> {
+        let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) => {
+                for change in folder_changes(event) {
+                    // The receiver only goes away when the app is closing
+                    let _ = changes.send(change);
+                }
+            }
+            Err(e) => println!("Library folder watch error: {}", e),
+        })
+        .map_err(|e| watch_error(Path::new(""), e))?;
+
+        Ok(Self {
+            watcher: Mutex::new(watcher),
+        })
+    }
+
+    pub fn watch(&self, folder: &Path) -> Result<()> {
+        self.watcher
+            .lock()
+            .unwrap()
+            .watch(folder, RecursiveMode::Recursive)
+            .map_err(|e| watch_error(folder, e))
+    }
+
+    pub fn unwatch(&self, folder: &Path) -> Result<()> {
+        self.watcher
+            .lock()
+            .unwrap()
+            .unwatch(folder)
+            .map_err(|e| watch_error(folder, e))
+    }
+}
+
+fn folder_changes(event: Event) -> Vec<FolderChange> {
+    let mut paths = event.paths.into_iter();
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => {
+            paths.map(FolderChange::Added).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => paths.map(FolderChange::Added).collect(),
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            paths.map(FolderChange::Removed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            paths.next().map(FolderChange::Removed).into_iter().chain(paths.map(FolderChange::Added)).collect()
+        }
+        // Which side of the rename this is isn't known, so look at the disk
+        EventKind::Modify(ModifyKind::Name(_)) => paths
+            .map(|path| match path.exists() {
+                true => FolderChange::Added(path),
+                false => FolderChange::Removed(path),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn watch_error(folder: &Path, error: notify::Error) -> Error {
+    match error.kind {
+        notify::ErrorKind::Io(e) => Error::io(folder, e),
+        notify::ErrorKind::PathNotFound => Error::FileNotFound(folder.to_path_buf()),
+        kind => Error::Internal(format!("Can't watch {}: {:?}", folder.display(), kind)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind, RemoveKind};
+
+    fn changes(kind: EventKind, paths: &[&Path]) -> Vec<FolderChange> {
+        let event = paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()));
+        folder_changes(event)
+    }
+
+    #[test]
+    fn events_map_to_changes() {
+        let (a, b) = (Path::new("/library/a.epub"), Path::new("/library/b.epub"));
+        let added = |path: &Path| FolderChange::Added(path.to_path_buf());
+        let removed = |path: &Path| FolderChange::Removed(path.to_path_buf());
+
+        assert_eq!(changes(EventKind::Create(CreateKind::File), &[a]), [added(a)]);
+        assert_eq!(changes(EventKind::Create(CreateKind::Folder), &[a, b]), [added(a), added(b)]);
+        assert_eq!(changes(EventKind::Modify(ModifyKind::Data(DataChange::Content)), &[a]), [added(a)]);
+        assert_eq!(changes(EventKind::Modify(ModifyKind::Name(RenameMode::To)), &[a]), [added(a)]);
+        assert_eq!(changes(EventKind::Remove(RemoveKind::File), &[a]), [removed(a)]);
+        assert_eq!(changes(EventKind::Modify(ModifyKind::Name(RenameMode::From)), &[a]), [removed(a)]);
+        assert_eq!(
+            changes(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[a, b]),
+            [removed(a), added(b)]
+        );
+
+        // Nothing about the file's contents changed
+        assert!(changes(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)), &[a]).is_empty());
+        assert!(changes(EventKind::Access(AccessKind::Any), &[a]).is_empty());
+    }
+
+    #[test]
+    fn unknown_renames_look_at_the_disk() {
+        let present = std::env::temp_dir();
+        let gone = present.join(format!("gone-{}", uuid::Uuid::new_v4()));
+
+        assert_eq!(
+            changes(EventKind::Modify(ModifyKind::Name(RenameMode::Any)), &[&present, &gone]),
+            [FolderChange::Added(present.clone()), FolderChange::Removed(gone)]
+        );
+    }
+}
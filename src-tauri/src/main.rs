@@ -1,19 +1,39 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod annotations;
 mod database;
+mod error;
+mod import;
 mod library;
 mod parsers;
+mod search;
 
+use annotations::{Annotation, HighlightColor, TextAnchor};
 use chrono::Utc;
-use database::{Database, StoredDocument, UserSettings};
-use library::Library;
-use parsers::{epub_parser, pdf_parser, txt_parser};
+use database::{
+    Bookmark, Database, Group, GroupKind, ImportJobStatus, LibrarySort, ShelfFilter, SmartShelf, StoredDocument, UserSettings,
+};
+use error::{Error, Result};
+use import::{ImportFailure, ImportJobProgress, ImportSummary};
+use library::metadata::DocumentMetadata;
+use library::watch::{FolderChange, FolderWatcher};
+use library::{health, Library};
+use parsers::txt_chapters::ChapterDetection;
+use parsers::progress::Progress;
+use parsers::{gutenberg, registry, txt_parser, ParseOptions};
+use parsers::xhtml::Block;
+use search::{DocumentSearchResults, LibrarySearchHit};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Mutex;
-use tauri::{command, State};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{command, AppHandle, Emitter, Manager, State};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use uuid::Uuid;
 
 // Document content cache
 struct DocumentCache {
@@ -47,12 +67,142 @@ impl DocumentCache {
     }
 }
 
+/// Imports that are still parsing, so the frontend can cancel them.
+#[derive(Default)]
+struct ImportJobs {
+    running: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl ImportJobs {
+    /// Registers a job for as long as the returned guard lives, or `None` if
+    /// a job with that id is already running.
+    fn try_start(&self, job_id: &str) -> Option<ImportJob<'_>> {
+        let mut running = self.running.lock().unwrap();
+        if running.contains_key(job_id) {
+            return None;
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        running.insert(job_id.to_string(), cancelled.clone());
+        Some(ImportJob {
+            jobs: self,
+            job_id: job_id.to_string(),
+            cancelled,
+        })
+    }
+
+    fn is_running(&self, job_id: &str) -> bool {
+        self.running.lock().unwrap().contains_key(job_id)
+    }
+
+    /// Returns false if the job has already finished.
+    fn cancel(&self, job_id: &str) -> bool {
+        match self.running.lock().unwrap().get(job_id) {
+            Some(cancelled) => {
+                cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+struct ImportJob<'a> {
+    jobs: &'a ImportJobs,
+    job_id: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for ImportJob<'_> {
+    fn drop(&mut self) {
+        // Only this guard's own entry; never another job's cancel flag
+        let mut running = self.jobs.running.lock().unwrap();
+        if running.get(&self.job_id).is_some_and(|cancelled| Arc::ptr_eq(cancelled, &self.cancelled)) {
+            running.remove(&self.job_id);
+        }
+    }
+}
+
+/// Payload of the `import-progress` event sent while a document is parsed.
+#[derive(Debug, Serialize, Clone)]
+pub struct ImportProgress {
+    pub job_id: String,
+    pub file_path: String,
+    pub stage: String,
+    pub percent: u8, // Through the current stage
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Chapter {
     pub id: String,
     pub title: String,
     pub start_position: usize,
     pub end_position: usize,
+    pub level: usize, // Nesting depth in the table of contents, 0 = top level
+    pub parent_id: Option<String>,
+    pub href: Option<String>, // Source location inside the book, e.g. "OEBPS/ch01.xhtml#sec2"
+}
+
+/// The deepest TOC entry containing `position`.
+pub fn chapter_at(chapters: &[Chapter], position: usize) -> Option<&Chapter> {
+    chapters
+        .iter()
+        .filter(|c| c.start_position <= position && position < c.end_position)
+        .max_by_key(|c| (c.level, c.start_position))
+}
+
+/// A physical page of a paginated source (PDF, comics) as a range of `content`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Page {
+    pub index: usize, // 0-based position in the file
+    pub label: String, // Printed page number from /PageLabels, e.g. "iv" or "A-7"
+    pub start_position: usize,
+    pub end_position: usize,
+    pub image: Option<String>, // Resource path of the page's image (comics), for get_document_resource
+}
+
+/// The page containing `position`.
+pub fn page_at(pages: &[Page], position: usize) -> Option<&Page> {
+    let index = pages.partition_point(|page| page.start_position <= position);
+    pages.get(index.checked_sub(1)?)
+}
+
+/// The page printed with `label`, ignoring case. A plain number that no page
+/// is labelled with counts pages from 1, as the viewer's page numbers do.
+pub fn page_labelled<'a>(pages: &'a [Page], label: &str) -> Option<&'a Page> {
+    let label = label.trim();
+    pages
+        .iter()
+        .find(|page| page.label == label)
+        .or_else(|| pages.iter().find(|page| page.label.eq_ignore_ascii_case(label)))
+        .or_else(|| pages.get(label.parse::<usize>().ok()?.checked_sub(1)?))
+}
+
+/// Text kept out of the reading flow, such as licence boilerplate.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Appendix {
+    pub title: String,
+    pub content: String,
+    #[serde(skip)]
+    pub source: Range<usize>, // Bytes of the parsed text it was cut from, surrounding whitespace included
+}
+
+/// Whether a document is read as text or as a sequence of page images.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentKind {
+    #[default]
+    Text,
+    Comic,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ReadingDirection {
+    #[default]
+    #[serde(rename = "ltr")]
+    LeftToRight,
+    #[serde(rename = "rtl")]
+    RightToLeft, // Manga
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -62,10 +212,22 @@ pub struct Document {
     pub author: Option<String>,
     pub file_path: PathBuf,
     pub file_type: String,
-    pub content: String,
+    pub content_hash: String, // SHA-256 of the file, see parsers::content_hash
+    pub kind: DocumentKind,
+    pub reading_direction: ReadingDirection,
+    pub encoding: Option<String>, // Character encoding the text was decoded with (TXT)
+    pub asin: Option<String>, // Amazon identifier from MOBI/AZW3 metadata
+    pub series: Option<String>,
+    pub series_index: Option<f64>, // Position within `series`, e.g. 3 or 2.5
+    pub genres: Vec<String>,
+    pub description: Option<String>, // Blurb or annotation from the book's metadata
+    pub content: String, // Plain-text projection, used for search and word counts
+    pub blocks: Vec<Block>, // Structured content for formats that have it (EPUB)
     pub current_position: usize,
     pub total_pages: usize,
     pub chapters: Vec<Chapter>,
+    pub pages: Vec<Page>, // Empty for reflowable formats
+    pub appendices: Vec<Appendix>, // Not part of `content`; positions never point into them
     pub cover_image: Option<String>, // Base64 encoded cover image
 }
 
@@ -76,47 +238,318 @@ pub struct ReadingProgress {
     pub percentage: f32,
 }
 
+/// Result of `relocate_missing_documents`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RelocationReport {
+    pub relocated: Vec<StoredDocument>, // With their new file_path
+    pub still_missing: Vec<StoredDocument>,
+    pub conflicts: Vec<StoredDocument>, // Found, but another document is stored at the path
+}
+
+// Words of text stored with a bookmark so the list can show where it points
+const BOOKMARK_SNIPPET_WORDS: usize = 20;
+
+// Search results returned when the caller doesn't ask for a limit
+const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+// Quiet time after a change to a library folder before it's imported, so a
+// file still being copied in isn't read half-written
+const FOLDER_SETTLE_TIME: Duration = Duration::from_secs(2);
+
 // Tauri commands
+
+/// Opens a file and adds it to the library. With a `job_id`, progress is sent
+/// as `import-progress` events and `cancel_import` can stop the parse.
 #[command]
-async fn open_document(file_path: String, db: State<'_, Database>) -> Result<Document, String> {
+async fn open_document(
+    file_path: String,
+    job_id: Option<String>,
+    app: AppHandle,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+    jobs: State<'_, ImportJobs>,
+) -> Result<Document> {
     let path = PathBuf::from(&file_path);
-    let extension = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or("Invalid file extension")?;
-
-    let document = match extension.to_lowercase().as_str() {
-        "epub" => epub_parser::parse_epub(&path).await,
-        "pdf" => pdf_parser::parse_pdf(&path).await,
-        "txt" => txt_parser::parse_txt(&path).await,
-        _ => Err(format!("Unsupported file format: {}", extension)),
-    }?;
-
-    // Save document to database
-    let stored_doc = StoredDocument {
-        id: document.id.clone(),
-        title: document.title.clone(),
-        author: document.author.clone(),
-        file_path: file_path,
-        file_type: document.file_type.clone(),
-        total_pages: document.total_pages as i32,
-        current_position: 0,
-        last_read: Utc::now(),
-        added_date: Utc::now(),
+    let job = match job_id.as_deref() {
+        Some(job_id) => Some(
+            jobs.inner()
+                .try_start(job_id)
+                .ok_or_else(|| Error::InvalidInput(format!("Import {} is already running", job_id)))?,
+        ),
+        None => None,
+    };
+    let progress = match &job {
+        Some(job) => {
+            let (job_id, file_path) = (job.job_id.clone(), file_path.clone());
+            let sink = move |stage: &str, percent: u8| {
+                let event = ImportProgress {
+                    job_id: job_id.clone(),
+                    file_path: file_path.clone(),
+                    stage: stage.to_string(),
+                    percent,
+                };
+                if let Err(e) = app.emit("import-progress", event) {
+                    println!("Failed to send import progress: {}", e);
+                }
+            };
+            Progress::new(sink, job.cancelled.clone())
+        }
+        None => Progress::default(),
     };
 
-    db.save_document(&stored_doc)
-        .await
-        .map_err(|e| format!("Failed to save document: {}", e))?;
+    import::import_document(&path, &progress, &db, &cache).await
+}
+
+/// Re-reads a text document with the given encoding, or with detection again
+/// when `encoding` is `None`, and remembers the choice for later opens.
+#[command]
+async fn reopen_with_encoding(
+    document_id: String,
+    encoding: Option<String>,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<Document> {
+    let mut stored_doc = db
+        .get_document(&document_id)
+        .await?
+        .ok_or(Error::NotFound("Document"))?;
+
+    if stored_doc.file_type != "txt" {
+        return Err(Error::InvalidInput(format!(
+            "{} files don't have a selectable encoding",
+            stored_doc.file_type
+        )));
+    }
+
+    let encoding = encoding
+        .map(|label| txt_parser::encoding_for_label(&label).map(|e| e.name().to_string()))
+        .transpose()?;
+
+    let mut options = parse_options(&db, None).await;
+    options.encoding = encoding.clone();
+    let mut document = registry::parse(&PathBuf::from(&stored_doc.file_path), &options, &Progress::default()).await?;
+    document.id = stored_doc.id.clone();
+    document.current_position = stored_doc.current_position.max(0) as usize;
+
+    // Everything derived from the old text is stale
+    cache.clear(&document.id);
+    db.clear_document_cache(&document.id).await?;
+
+    // The stored title and author are the parsed ones; the user's edits are
+    // kept apart and applied on top
+    stored_doc.title = document.title.clone();
+    stored_doc.author = document.author.clone();
+    stored_doc.encoding = document.encoding.clone();
+    stored_doc.encoding_override = encoding;
+    stored_doc.content_hash = Some(document.content_hash.clone());
+    stored_doc.total_pages = document.total_pages as i32;
+    stored_doc.last_read = Utc::now();
+
+    db.save_document(&stored_doc).await?;
+
+    db.get_document_metadata(&document.id).await?.apply(&mut document);
+    cache.set(document.id.clone(), document.clone());
+
+    if let Err(e) = index_document_text(&document, &db).await {
+        println!("Failed to index document {}: {}", document.id, e);
+    }
 
     Ok(document)
 }
 
+/// The library, or only the documents in a collection, with a tag and/or on
+/// a smart shelf. Most recently read first unless `sort` says otherwise.
 #[command]
-async fn get_library(db: State<'_, Database>) -> Result<Vec<StoredDocument>, String> {
-    db.get_all_documents()
-        .await
-        .map_err(|e| format!("Failed to get library: {}", e))
+async fn get_library(
+    collection_id: Option<String>,
+    tag_id: Option<String>,
+    shelf_id: Option<String>,
+    sort: Option<LibrarySort>,
+    db: State<'_, Database>,
+) -> Result<Vec<StoredDocument>> {
+    let shelf = match shelf_id {
+        Some(shelf_id) => Some(db.get_shelf(&shelf_id).await?.ok_or(Error::NotFound("Shelf"))?),
+        None => None,
+    };
+
+    db.get_library(
+        collection_id.as_deref(),
+        tag_id.as_deref(),
+        shelf.as_ref().map(|shelf| &shelf.filter),
+        sort.unwrap_or_default(),
+    )
+    .await
+}
+
+/// Checks that every document's file is still where the library expects it,
+/// updating `file_missing` to match. Returns the documents that are missing.
+#[command]
+async fn check_library_health(db: State<'_, Database>) -> Result<Vec<StoredDocument>> {
+    let documents = db.get_all_documents().await?;
+
+    // Can be slow on network drives, so kept off the async workers
+    let paths: Vec<PathBuf> = documents.iter().map(|doc| PathBuf::from(&doc.file_path)).collect();
+    let present = tokio::task::spawn_blocking(move || paths.iter().map(|path| path.is_file()).collect::<Vec<_>>())
+        .await?;
+
+    let mut missing = Vec::new();
+    for (mut document, present) in documents.into_iter().zip(present) {
+        if document.file_missing == present {
+            db.set_file_missing(&document.id, !present).await?;
+            document.file_missing = !present;
+        }
+        if !present {
+            missing.push(document);
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Looks under `directory` for the files of documents marked missing, matching
+/// them by content hash, and points the library at the files it finds.
+#[command]
+async fn relocate_missing_documents(directory: String, db: State<'_, Database>) -> Result<RelocationReport> {
+    let missing: Vec<StoredDocument> = db
+        .get_all_documents()
+        .await?
+        .into_iter()
+        .filter(|doc| doc.file_missing)
+        .collect();
+
+    // Documents imported before hashing have nothing to match on
+    let wanted: HashMap<String, String> = missing
+        .iter()
+        .filter_map(|doc| {
+            let file_name = Path::new(&doc.file_path).file_name().unwrap_or_default();
+            Some((doc.content_hash.clone()?, file_name.to_string_lossy().into_owned()))
+        })
+        .collect();
+
+    let directory = PathBuf::from(directory);
+    let found = tokio::task::spawn_blocking(move || health::find_by_content_hash(&directory, &wanted)).await??;
+
+    let mut report = RelocationReport {
+        relocated: Vec::new(),
+        still_missing: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    for mut document in missing {
+        match document.content_hash.as_ref().and_then(|hash| found.get(hash)) {
+            Some(path) => {
+                let path = path.to_string_lossy().into_owned();
+                if db.relocate_document(&document.id, &path).await? {
+                    document.file_path = path;
+                    document.file_missing = false;
+                    report.relocated.push(document);
+                } else {
+                    report.conflicts.push(document);
+                }
+            }
+            None => report.still_missing.push(document),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Adds a folder to the library. Every book in it is imported now, and books
+/// added to it later are imported as they appear.
+#[command]
+async fn add_library_folder(
+    path: String,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+    watcher: State<'_, FolderWatcher>,
+) -> Result<ImportSummary> {
+    let folder = PathBuf::from(&path);
+    if !folder.is_dir() {
+        return Err(Error::InvalidInput(format!("{} isn't a folder", path)));
+    }
+
+    watcher.watch(&folder)?;
+    db.add_library_folder(&path).await?;
+
+    Ok(import::scan_folders(&[path], &db, &cache).await)
+}
+
+/// Stops importing from a folder. Books already imported from it stay.
+#[command]
+async fn remove_library_folder(
+    path: String,
+    db: State<'_, Database>,
+    watcher: State<'_, FolderWatcher>,
+) -> Result<()> {
+    if !db.remove_library_folder(&path).await? {
+        return Err(Error::NotFound("Library folder"));
+    }
+
+    // A folder that has been deleted is no longer watched anyway
+    if let Err(e) = watcher.unwatch(Path::new(&path)) {
+        println!("Failed to stop watching {}: {}", path, e);
+    }
+
+    Ok(())
+}
+
+#[command]
+async fn get_library_folders(db: State<'_, Database>) -> Result<Vec<String>> {
+    db.get_library_folders().await
+}
+
+/// Imports anything in the library folders that the library doesn't have yet.
+#[command]
+async fn scan_library_folders(
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<ImportSummary> {
+    let folders = db.get_library_folders().await?;
+    Ok(import::scan_folders(&folders, &db, &cache).await)
+}
+
+/// Catches up with the library folders, then follows changes to them for as
+/// long as the app runs. Anything that changes the library is reported as a
+/// `library-folder-import` event.
+async fn watch_library_folders(app: AppHandle, mut changes: UnboundedReceiver<FolderChange>) {
+    let (db, cache, watcher) = (
+        app.state::<Database>(),
+        app.state::<DocumentCache>(),
+        app.state::<FolderWatcher>(),
+    );
+
+    let folders = match db.get_library_folders().await {
+        Ok(folders) => folders,
+        Err(e) => {
+            println!("Failed to load library folders: {}", e);
+            Vec::new()
+        }
+    };
+    for folder in &folders {
+        if let Err(e) = watcher.watch(Path::new(folder)) {
+            println!("Not watching {}: {}", folder, e);
+        }
+    }
+    send_folder_import(&app, import::scan_folders(&folders, &db, &cache).await);
+
+    while let Some(change) = changes.recv().await {
+        let mut batch = vec![change];
+        while let Ok(Some(change)) = tokio::time::timeout(FOLDER_SETTLE_TIME, changes.recv()).await {
+            batch.push(change);
+        }
+
+        match import::apply_folder_changes(batch, &db, &cache).await {
+            Ok(summary) => send_folder_import(&app, summary),
+            Err(e) => println!("Failed to update library from its folders: {}", e),
+        }
+    }
+}
+
+fn send_folder_import(app: &AppHandle, summary: ImportSummary) {
+    if summary.has_changes() {
+        if let Err(e) = app.emit("library-folder-import", summary) {
+            println!("Failed to send library folder import: {}", e);
+        }
+    }
 }
 
 #[command]
@@ -124,74 +557,499 @@ async fn update_reading_progress(
     document_id: String,
     position: i32,
     db: State<'_, Database>,
-) -> Result<(), String> {
-    db.update_reading_progress(&document_id, position)
-        .await
-        .map_err(|e| format!("Failed to update progress: {}", e))
+) -> Result<()> {
+    db.update_reading_progress(&document_id, position).await
 }
 
 #[command]
-async fn save_user_settings(settings: UserSettings, db: State<'_, Database>) -> Result<(), String> {
-    db.save_settings(&settings)
-        .await
-        .map_err(|e| format!("Failed to save settings: {}", e))
+async fn add_annotation(
+    document_id: String,
+    start_position: usize,
+    end_position: usize,
+    color: HighlightColor,
+    note: Option<String>,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<Annotation> {
+    let document = load_document_by_id(&document_id, &cache, &db).await?;
+    let anchor = TextAnchor::new(&document.content, &document.chapters, start_position, end_position)?;
+
+    let now = Utc::now();
+    let annotation = Annotation {
+        id: Uuid::new_v4().to_string(),
+        document_id,
+        anchor,
+        color,
+        note,
+        orphaned: false,
+        created_at: now,
+        updated_at: now,
+    };
+
+    db.save_annotation(&annotation).await?;
+
+    Ok(annotation)
 }
 
 #[command]
-async fn get_user_settings(db: State<'_, Database>) -> Result<UserSettings, String> {
-    db.get_settings()
-        .await
-        .map_err(|e| format!("Failed to get settings: {}", e))
+async fn get_annotations(
+    document_id: String,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<Vec<Annotation>> {
+    let mut annotations = db.get_annotations(&document_id).await?;
+    if annotations.is_empty() {
+        return Ok(annotations);
+    }
+
+    // Re-attach anchors in case the content shifted since they were made
+    let document = load_document_by_id(&document_id, &cache, &db).await?;
+    for annotation in &mut annotations {
+        match annotation.anchor.resolve(&document.content, &document.chapters) {
+            Some(anchor) if anchor == annotation.anchor => {}
+            Some(anchor) => {
+                db.update_annotation_anchor(&annotation.id, &anchor).await?;
+                annotation.anchor = anchor;
+            }
+            None => annotation.orphaned = true,
+        }
+    }
+
+    Ok(annotations)
+}
+
+#[command]
+async fn update_annotation(
+    annotation_id: String,
+    color: HighlightColor,
+    note: Option<String>,
+    db: State<'_, Database>,
+) -> Result<()> {
+    let updated = db.update_annotation(&annotation_id, color, note.as_deref()).await?;
+
+    if !updated {
+        return Err(Error::NotFound("Annotation"));
+    }
+    Ok(())
+}
+
+#[command]
+async fn delete_annotation(annotation_id: String, db: State<'_, Database>) -> Result<()> {
+    db.delete_annotation(&annotation_id).await
+}
+
+#[command]
+async fn get_bookmarks(document_id: String, db: State<'_, Database>) -> Result<Vec<Bookmark>> {
+    db.get_bookmarks(&document_id).await
+}
+
+#[command]
+async fn add_bookmark(
+    document_id: String,
+    position: usize,
+    name: Option<String>,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<Bookmark> {
+    let document = load_document_by_id(&document_id, &cache, &db).await?;
+
+    let mut position = position.min(document.content.len());
+    while !document.content.is_char_boundary(position) {
+        position -= 1;
+    }
+
+    let chapter = chapter_at(&document.chapters, position);
+    let snippet = document.content[position..]
+        .split_whitespace()
+        .take(BOOKMARK_SNIPPET_WORDS)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let name = name
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| chapter.map(|c| c.title.clone()))
+        .unwrap_or_else(|| "Bookmark".to_string());
+
+    let bookmark = Bookmark {
+        id: Uuid::new_v4().to_string(),
+        document_id,
+        name,
+        position,
+        chapter_id: chapter.map(|c| c.id.clone()),
+        chapter_title: chapter.map(|c| c.title.clone()),
+        snippet,
+        created_at: Utc::now(),
+    };
+
+    db.save_bookmark(&bookmark).await?;
+
+    Ok(bookmark)
+}
+
+#[command]
+async fn rename_bookmark(bookmark_id: String, name: String, db: State<'_, Database>) -> Result<()> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::InvalidInput("Bookmark name cannot be empty".to_string()));
+    }
+
+    let renamed = db.rename_bookmark(&bookmark_id, name).await?;
+
+    if !renamed {
+        return Err(Error::NotFound("Bookmark"));
+    }
+    Ok(())
+}
+
+#[command]
+async fn delete_bookmark(bookmark_id: String, db: State<'_, Database>) -> Result<()> {
+    if !db.delete_bookmark(&bookmark_id).await? {
+        return Err(Error::NotFound("Bookmark"));
+    }
+    Ok(())
+}
+
+#[command]
+async fn create_group(kind: GroupKind, name: String, db: State<'_, Database>) -> Result<Group> {
+    let group = Group {
+        id: Uuid::new_v4().to_string(),
+        kind,
+        name: group_name(kind, &name)?.to_string(),
+        document_count: 0,
+        created_at: Utc::now(),
+    };
+
+    db.save_group(&group).await?;
+
+    Ok(group)
+}
+
+#[command]
+async fn get_groups(kind: GroupKind, db: State<'_, Database>) -> Result<Vec<Group>> {
+    db.get_groups(kind).await
+}
+
+/// The collections or tags a document is in.
+#[command]
+async fn get_document_groups(kind: GroupKind, document_id: String, db: State<'_, Database>) -> Result<Vec<Group>> {
+    db.get_document_groups(kind, &document_id).await
+}
+
+#[command]
+async fn rename_group(kind: GroupKind, group_id: String, name: String, db: State<'_, Database>) -> Result<()> {
+    let renamed = db.rename_group(kind, &group_id, group_name(kind, &name)?).await?;
+
+    if !renamed {
+        return Err(group_not_found(kind));
+    }
+    Ok(())
+}
+
+#[command]
+async fn delete_group(kind: GroupKind, group_id: String, db: State<'_, Database>) -> Result<()> {
+    if !db.delete_group(kind, &group_id).await? {
+        return Err(group_not_found(kind));
+    }
+    Ok(())
+}
+
+#[command]
+async fn add_to_group(
+    kind: GroupKind,
+    group_id: String,
+    document_ids: Vec<String>,
+    db: State<'_, Database>,
+) -> Result<()> {
+    if !db.add_to_group(kind, &group_id, &document_ids).await? {
+        return Err(group_not_found(kind));
+    }
+    Ok(())
+}
+
+#[command]
+async fn remove_from_group(
+    kind: GroupKind,
+    group_id: String,
+    document_ids: Vec<String>,
+    db: State<'_, Database>,
+) -> Result<()> {
+    db.remove_from_group(kind, &group_id, &document_ids).await
+}
+
+fn group_name(kind: GroupKind, name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::InvalidInput(format!("A {} needs a name", kind.as_str())));
+    }
+    Ok(name)
+}
+
+fn group_not_found(kind: GroupKind) -> Error {
+    match kind {
+        GroupKind::Collection => Error::NotFound("Collection"),
+        GroupKind::Tag => Error::NotFound("Tag"),
+    }
+}
+
+/// Saves a smart shelf. `filter` is a `ShelfFilter` as JSON, and is rejected
+/// with `invalid_input` if it doesn't describe a valid query.
+#[command]
+async fn create_shelf(name: String, filter: serde_json::Value, db: State<'_, Database>) -> Result<SmartShelf> {
+    let mut shelf = SmartShelf {
+        id: Uuid::new_v4().to_string(),
+        name: shelf_name(&name)?.to_string(),
+        filter: ShelfFilter::from_json(filter)?,
+        document_count: 0,
+        created_at: Utc::now(),
+    };
+
+    db.save_shelf(&shelf).await?;
+    shelf.document_count = db.get_shelf(&shelf.id).await?.map_or(0, |saved| saved.document_count);
+
+    Ok(shelf)
+}
+
+#[command]
+async fn update_shelf(
+    shelf_id: String,
+    name: String,
+    filter: serde_json::Value,
+    db: State<'_, Database>,
+) -> Result<SmartShelf> {
+    let mut shelf = db.get_shelf(&shelf_id).await?.ok_or(Error::NotFound("Shelf"))?;
+    shelf.name = shelf_name(&name)?.to_string();
+    shelf.filter = ShelfFilter::from_json(filter)?;
+
+    db.save_shelf(&shelf).await?;
+    db.get_shelf(&shelf_id).await?.ok_or(Error::NotFound("Shelf"))
+}
+
+#[command]
+async fn get_shelves(db: State<'_, Database>) -> Result<Vec<SmartShelf>> {
+    db.get_shelves().await
+}
+
+#[command]
+async fn delete_shelf(shelf_id: String, db: State<'_, Database>) -> Result<()> {
+    if !db.delete_shelf(&shelf_id).await? {
+        return Err(Error::NotFound("Shelf"));
+    }
+    Ok(())
+}
+
+fn shelf_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(Error::InvalidInput("A shelf needs a name".to_string()));
+    }
+    Ok(name)
+}
+
+/// Returns where to navigate for a bookmark, as a text position and a
+/// percentage of the book for the paginated reader.
+#[command]
+async fn jump_to_bookmark(
+    bookmark_id: String,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<ReadingProgress> {
+    let bookmark = db
+        .get_bookmark(&bookmark_id)
+        .await?
+        .ok_or(Error::NotFound("Bookmark"))?;
+
+    let document = load_document_by_id(&bookmark.document_id, &cache, &db).await?;
+    let percentage = if document.content.is_empty() {
+        0.0
+    } else {
+        bookmark.position as f32 / document.content.len() as f32 * 100.0
+    };
+
+    Ok(ReadingProgress {
+        document_id: bookmark.document_id,
+        position: bookmark.position,
+        percentage,
+    })
+}
+
+/// Returns where to navigate for a printed page number such as "xiv" or
+/// "A-7", like `jump_to_bookmark`.
+#[command]
+async fn go_to_page_label(
+    document_id: String,
+    label: String,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<ReadingProgress> {
+    let document = load_document_by_id(&document_id, &cache, &db).await?;
+    let page = page_labelled(&document.pages, &label).ok_or(Error::NotFound("Page"))?;
+
+    let percentage = if document.content.is_empty() {
+        page.index as f32 / document.pages.len() as f32 * 100.0
+    } else {
+        page.start_position as f32 / document.content.len() as f32 * 100.0
+    };
+
+    Ok(ReadingProgress {
+        document_id,
+        position: page.start_position,
+        percentage,
+    })
+}
+
+#[command]
+async fn save_user_settings(
+    settings: UserSettings,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<()> {
+    let previous = db.get_settings().await?;
+
+    db.save_settings(&settings).await?;
+
+    // Chapters found in text files under the old heuristics are stale
+    if chapter_detection(&previous) != chapter_detection(&settings) {
+        let documents = db.get_all_documents().await?;
+
+        for document in documents.iter().filter(|doc| doc.file_type == "txt") {
+            cache.clear(&document.id);
+            db.clear_document_cache(&document.id).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[command]
+async fn get_user_settings(db: State<'_, Database>) -> Result<UserSettings> {
+    db.get_settings().await
 }
 
 #[command]
 async fn search_in_document(
     document_id: String,
     query: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
     db: State<'_, Database>,
-) -> Result<Vec<(usize, String)>, String> {
-    // Get document from database to get file path
-    let documents = db.get_all_documents().await
-        .map_err(|e| format!("Failed to get documents: {}", e))?;
-    
-    let stored_doc = documents.iter()
-        .find(|doc| doc.id == document_id)
-        .ok_or("Document not found")?;
-    
-    // Load content from file - use direct parsing for search to avoid circular dependency
-    let path = PathBuf::from(&stored_doc.file_path);
-    let extension = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or("Invalid file extension")?;
-
-    let document = match extension.to_lowercase().as_str() {
-        "epub" => epub_parser::parse_epub(&path).await,
-        "pdf" => pdf_parser::parse_pdf(&path).await,
-        "txt" => txt_parser::parse_txt(&path).await,
-        _ => Err(format!("Unsupported file format: {}", extension)),
-    }.map_err(|e| format!("Failed to parse document: {}", e))?;
-    
-    let content = document.content;
-    
-    // Search in content
-    let mut results = Vec::new();
-    let lines: Vec<&str> = content.lines().collect();
-    
-    for (line_num, line) in lines.iter().enumerate() {
-        if line.to_lowercase().contains(&query.to_lowercase()) {
-            results.push((line_num, line.to_string()));
+    cache: State<'_, DocumentCache>,
+) -> Result<DocumentSearchResults> {
+    let document = load_document_by_id(&document_id, &cache, &db).await?;
+
+    Ok(search::search_document(
+        &document.content,
+        &document.chapters,
+        &document.pages,
+        &query,
+        offset.unwrap_or(0),
+        limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+    ))
+}
+
+#[command]
+async fn search_library(
+    query: String,
+    document_ids: Option<Vec<String>>,
+    limit: Option<usize>,
+    db: State<'_, Database>,
+) -> Result<Vec<LibrarySearchHit>> {
+    let Some(match_query) = search::match_query(&query) else {
+        return Ok(Vec::new());
+    };
+
+    db.search_text(
+        &match_query,
+        document_ids.as_deref(),
+        limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+    )
+    .await
+}
+
+/// Indexes library documents imported before search existed, or whose file
+/// changed since. Returns how many were indexed.
+#[command]
+async fn index_library(
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<usize> {
+    let documents = db.get_unindexed_documents().await?;
+
+    let mut indexed = 0;
+    for (document_id, file_path) in documents {
+        let result = match load_document(&file_path, &cache, &db).await {
+            Ok(document) => index_document_text(&document, &db).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => indexed += 1,
+            Err(e) => println!("Failed to index document {}: {}", document_id, e),
         }
     }
-    
-    Ok(results)
+
+    Ok(indexed)
+}
+
+async fn index_document_text(document: &Document, db: &Database) -> Result<()> {
+    let up_to_date = db.is_text_indexed(&document.id, &document.content_hash).await?;
+    if up_to_date {
+        return Ok(());
+    }
+
+    let passages = search::passages(&document.content, &document.chapters, &document.pages);
+    db.index_document_text(&document.id, &document.content_hash, &passages).await
 }
 
+/// The metadata the user has edited for a document, and its tags.
 #[command]
-async fn delete_document(document_id: String, db: State<'_, Database>) -> Result<(), String> {
-    db.delete_document(&document_id)
-        .await
-        .map_err(|e| format!("Failed to delete document: {}", e))
+async fn get_document_metadata(document_id: String, db: State<'_, Database>) -> Result<DocumentMetadata> {
+    db.get_document(&document_id)
+        .await?
+        .ok_or(Error::NotFound("Document"))?;
+
+    let tags = db.get_document_groups(GroupKind::Tag, &document_id).await?;
+
+    Ok(DocumentMetadata {
+        overrides: db.get_document_metadata(&document_id).await?,
+        tags: Some(tags.into_iter().map(|tag| tag.name).collect()),
+    })
+}
+
+/// Replaces the metadata the user has edited for a document. Blank fields go
+/// back to what the book itself says; the parsed values are never changed,
+/// so the edits survive re-importing the file. Returns the document as the
+/// library now shows it.
+#[command]
+async fn update_document_metadata(
+    document_id: String,
+    metadata: DocumentMetadata,
+    db: State<'_, Database>,
+    cache: State<'_, DocumentCache>,
+) -> Result<StoredDocument> {
+    let overrides = metadata.overrides.validated()?;
+
+    if !db.set_document_metadata(&document_id, &overrides).await? {
+        return Err(Error::NotFound("Document"));
+    }
+
+    if let Some(tags) = metadata.tags {
+        let names = tags
+            .iter()
+            .map(|tag| group_name(GroupKind::Tag, tag).map(str::to_string))
+            .collect::<Result<Vec<_>>>()?;
+        db.set_document_tags(&document_id, &names).await?;
+    }
+
+    // The next open parses again and picks the edits up
+    cache.clear(&document_id);
+
+    db.get_document(&document_id)
+        .await?
+        .ok_or(Error::NotFound("Document"))
+}
+
+#[command]
+async fn delete_document(document_id: String, db: State<'_, Database>) -> Result<()> {
+    db.delete_document(&document_id).await
 }
 
 #[command]
@@ -199,42 +1057,296 @@ async fn get_document_content(
     file_path: String, 
     cache: State<'_, DocumentCache>,
     db: State<'_, Database>
-) -> Result<String, String> {
+) -> Result<String> {
+    let document = load_document(&file_path, &cache, &db).await?;
+    Ok(document.content)
+}
+
+#[command]
+async fn get_document_blocks(
+    file_path: String,
+    cache: State<'_, DocumentCache>,
+    db: State<'_, Database>,
+) -> Result<Vec<Block>> {
+    let document = load_document(&file_path, &cache, &db).await?;
+    Ok(document.blocks)
+}
+
+#[command]
+async fn get_document_appendices(
+    file_path: String,
+    cache: State<'_, DocumentCache>,
+    db: State<'_, Database>,
+) -> Result<Vec<Appendix>> {
+    let document = load_document(&file_path, &cache, &db).await?;
+    Ok(document.appendices)
+}
+
+#[command]
+async fn get_document_resource(file_path: String, resource_path: String) -> Result<String> {
+    registry::read_resource(&PathBuf::from(&file_path), &resource_path).await
+}
+
+/// Stops an import started by `open_document`. Its `open_document` call then
+/// fails with `Error::Cancelled`.
+#[command]
+async fn cancel_import(job_id: String, jobs: State<'_, ImportJobs>) -> Result<()> {
+    if jobs.cancel(&job_id) {
+        Ok(())
+    } else {
+        Err(Error::NotFound("Import"))
+    }
+}
+
+/// Imports files and folders in the background, returning the job at once.
+/// Each file is reported in an `import-job-progress` event, and the job in
+/// an `import-job-finished` event when it's done.
+#[command]
+async fn start_import_job(
+    paths: Vec<String>,
+    app: AppHandle,
+    db: State<'_, Database>,
+) -> Result<database::ImportJob> {
+    if paths.is_empty() {
+        return Err(Error::InvalidInput("There is nothing to import".to_string()));
+    }
+
+    let files = tokio::task::spawn_blocking(move || import::expand_paths(&paths)).await?;
+    let job_id = Uuid::new_v4().to_string();
+    db.create_import_job(&job_id, &files).await?;
+    let job = db.get_import_job(&job_id).await?.ok_or(Error::NotFound("Import job"))?;
+
+    spawn_import_job(app, job_id);
+    Ok(job)
+}
+
+/// Carries on with an interrupted or cancelled import job from where it stopped.
+/// A job with no files left is returned as it is, marked finished.
+#[command]
+async fn resume_import_job(
+    job_id: String,
+    app: AppHandle,
+    db: State<'_, Database>,
+    jobs: State<'_, ImportJobs>,
+) -> Result<database::ImportJob> {
+    let mut job = db.get_import_job(&job_id).await?.ok_or(Error::NotFound("Import job"))?;
+
+    if jobs.is_running(&job_id) {
+        return Ok(job);
+    }
+    if job.pending == 0 {
+        // Stopped after its last file, before it could be marked finished
+        if job.status != ImportJobStatus::Finished {
+            db.set_import_job_status(&job_id, ImportJobStatus::Finished).await?;
+            job.status = ImportJobStatus::Finished;
+        }
+        return Ok(job);
+    }
+
+    job.status = ImportJobStatus::Running;
+    spawn_import_job(app, job_id);
+    Ok(job)
+}
+
+/// Stops an import job. Its remaining files stay pending, so it can still
+/// be resumed.
+#[command]
+async fn cancel_import_job(job_id: String, db: State<'_, Database>, jobs: State<'_, ImportJobs>) -> Result<()> {
+    if jobs.cancel(&job_id) {
+        return Ok(());
+    }
+
+    // An interrupted job isn't running to notice, so it's marked here
+    let job = db.get_import_job(&job_id).await?.ok_or(Error::NotFound("Import job"))?;
+    if job.status == ImportJobStatus::Running {
+        db.set_import_job_status(&job_id, ImportJobStatus::Cancelled).await?;
+    }
+
+    Ok(())
+}
+
+#[command]
+async fn get_import_jobs(db: State<'_, Database>, jobs: State<'_, ImportJobs>) -> Result<Vec<database::ImportJob>> {
+    let mut import_jobs = db.get_import_jobs().await?;
+
+    for job in &mut import_jobs {
+        if job.status == ImportJobStatus::Running && !jobs.is_running(&job.id) {
+            job.status = ImportJobStatus::Interrupted;
+        }
+    }
+
+    Ok(import_jobs)
+}
+
+#[command]
+async fn get_import_job_failures(job_id: String, db: State<'_, Database>) -> Result<Vec<ImportFailure>> {
+    let failures = db.get_import_job_failures(&job_id).await?;
+
+    Ok(failures
+        .into_iter()
+        .map(|(file_path, kind, message)| ImportFailure { file_path, kind, message })
+        .collect())
+}
+
+fn spawn_import_job(app: AppHandle, job_id: String) {
+    tauri::async_runtime::spawn(async move {
+        let (db, cache, jobs) = (
+            app.state::<Database>(),
+            app.state::<DocumentCache>(),
+            app.state::<ImportJobs>(),
+        );
+        // Resumed twice
+        let Some(job) = jobs.inner().try_start(&job_id) else {
+            return;
+        };
+
+        let progress = Progress::new(|_: &str, _: u8| {}, job.cancelled.clone());
+        let on_file = |event: ImportJobProgress| {
+            if let Err(e) = app.emit("import-job-progress", event) {
+                println!("Failed to send import job progress: {}", e);
+            }
+        };
+
+        let finished = match import::run_job(&job_id, &db, &cache, &progress, on_file).await {
+            Ok(finished) => Some(finished),
+            Err(e) => {
+                println!("Import job {} stopped: {}", job_id, e);
+                // Still saved as running, so it's offered for resuming
+                db.get_import_job(&job_id)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|job| database::ImportJob {
+                        status: ImportJobStatus::Interrupted,
+                        ..job
+                    })
+            }
+        };
+
+        if let Some(finished) = finished {
+            if let Err(e) = app.emit("import-job-finished", finished) {
+                println!("Failed to send import job result: {}", e);
+            }
+        }
+    });
+}
+
+async fn load_document_by_id(
+    document_id: &str,
+    cache: &DocumentCache,
+    db: &Database,
+) -> Result<Document> {
+    if let Some(cached_doc) = cache.get(document_id) {
+        return Ok(cached_doc);
+    }
+
+    let stored_doc = db
+        .get_document(document_id)
+        .await?
+        .ok_or(Error::NotFound("Document"))?;
+
+    load_document(&stored_doc.file_path, cache, db).await
+}
+
+/// Parse options for a file, from the user's settings and what the library
+/// remembers about the file.
+async fn parse_options(db: &Database, stored_doc: Option<&StoredDocument>) -> ParseOptions {
+    let settings = db.get_settings().await.unwrap_or_default();
+
+    ParseOptions {
+        encoding: stored_doc.and_then(|doc| doc.encoding_override.clone()),
+        chapter_detection: chapter_detection(&settings),
+    }
+}
+
+fn chapter_detection(settings: &UserSettings) -> ChapterDetection {
+    ChapterDetection {
+        numbered: settings.detect_numbered_chapters,
+        parts: settings.detect_part_headings,
+        all_caps: settings.detect_caps_headings,
+        markdown: settings.detect_markdown_headings,
+    }
+}
+
+/// Returns the parsed document for a library file, from the cache when possible.
+async fn load_document(
+    file_path: &str,
+    cache: &DocumentCache,
+    db: &Database,
+) -> Result<Document> {
     // Try to find document ID from file path
-    let documents = db.get_all_documents().await
-        .map_err(|e| format!("Failed to get documents: {}", e))?;
+    let documents = db.get_all_documents().await?;
     
-    let document_id = documents.iter()
-        .find(|doc| doc.file_path == file_path)
-        .map(|doc| doc.id.clone());
+    let stored_doc = documents.iter()
+        .find(|doc| doc.file_path == file_path);
+    let document_id = stored_doc.map(|doc| doc.id.clone());
     
     // Try cache first if we have a document ID
     if let Some(doc_id) = &document_id {
         if let Some(cached_doc) = cache.get(doc_id) {
             println!("Using cached content for document {}", doc_id);
-            return Ok(cached_doc.content);
+            return Ok(cached_doc);
         }
     }
 
-    let path = PathBuf::from(&file_path);
-    let extension = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or("Invalid file extension")?;
-
-    let document = match extension.to_lowercase().as_str() {
-        "epub" => epub_parser::parse_epub(&path).await,
-        "pdf" => pdf_parser::parse_pdf(&path).await,
-        "txt" => txt_parser::parse_txt(&path).await,
-        _ => Err(format!("Unsupported file format: {}", extension)),
-    }?;
+    let options = parse_options(db, stored_doc).await;
+    let mut document = match registry::parse(&PathBuf::from(file_path), &options, &Progress::default()).await {
+        Err(Error::FileNotFound(path)) => {
+            // Flag it so the library can offer to find it
+            if let Some(doc_id) = &document_id {
+                db.set_file_missing(doc_id, true).await?;
+            }
+            return Err(Error::FileNotFound(path));
+        }
+        result => result?,
+    };
 
     // Cache the document if we have an ID
     if let Some(doc_id) = document_id {
+        if db.take_position_repair(&doc_id).await? {
+            reanchor_stripped_positions(&doc_id, &document, stored_doc, db).await?;
+        }
+        db.get_document_metadata(&doc_id).await?.apply(&mut document);
         cache.set(doc_id, document.clone());
     }
 
-    Ok(document.content)
+    Ok(document)
+}
+
+/// Moves a reading position and bookmarks saved before the Project Gutenberg
+/// header was stripped onto the stripped text. Bookmarks are found again by
+/// their snippet; the position, a page of the reader's, loses the header's words.
+async fn reanchor_stripped_positions(
+    document_id: &str,
+    document: &Document,
+    stored_doc: Option<&StoredDocument>,
+    db: &Database,
+) -> Result<()> {
+    let Some(header) = document.appendices.iter().find(|a| a.title == gutenberg::HEADER_TITLE) else {
+        return Ok(());
+    };
+
+    if let Some(stored_doc) = stored_doc {
+        // The reader pages the text by the user's words per page
+        let words_per_page = db.get_settings().await?.words_per_page.max(1) as usize;
+        let first_word = stored_doc.current_position.max(0) as usize * words_per_page;
+        let first_word = first_word.saturating_sub(header.content.split_whitespace().count());
+        db.set_current_position(document_id, (first_word / words_per_page) as i32).await?;
+    }
+
+    // Offsets saved before counted everything up to where the book starts
+    for bookmark in db.get_bookmarks(document_id).await? {
+        let expected = bookmark.position.saturating_sub(header.source.end);
+        let position = annotations::find_ignoring_whitespace(&document.content, &bookmark.snippet)
+            .into_iter()
+            .map(|(start, _)| start)
+            .min_by_key(|start| start.abs_diff(expected))
+            .unwrap_or_else(|| annotations::char_boundary(&document.content, expected.min(document.content.len())));
+        db.update_bookmark_position(&bookmark.id, position, chapter_at(&document.chapters, position))
+            .await?;
+    }
+
+    Ok(())
 }
 
 #[command]
@@ -242,7 +1354,7 @@ async fn get_chapters(
     document_id: String, 
     db: State<'_, Database>,
     cache: State<'_, DocumentCache>
-) -> Result<Vec<Chapter>, String> {
+) -> Result<Vec<Chapter>> {
     // First, try memory cache for the full document (fastest)
     if let Some(cached_doc) = cache.get(&document_id) {
         println!("Using memory-cached document for chapters: {}", document_id);
@@ -264,26 +1376,17 @@ async fn get_chapters(
     }
     
     // Get document from database to get file path
-    let documents = db.get_all_documents().await
-        .map_err(|e| format!("Failed to get documents: {}", e))?;
+    let documents = db.get_all_documents().await?;
     
     let stored_doc = documents.iter()
         .find(|doc| doc.id == document_id)
-        .ok_or("Document not found")?;
+        .ok_or(Error::NotFound("Document"))?;
     
     let path = PathBuf::from(&stored_doc.file_path);
-    let extension = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or("Invalid file extension")?;
 
     println!("Parsing document from file: {:?}", path);
-    let document = match extension.to_lowercase().as_str() {
-        "epub" => epub_parser::parse_epub(&path).await,
-        "pdf" => pdf_parser::parse_pdf(&path).await,
-        "txt" => txt_parser::parse_txt(&path).await,
-        _ => Err(format!("Unsupported file format: {}", extension)),
-    }?;
+    let options = parse_options(&db, Some(stored_doc)).await;
+    let document = registry::parse(&path, &options, &Progress::default()).await?;
 
     // Cache the chapters for future use
     if !document.chapters.is_empty() {
@@ -323,6 +1426,9 @@ async fn main() {
     let library = Library::new();
     let document_cache = DocumentCache::new();
 
+    let (folder_changes, folder_change_receiver) = mpsc::unbounded_channel();
+    let folder_watcher = FolderWatcher::new(folder_changes).expect("Failed to start library folder watcher");
+
     tauri::Builder::default()
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_fs::init())
@@ -333,18 +1439,63 @@ async fn main() {
         .manage(database)
         .manage(library)
         .manage(document_cache)
+        .manage(ImportJobs::default())
+        .manage(folder_watcher)
         .invoke_handler(tauri::generate_handler![
             open_document,
+            cancel_import,
+            start_import_job,
+            resume_import_job,
+            cancel_import_job,
+            get_import_jobs,
+            get_import_job_failures,
+            reopen_with_encoding,
             get_library,
+            check_library_health,
+            relocate_missing_documents,
+            add_library_folder,
+            remove_library_folder,
+            get_library_folders,
+            scan_library_folders,
             update_reading_progress,
+            add_annotation,
+            get_annotations,
+            update_annotation,
+            delete_annotation,
+            get_bookmarks,
+            add_bookmark,
+            rename_bookmark,
+            delete_bookmark,
+            jump_to_bookmark,
+            go_to_page_label,
+            get_document_metadata,
+            update_document_metadata,
+            create_group,
+            get_groups,
+            get_document_groups,
+            rename_group,
+            delete_group,
+            add_to_group,
+            remove_from_group,
+            create_shelf,
+            update_shelf,
+            get_shelves,
+            delete_shelf,
             save_user_settings,
             get_user_settings,
             search_in_document,
+            search_library,
+            index_library,
             delete_document,
             get_document_content,
+            get_document_blocks,
+            get_document_appendices,
+            get_document_resource,
             get_chapters
         ])
         .setup(|app| {
+            tauri::async_runtime::spawn(watch_library_folders(app.handle().clone(), folder_change_receiver));
+
             // Ensure API is properly injected
             println!("Tauri app setup completed");
             Ok(())
@@ -0,0 +1,593 @@
+use crate::error::{Error, Result};
+use crate::{Chapter, Document, DocumentKind, Page, ReadingDirection};
+use super::progress::Progress;
+use super::{content_hash, DocumentParser, FileSignature, ParseOptions};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp", "avif"];
+
+// Larger than any real page scan; an entry decompressing past this is refused
+// rather than read into memory
+const MAX_PAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Container formats used by comic archives. Detected from the file's first
+/// bytes, because plenty of `.cbr` files are really zips and vice versa.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    Zip,
+    Rar,
+    SevenZip,
+}
+
+pub struct ComicParser;
+
+impl DocumentParser for ComicParser {
+    fn detect(signature: &FileSignature) -> bool {
+        match ArchiveFormat::from_magic(&signature.head) {
+            Some(ArchiveFormat::Zip) => signature.zip_entries.iter().any(|name| is_page_image(name)),
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn parse(file_path: &PathBuf, _options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        parse_comic(file_path, progress)
+    }
+
+    fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+        read_resource(file_path, resource_path)
+    }
+}
+
+/// Parses a CBZ, CBR or CB7 comic. Only the archive's listing, ComicInfo.xml
+/// and the cover are read here; pages are fetched one at a time through
+/// `read_resource`.
+pub fn parse_comic(file_path: &PathBuf, progress: &Progress) -> Result<Document> {
+    progress.update("Opening", 0.0)?;
+    let format = archive_format(file_path)?;
+    let content_hash = content_hash(file_path)?;
+    progress.update("Listing pages", 0.0)?;
+    let entries = list_entries(file_path, format)?;
+
+    let mut images: Vec<&String> = entries.iter().filter(|name| is_page_image(name)).collect();
+    images.sort_by(|a, b| natural_cmp(a, b));
+    if images.is_empty() {
+        return Err(Error::corrupt("No page images found in the comic archive"));
+    }
+
+    let info = entries
+        .iter()
+        .find(|name| file_name(name).eq_ignore_ascii_case("ComicInfo.xml"))
+        .and_then(|name| read_entry(file_path, format, name).ok())
+        .map(|xml| ComicInfo::parse(&String::from_utf8_lossy(&xml)))
+        .unwrap_or_default();
+
+    // One line per page, so positions, bookmarks and chapters work as they do
+    // for text
+    let mut content = String::new();
+    let pages: Vec<Page> = images
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let label = (index + 1).to_string();
+            let start_position = content.len();
+            content.push_str(&format!("Page {}\n", label));
+            Page {
+                index,
+                label,
+                start_position,
+                end_position: content.len(),
+                image: Some(name.to_string()),
+            }
+        })
+        .collect();
+
+    let chapters = bookmark_chapters(&info.bookmarks, &pages, content.len());
+    progress.update("Reading cover", 0.0)?;
+    let cover_image = read_resource(file_path, images[0]).ok();
+
+    let title = info
+        .title
+        .clone()
+        .or_else(|| {
+            let series = info.series.as_ref()?;
+            Some(match &info.number {
+                Some(number) => format!("{} #{}", series, number),
+                None => series.clone(),
+            })
+        })
+        .unwrap_or_else(|| {
+            file_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or("Unknown Title")
+                .to_string()
+        });
+
+    let file_type = match format {
+        ArchiveFormat::Zip => "cbz",
+        ArchiveFormat::Rar => "cbr",
+        ArchiveFormat::SevenZip => "cb7",
+    };
+
+    Ok(Document {
+        id: content_hash.clone(),
+        title,
+        author: info.writer,
+        file_path: file_path.clone(),
+        file_type: file_type.to_string(),
+        content_hash,
+        kind: DocumentKind::Comic,
+        reading_direction: info.reading_direction,
+        encoding: None,
+        asin: None,
+        series: info.series,
+        series_index: info.number.as_deref().and_then(|number| number.trim().parse().ok()),
+        genres: info.genres,
+        description: info.summary,
+        content,
+        blocks: Vec::new(),
+        current_position: 0,
+        total_pages: pages.len(),
+        chapters,
+        pages,
+        appendices: Vec::new(),
+        cover_image,
+    })
+}
+
+/// Reads one page image (an archive entry named by `Page::image`) as a data
+/// URL, decompressing only that entry.
+pub fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+    let format = archive_format(file_path)?;
+    let data = read_entry(file_path, format, resource_path)?;
+
+    let extension = resource_path.rsplit('.').next().unwrap_or_default().to_lowercase();
+    let mime_type = match extension.as_str() {
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "avif" => "image/avif",
+        _ => "image/jpeg",
+    };
+    Ok(format!("data:{};base64,{}", mime_type, BASE64.encode(data)))
+}
+
+impl ArchiveFormat {
+    fn from_magic(head: &[u8]) -> Option<Self> {
+        match head {
+            [b'P', b'K', 3, 4, ..] => Some(ArchiveFormat::Zip),
+            [b'R', b'a', b'r', b'!', 0x1A, 0x07, ..] => Some(ArchiveFormat::Rar),
+            [b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C, ..] => Some(ArchiveFormat::SevenZip),
+            _ => None,
+        }
+    }
+}
+
+fn archive_format(file_path: &Path) -> Result<ArchiveFormat> {
+    let mut magic = [0u8; 6];
+    let read = File::open(file_path)
+        .and_then(|mut file| file.read(&mut magic))
+        .map_err(|e| Error::io(file_path, e))?;
+
+    ArchiveFormat::from_magic(&magic[..read]).ok_or(Error::UnsupportedFormat)
+}
+
+/// Names of the files in the archive, with `/` separators.
+fn list_entries(file_path: &Path, format: ArchiveFormat) -> Result<Vec<String>> {
+    match format {
+        ArchiveFormat::Zip => {
+            let archive = open_zip(file_path)?;
+            Ok(archive
+                .file_names()
+                .filter(|name| !name.ends_with('/'))
+                .map(str::to_string)
+                .collect())
+        }
+        ArchiveFormat::Rar => {
+            let archive = unrar::Archive::new(file_path)
+                .open_for_listing()
+                .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+            let mut names = Vec::new();
+            for header in archive {
+                let header = header.map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+                if header.is_file() {
+                    names.push(entry_name(&header.filename));
+                }
+            }
+            Ok(names)
+        }
+        ArchiveFormat::SevenZip => {
+            let archive = sevenz_rust::Archive::open(file_path)
+                .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+            Ok(archive
+                .files
+                .iter()
+                .filter(|entry| entry.has_stream() && !entry.is_directory())
+                .map(|entry| entry.name().replace('\\', "/"))
+                .collect())
+        }
+    }
+}
+
+/// The bytes of a single entry. RAR and 7z entries are found by decoding
+/// forwards through the archive; nothing but the entry itself is kept.
+fn read_entry(file_path: &Path, format: ArchiveFormat, name: &str) -> Result<Vec<u8>> {
+    let not_found = || Error::NotFound("Page image");
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = open_zip(file_path)?;
+            let entry = archive.by_name(name).map_err(|_| not_found())?;
+            // The declared size is the archive's claim, so it isn't used to allocate
+            read_limited(entry, MAX_PAGE_BYTES)
+                .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?
+                .ok_or_else(|| too_large(name))
+        }
+        ArchiveFormat::Rar => {
+            let mut archive = unrar::Archive::new(file_path)
+                .open_for_processing()
+                .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+            loop {
+                let header = archive
+                    .read_header()
+                    .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?
+                    .ok_or_else(not_found)?;
+                archive = if header.entry().is_file() && entry_name(&header.entry().filename) == name {
+                    // unrar can only read whole entries, but never writes past
+                    // the size in the header
+                    if header.entry().unpacked_size > MAX_PAGE_BYTES {
+                        return Err(too_large(name));
+                    }
+                    let (data, _) = header.read().map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+                    if data.len() as u64 > MAX_PAGE_BYTES {
+                        return Err(too_large(name));
+                    }
+                    return Ok(data);
+                } else {
+                    header.skip().map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?
+                };
+            }
+        }
+        ArchiveFormat::SevenZip => {
+            let mut file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+            let archive = sevenz_rust::Archive::open(file_path)
+                .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+            let index = archive
+                .files
+                .iter()
+                .position(|entry| entry.has_stream() && entry.name().replace('\\', "/") == name)
+                .ok_or_else(not_found)?;
+            let folder = archive.stream_map.file_folder_index[index].ok_or_else(not_found)?;
+
+            // Only the block holding the entry is decoded, and only up to it
+            let mut data = None;
+            sevenz_rust::BlockDecoder::new(folder, &archive, &[], &mut file)
+                .for_each_entries(&mut |entry, reader| {
+                    if entry.name() != archive.files[index].name() {
+                        std::io::copy(reader, &mut std::io::sink())?;
+                        return Ok(true);
+                    }
+                    data = Some(read_limited(reader, MAX_PAGE_BYTES)?);
+                    Ok(false)
+                })
+                .map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))?;
+            data.ok_or_else(not_found)?.ok_or_else(|| too_large(name))
+        }
+    }
+}
+
+/// All of `reader`, or `None` if it holds more than `limit` bytes. Only
+/// `limit` bytes are ever read.
+fn read_limited(reader: impl Read, limit: u64) -> std::io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut data)?;
+    Ok((data.len() as u64 <= limit).then_some(data))
+}
+
+fn too_large(name: &str) -> Error {
+    Error::corrupt(format!("Page {} is too large to be an image", name))
+}
+
+fn open_zip(file_path: &Path) -> Result<zip::ZipArchive<File>> {
+    let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    zip::ZipArchive::new(file).map_err(|e| Error::corrupt(format!("Failed to read comic archive: {}", e)))
+}
+
+fn entry_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// Images, minus the resource forks and thumbnails archivers leave behind.
+fn is_page_image(name: &str) -> bool {
+    let base = file_name(name);
+    let hidden = base.starts_with('.') || name.split('/').any(|part| part == "__MACOSX");
+    let extension = base.rsplit_once('.').map(|(_, ext)| ext.to_lowercase());
+    !hidden && extension.is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Compares names the way people count, so "page2" sorts before "page10".
+/// Letters compare case-insensitively, with case only breaking ties.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    natural_cmp_ignoring_case(a, b).then_with(|| a.cmp(b))
+}
+
+fn natural_cmp_ignoring_case(mut a: &str, mut b: &str) -> Ordering {
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_len = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_len = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let (a_digits, b_digits) = (&a[..a_len], &b[..b_len]);
+                let (a_value, b_value) = (a_digits.trim_start_matches('0'), b_digits.trim_start_matches('0'));
+                // Longer numbers are bigger; equal lengths compare digit by digit
+                let ordering = a_value
+                    .len()
+                    .cmp(&b_value.len())
+                    .then_with(|| a_value.cmp(b_value))
+                    .then_with(|| a_digits.len().cmp(&b_digits.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[a_len..];
+                b = &b[b_len..];
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+        }
+    }
+}
+
+/// The fields we use from a ComicRack-style ComicInfo.xml.
+#[derive(Default)]
+struct ComicInfo {
+    title: Option<String>,
+    series: Option<String>,
+    number: Option<String>, // Issue number, kept as text ("12", "1.5", "Annual 1")
+    writer: Option<String>,
+    summary: Option<String>,
+    genres: Vec<String>,
+    reading_direction: ReadingDirection,
+    bookmarks: Vec<(usize, String)>, // Page index and bookmark title
+}
+
+impl ComicInfo {
+    fn parse(xml: &str) -> Self {
+        let Ok(doc) = roxmltree::Document::parse(xml.trim_start_matches('\u{feff}')) else {
+            return Self::default();
+        };
+        let root = doc.root_element();
+        let field = |name: &str| {
+            root.children()
+                .find(|n| n.has_tag_name(name))
+                .and_then(|n| n.text())
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+        };
+
+        let genres = field("Genre")
+            .map(|genre| {
+                genre
+                    .split(',')
+                    .map(|g| g.trim().to_string())
+                    .filter(|g| !g.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let reading_direction = match field("Manga").as_deref() {
+            Some("YesAndRightToLeft") => ReadingDirection::RightToLeft,
+            _ => ReadingDirection::LeftToRight,
+        };
+
+        let bookmarks = root
+            .children()
+            .find(|n| n.has_tag_name("Pages"))
+            .map(|pages| {
+                pages
+                    .children()
+                    .filter(|n| n.has_tag_name("Page"))
+                    .filter_map(|page| {
+                        let index = page.attribute("Image")?.trim().parse().ok()?;
+                        let title = page.attribute("Bookmark")?.trim();
+                        (!title.is_empty()).then(|| (index, title.to_string()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            title: field("Title"),
+            series: field("Series"),
+            number: field("Number"),
+            writer: field("Writer"),
+            summary: field("Summary"),
+            genres,
+            reading_direction,
+            bookmarks,
+        }
+    }
+}
+
+/// Chapters from ComicInfo page bookmarks, each running to the next one.
+fn bookmark_chapters(bookmarks: &[(usize, String)], pages: &[Page], content_len: usize) -> Vec<Chapter> {
+    let mut bookmarks: Vec<&(usize, String)> = bookmarks.iter().filter(|(index, _)| *index < pages.len()).collect();
+    bookmarks.sort_by_key(|(index, _)| *index);
+
+    bookmarks
+        .iter()
+        .enumerate()
+        .map(|(i, (index, title))| Chapter {
+            id: format!("page_{}", index),
+            title: title.clone(),
+            start_position: pages[*index].start_position,
+            end_position: bookmarks
+                .get(i + 1)
+                .map(|(next, _)| pages[*next].start_position)
+                .unwrap_or(content_len),
+            level: 0,
+            parent_id: None,
+            href: pages[*index].image.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    fn page(index: usize) -> Page {
+        Page {
+            index,
+            label: (index + 1).to_string(),
+            start_position: index * 10,
+            end_position: index * 10 + 10,
+            image: Some(format!("{:03}.jpg", index)),
+        }
+    }
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(sorted(&["page10.jpg", "page2.jpg", "page1.jpg"]), ["page1.jpg", "page2.jpg", "page10.jpg"]);
+        assert_eq!(natural_cmp("v2/p9", "v10/p1"), Ordering::Less);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(sorted(&["p010", "p9", "p0009"]), ["p9", "p0009", "p010"]);
+        assert_eq!(natural_cmp("007", "7"), Ordering::Greater);
+        assert_eq!(natural_cmp("007", "007"), Ordering::Equal);
+    }
+
+    #[test]
+    fn case_only_breaks_ties() {
+        assert_eq!(sorted(&["b.jpg", "A.jpg", "a.jpg"]), ["A.jpg", "a.jpg", "b.jpg"]);
+        assert_eq!(natural_cmp("Page2", "page10"), Ordering::Less);
+    }
+
+    #[test]
+    fn oversized_entries_are_not_read_whole() {
+        // Ten thousand zeros deflate to a few bytes
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("001.jpg", options).unwrap();
+        zip.write_all(&[0; 10_000]).unwrap();
+        let mut archive = zip::ZipArchive::new(zip.finish().unwrap()).unwrap();
+
+        let read = |archive: &mut zip::ZipArchive<_>, limit| read_limited(archive.by_name("001.jpg").unwrap(), limit).unwrap();
+        assert_eq!(read(&mut archive, 10_000).map(|data| data.len()), Some(10_000));
+        assert_eq!(read(&mut archive, 9_999), None);
+        assert_eq!(read_limited(&b""[..], 0).unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn archiver_clutter_is_not_a_page() {
+        assert!(is_page_image("chapter 1/001.JPG"));
+        assert!(is_page_image("cover.webp"));
+        assert!(!is_page_image("__MACOSX/chapter 1/._001.jpg"));
+        assert!(!is_page_image("__MACOSX/001.jpg"));
+        assert!(!is_page_image("chapter 1/.thumb.png"));
+        assert!(!is_page_image("ComicInfo.xml"));
+        assert!(!is_page_image("jpg"));
+    }
+
+    #[test]
+    fn comic_info_fields() {
+        let info = ComicInfo::parse(
+            "\u{feff}<?xml version=\"1.0\"?>
+            <ComicInfo>
+                <Series>Akira</Series>
+                <Number>3</Number>
+                <Writer>Katsuhiro Otomo</Writer>
+                <Genre>Manga, Science Fiction, </Genre>
+                <Manga>YesAndRightToLeft</Manga>
+                <Pages>
+                    <Page Image=\"0\" Type=\"FrontCover\" />
+                    <Page Image=\"5\" Bookmark=\" Part Two \" />
+                    <Page Image=\"2\" Bookmark=\"Part One\" />
+                    <Page Image=\"x\" Bookmark=\"Broken\" />
+                </Pages>
+            </ComicInfo>",
+        );
+
+        assert_eq!(info.title, None);
+        assert_eq!(info.series.as_deref(), Some("Akira"));
+        assert_eq!(info.number.as_deref(), Some("3"));
+        assert_eq!(info.writer.as_deref(), Some("Katsuhiro Otomo"));
+        assert_eq!(info.genres, ["Manga", "Science Fiction"]);
+        assert_eq!(info.reading_direction, ReadingDirection::RightToLeft);
+        assert_eq!(info.bookmarks, [(5, "Part Two".to_string()), (2, "Part One".to_string())]);
+
+        // "Yes" alone doesn't say which way to read
+        let info = ComicInfo::parse("<ComicInfo><Manga>Yes</Manga></ComicInfo>");
+        assert_eq!(info.reading_direction, ReadingDirection::LeftToRight);
+        assert!(ComicInfo::parse("not xml").bookmarks.is_empty());
+    }
+
+    #[test]
+    fn bookmarks_become_chapters_in_page_order() {
+        let pages: Vec<Page> = (0..6).map(page).collect();
+        let bookmarks = [(4, "Two".to_string()), (1, "One".to_string()), (9, "Past the end".to_string())];
+
+        let chapters = bookmark_chapters(&bookmarks, &pages, 60);
+        let spans: Vec<(&str, usize, usize)> = chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.start_position, c.end_position))
+            .collect();
+        assert_eq!(spans, [("One", 10, 40), ("Two", 40, 60)]);
+        assert_eq!(chapters[0].href.as_deref(), Some("001.jpg"));
+    }
+
+    #[test]
+    fn cbz_pages_are_in_natural_order() {
+        let path = std::env::temp_dir().join(format!("comic-{}.cbz", uuid::Uuid::new_v4()));
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        for name in ["p10.png", "p2.png", "__MACOSX/._p1.png", "p1.png", "ComicInfo.xml"] {
+            zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+            let data: &[u8] = match name {
+                "ComicInfo.xml" => b"<ComicInfo><Title>Test</Title><Pages><Page Image=\"1\" Bookmark=\"Middle\"/></Pages></ComicInfo>",
+                _ => b"\x89PNG",
+            };
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let document = parse_comic(&path, &Progress::default());
+        std::fs::remove_file(&path).ok();
+        let document = document.unwrap();
+
+        let images: Vec<&str> = document.pages.iter().filter_map(|p| p.image.as_deref()).collect();
+        assert_eq!(images, ["p1.png", "p2.png", "p10.png"]);
+        assert_eq!(document.title, "Test");
+        assert_eq!(document.file_type, "cbz");
+        assert_eq!(document.chapters[0].start_position, document.pages[1].start_position);
+        assert!(document.cover_image.unwrap().starts_with("data:image/png;base64,"));
+    }
+}
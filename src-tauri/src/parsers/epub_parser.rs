@@ -1,47 +1,147 @@
-use crate::{Document, Chapter};
+use crate::error::{Error, Result};
+use crate::{Chapter, Document, DocumentKind, ReadingDirection};
+use super::progress::Progress;
+use super::{content_hash, gutenberg, DocumentParser, FileSignature, ParseOptions};
+use super::xhtml::{normalize_path, BlockKind, ContentBuilder};
 use epub::doc::EpubDoc;
+use percent_encoding::percent_decode_str;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
 use std::path::PathBuf;
-use uuid::Uuid;
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64;
 
-pub async fn parse_epub(file_path: &PathBuf) -> Result<Document, String> {
-    let mut doc = EpubDoc::new(file_path).map_err(|e| format!("Failed to open EPUB: {}", e))?;
+type EpubArchive = EpubDoc<std::io::BufReader<std::fs::File>>;
 
-    let title = doc
-        .mdata("title")
-        .unwrap_or_else(|| "Unknown Title".to_string());
-    let author = doc.mdata("creator");
+/// A table of contents entry as read from nav.xhtml or toc.ncx, before it is
+/// resolved to a position in the extracted text.
+struct TocEntry {
+    label: String,
+    target: Option<(String, Option<String>)>, // (archive path, fragment)
+    children: Vec<TocEntry>,
+}
 
-    // Extract all text content and build chapters
-    let mut content = String::new();
-    let mut chapters = Vec::new();
+pub struct EpubParser;
+
+impl DocumentParser for EpubParser {
+    /// A zip with the EPUB container file, or whose leading `mimetype` entry
+    /// says it's an EPUB. OpenDocument files have a `mimetype` entry too.
+    fn detect(signature: &FileSignature) -> bool {
+        signature.zip_entries.iter().any(|name| name == "META-INF/container.xml")
+            || leading_mimetype(&signature.head) == Some(b"application/epub+zip".as_slice())
+    }
+
+    fn parse(file_path: &PathBuf, _options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        parse_epub(file_path, progress)
+    }
+
+    fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+        read_resource(file_path, resource_path)
+    }
+}
+
+/// The content of the zip's first entry when it's an uncompressed file named
+/// `mimetype`, which is where the EPUB spec puts it.
+fn leading_mimetype(head: &[u8]) -> Option<&[u8]> {
+    let u16_at = |offset: usize| Some(u16::from_le_bytes(head.get(offset..offset + 2)?.try_into().ok()?) as usize);
+    let stored = u16_at(8)? == 0;
+    let size = u32::from_le_bytes(head.get(18..22)?.try_into().ok()?) as usize;
+    let (name_length, extra_length) = (u16_at(26)?, u16_at(28)?);
+
+    if !head.starts_with(b"PK\x03\x04") || !stored || head.get(30..30 + name_length)? != b"mimetype" {
+        return None;
+    }
+    let start = 30 + name_length + extra_length;
+    head.get(start..start + size)
+}
+
+pub fn parse_epub(file_path: &PathBuf, progress: &Progress) -> Result<Document> {
+    progress.update("Opening", 0.0)?;
+    let mut doc = EpubDoc::new(file_path).map_err(|e| Error::corrupt(format!("Failed to open EPUB: {}", e)))?;
+    let content_hash = content_hash(file_path)?;
+
+    let mut title = doc.mdata("title");
+    let mut author = doc.mdata("creator");
+
+    // Convert every spine file into blocks plus a plain-text projection
+    let mut builder = ContentBuilder::new();
+    let mut spine_chapters = Vec::new();
+
+    // Where each spine file, and each element id inside it, starts in `content`
+    let mut file_offsets: HashMap<String, usize> = HashMap::new();
+    let mut anchor_offsets: HashMap<(String, String), usize> = HashMap::new();
 
     // Iterate through spine resources
     let spine = doc.spine.clone();
     for (index, spine_item) in spine.iter().enumerate() {
+        progress.update("Converting chapters", index as f32 / spine.len() as f32)?;
+        let path = match doc.resources.get(&spine_item.idref) {
+            Some((path, _)) => {
+                normalize_path(&percent_decode_str(&path.to_string_lossy()).decode_utf8_lossy())
+            }
+            None => continue,
+        };
+
         if let Some((chapter_content, _)) = doc.get_resource_str(&spine_item.idref) {
-            let start_position = content.len();
-            
-            // Simple HTML tag removal - in a real app you'd want a proper HTML parser
-            let text_content = strip_html_tags(&chapter_content);
-            content.push_str(&text_content);
-            content.push_str("\n\n");
-            
-            let end_position = content.len();
-            
-            // Extract chapter title (try to find h1, h2, etc. or use spine item title)
-            let chapter_title = extract_chapter_title(&chapter_content, index + 1);
-            
-            chapters.push(Chapter {
-                id: format!("{}_{}", doc.get_current_id().unwrap_or(spine_item.idref.clone()), index),
+            let first_block = builder.blocks.len();
+
+            let anchors = builder.add_xhtml(&chapter_content, &path);
+            for (id, offset) in anchors {
+                anchor_offsets.insert((path.clone(), id), offset);
+            }
+
+            let start_position = builder
+                .blocks
+                .get(first_block)
+                .map(|block| block.position)
+                .unwrap_or(builder.content.len());
+            let end_position = builder.content.len();
+
+            // Only used when the book has no usable table of contents
+            let chapter_title = builder.blocks[first_block..]
+                .iter()
+                .find_map(|block| match &block.kind {
+                    BlockKind::Heading { spans, .. } => {
+                        Some(collapse_whitespace(&spans.iter().map(|s| s.text.as_str()).collect::<String>()))
+                    }
+                    _ => None,
+                })
+                .filter(|title| !title.is_empty())
+                .unwrap_or_else(|| format!("Chapter {}", index + 1));
+
+            spine_chapters.push(Chapter {
+                id: format!("{}_{}", spine_item.idref, index),
                 title: chapter_title,
                 start_position,
                 end_position,
+                level: 0,
+                parent_id: None,
+                href: Some(path.clone()),
             });
+            file_offsets.insert(path, start_position);
         }
     }
 
+    let (mut content, mut blocks) = builder.finish();
+
+    // Prefer the book's own table of contents over one entry per spine file
+    progress.update("Reading table of contents", 0.0)?;
+    let toc = read_toc(&mut doc);
+    let mut chapters = resolve_toc(&toc, &file_offsets, &anchor_offsets, content.len());
+    if chapters.is_empty() {
+        chapters = spine_chapters;
+    }
+
+    // Project Gutenberg's header and licence are kept out of the reading flow
+    let mut appendices = Vec::new();
+    if let Some(boilerplate) = gutenberg::find(&content) {
+        appendices = boilerplate.appendices(&content);
+        gutenberg::strip_structured(&mut content, &mut blocks, &mut chapters, &boilerplate);
+        title = title.or(boilerplate.title);
+        author = author.or(boilerplate.author);
+    }
+    let title = title.unwrap_or_else(|| "Unknown Title".to_string());
+
     // Estimate pages (rough calculation: ~500 words per page)
     let word_count = content.split_whitespace().count();
     let estimated_pages = (word_count / 500).max(1);
@@ -50,51 +150,307 @@ pub async fn parse_epub(file_path: &PathBuf) -> Result<Document, String> {
     let cover_image = extract_cover_image(&mut doc);
 
     Ok(Document {
-        id: Uuid::new_v4().to_string(),
+        id: content_hash.clone(),
         title,
         author,
         file_path: file_path.clone(),
         file_type: "epub".to_string(),
+        content_hash,
+        kind: DocumentKind::Text,
+        reading_direction: ReadingDirection::LeftToRight,
+        encoding: None,
+        asin: None,
+        series: None,
+        series_index: None,
+        genres: Vec::new(),
+        description: None,
         content,
+        blocks,
         current_position: 0,
         total_pages: estimated_pages,
         chapters,
+        pages: Vec::new(),
+        appendices,
         cover_image,
     })
 }
 
-fn extract_chapter_title(html: &str, chapter_number: usize) -> String {
-    // Try to find h1, h2, h3 tags for chapter title
-    if let Some(title_start) = html.find("<h1") {
-        if let Some(content_start) = html[title_start..].find('>') {
-            let start_pos = title_start + content_start + 1;
-            if let Some(end_pos) = html[start_pos..].find("</h1>") {
-                let title = &html[start_pos..start_pos + end_pos];
-                return strip_html_tags(title).trim().to_string();
+/// Reads the EPUB3 navigation document, falling back to the EPUB2 NCX.
+fn read_toc(doc: &mut EpubArchive) -> Vec<TocEntry> {
+    let root_file = doc.root_file.clone();
+    let Some(opf) = doc.get_resource_str_by_path(&root_file) else {
+        return Vec::new();
+    };
+    let Some(package) = parse_xml(&opf) else {
+        return Vec::new();
+    };
+    let opf_dir = parent_dir(&normalize_path(&root_file.to_string_lossy()));
+
+    let ncx_id = package
+        .descendants()
+        .find(|n| n.tag_name().name() == "spine")
+        .and_then(|spine| spine.attribute("toc"));
+
+    let mut nav_href = None;
+    let mut ncx_href = None;
+    for item in package.descendants().filter(|n| n.tag_name().name() == "item") {
+        let Some(href) = item.attribute("href") else {
+            continue;
+        };
+        let is_nav = item
+            .attribute("properties")
+            .is_some_and(|props| props.split_whitespace().any(|p| p == "nav"));
+        if is_nav {
+            nav_href = Some(href);
+        } else if item.attribute("media-type") == Some("application/x-dtbncx+xml")
+            || (ncx_id.is_some() && item.attribute("id") == ncx_id)
+        {
+            ncx_href = Some(href);
+        }
+    }
+
+    if let Some(href) = nav_href {
+        let (path, _) = resolve_href(&opf_dir, href);
+        if let Some(xhtml) = doc.get_resource_str_by_path(&path) {
+            let entries = parse_nav(&xhtml, &parent_dir(&path));
+            if !entries.is_empty() {
+                return entries;
             }
         }
     }
-    
-    if let Some(title_start) = html.find("<h2") {
-        if let Some(content_start) = html[title_start..].find('>') {
-            let start_pos = title_start + content_start + 1;
-            if let Some(end_pos) = html[start_pos..].find("</h2>") {
-                let title = &html[start_pos..start_pos + end_pos];
-                return strip_html_tags(title).trim().to_string();
+
+    if let Some(href) = ncx_href {
+        let (path, _) = resolve_href(&opf_dir, href);
+        if let Some(xml) = doc.get_resource_str_by_path(&path) {
+            return parse_ncx(&xml, &parent_dir(&path));
+        }
+    }
+
+    Vec::new()
+}
+
+/// Parses the `<nav epub:type="toc">` list of an EPUB3 navigation document.
+fn parse_nav(xhtml: &str, base_dir: &str) -> Vec<TocEntry> {
+    let html = Html::parse_document(xhtml);
+    let nav_selector = Selector::parse("nav").unwrap();
+    let navs: Vec<ElementRef> = html.select(&nav_selector).collect();
+
+    let toc_nav = navs
+        .iter()
+        .find(|nav| {
+            nav.value().attrs().any(|(name, value)| {
+                (name == "epub:type" || name == "role")
+                    && value.split_whitespace().any(|v| v == "toc" || v == "doc-toc")
+            })
+        })
+        .or(navs.first());
+
+    toc_nav
+        .and_then(|nav| nav.descendent_elements().find(|e| e.value().name() == "ol"))
+        .map(|ol| parse_nav_list(ol, base_dir))
+        .unwrap_or_default()
+}
+
+fn parse_nav_list(ol: ElementRef, base_dir: &str) -> Vec<TocEntry> {
+    ol.child_elements()
+        .filter(|li| li.value().name() == "li")
+        .filter_map(|li| {
+            // Each item is an <a> (or an unlinked <span> heading) plus an optional nested <ol>
+            let link = li
+                .child_elements()
+                .find(|e| matches!(e.value().name(), "a" | "span"));
+            let label = link
+                .map(|l| collapse_whitespace(&l.text().collect::<String>()))
+                .unwrap_or_default();
+            let target = link
+                .and_then(|l| l.value().attr("href"))
+                .map(|href| resolve_href(base_dir, href));
+            let children = li
+                .child_elements()
+                .find(|e| e.value().name() == "ol")
+                .map(|nested| parse_nav_list(nested, base_dir))
+                .unwrap_or_default();
+
+            if label.is_empty() && children.is_empty() {
+                return None;
+            }
+            Some(TocEntry { label, target, children })
+        })
+        .collect()
+}
+
+/// Parses the `<navMap>` of an EPUB2 toc.ncx, keeping document order.
+fn parse_ncx(xml: &str, base_dir: &str) -> Vec<TocEntry> {
+    let Some(ncx) = parse_xml(xml) else {
+        return Vec::new();
+    };
+    ncx.descendants()
+        .find(|n| n.tag_name().name() == "navMap")
+        .map(|nav_map| parse_nav_points(nav_map, base_dir))
+        .unwrap_or_default()
+}
+
+fn parse_nav_points(parent: roxmltree::Node, base_dir: &str) -> Vec<TocEntry> {
+    parent
+        .children()
+        .filter(|n| n.tag_name().name() == "navPoint")
+        .map(|point| {
+            let label = point
+                .children()
+                .find(|n| n.tag_name().name() == "navLabel")
+                .and_then(|l| l.descendants().find(|n| n.tag_name().name() == "text"))
+                .and_then(|t| t.text())
+                .map(collapse_whitespace)
+                .unwrap_or_default();
+            let target = point
+                .children()
+                .find(|n| n.tag_name().name() == "content")
+                .and_then(|c| c.attribute("src"))
+                .map(|src| resolve_href(base_dir, src));
+
+            TocEntry {
+                label,
+                target,
+                children: parse_nav_points(point, base_dir),
+            }
+        })
+        .collect()
+}
+
+/// Flattens the TOC tree into chapters in reading order. Each entry ends where
+/// the next entry at the same or a shallower level begins.
+fn resolve_toc(
+    entries: &[TocEntry],
+    file_offsets: &HashMap<String, usize>,
+    anchor_offsets: &HashMap<(String, String), usize>,
+    content_len: usize,
+) -> Vec<Chapter> {
+    let mut chapters = Vec::new();
+    flatten_toc(entries, 0, None, file_offsets, anchor_offsets, &mut chapters);
+
+    for i in 0..chapters.len() {
+        let level = chapters[i].level;
+        let end = chapters[i + 1..]
+            .iter()
+            .find(|next| next.level <= level)
+            .map(|next| next.start_position)
+            .unwrap_or(content_len);
+        chapters[i].end_position = end.max(chapters[i].start_position);
+    }
+
+    chapters
+}
+
+fn flatten_toc(
+    entries: &[TocEntry],
+    level: usize,
+    parent_id: Option<&str>,
+    file_offsets: &HashMap<String, usize>,
+    anchor_offsets: &HashMap<(String, String), usize>,
+    chapters: &mut Vec<Chapter>,
+) {
+    for entry in entries {
+        let start = entry.target.as_ref().and_then(|(path, fragment)| {
+            fragment
+                .as_ref()
+                .and_then(|f| anchor_offsets.get(&(path.clone(), f.clone())))
+                .or_else(|| file_offsets.get(path))
+                .copied()
+        });
+        if start.is_none() && entry.children.is_empty() {
+            continue;
+        }
+
+        let index = chapters.len();
+        let id = format!("toc_{}", index);
+        let title = if entry.label.is_empty() {
+            format!("Section {}", index + 1)
+        } else {
+            entry.label.clone()
+        };
+        chapters.push(Chapter {
+            id: id.clone(),
+            title,
+            start_position: start.unwrap_or(0),
+            end_position: 0,
+            level,
+            parent_id: parent_id.map(str::to_string),
+            href: entry.target.as_ref().map(|(path, fragment)| match fragment {
+                Some(fragment) => format!("{}#{}", path, fragment),
+                None => path.clone(),
+            }),
+        });
+
+        flatten_toc(&entry.children, level + 1, Some(&id), file_offsets, anchor_offsets, chapters);
+
+        // Unlinked headings (e.g. a "Part One" <span>) start where their first child does
+        if start.is_none() {
+            match chapters.get(index + 1) {
+                Some(child) => chapters[index].start_position = child.start_position,
+                None => {
+                    chapters.remove(index);
+                }
             }
         }
     }
-    
-    // If no title found, use generic chapter name
-    format!("Chapter {}", chapter_number)
 }
 
-fn extract_cover_image(doc: &mut EpubDoc<std::io::BufReader<std::fs::File>>) -> Option<String> {
+fn parse_xml(xml: &str) -> Option<roxmltree::Document<'_>> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    roxmltree::Document::parse_with_options(xml.trim_start_matches('\u{feff}'), options).ok()
+}
+
+/// Resolves a (possibly percent-encoded) href against the directory of the
+/// file it appears in, returning the archive path and the fragment, if any.
+fn resolve_href(base_dir: &str, href: &str) -> (String, Option<String>) {
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (href, None),
+    };
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let fragment = fragment.map(|f| percent_decode_str(f).decode_utf8_lossy().into_owned());
+
+    (normalize_path(&format!("{}/{}", base_dir, path)), fragment)
+}
+
+fn parent_dir(path: &str) -> String {
+    path.rsplit_once('/')
+        .map(|(dir, _)| dir.to_string())
+        .unwrap_or_default()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Reads a file from inside the book (e.g. an `Image` block's `src`) as a data URL.
+pub fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+    let mut doc = EpubDoc::new(file_path).map_err(|e| Error::corrupt(format!("Failed to open EPUB: {}", e)))?;
+    let data = doc
+        .get_resource_by_path(resource_path)
+        .ok_or(Error::NotFound("Resource"))?;
+    // Manifest paths are stored percent-encoded, block sources are decoded
+    let mime_type = doc
+        .resources
+        .values()
+        .find(|(path, _)| {
+            normalize_path(&percent_decode_str(&path.to_string_lossy()).decode_utf8_lossy()) == resource_path
+        })
+        .map(|(_, mime)| mime.clone())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    Ok(format!("data:{};base64,{}", mime_type, BASE64.encode(&data)))
+}
+
+fn extract_cover_image(doc: &mut EpubArchive) -> Option<String> {
     // Try to get cover image from EPUB metadata
     if let Some((cover_data, _mime_type)) = doc.get_cover() {
         // Convert image data to base64
         let base64_image = BASE64.encode(&cover_data);
-        
+
         // Try to determine image type from first few bytes
         let mime_type = if cover_data.starts_with(b"\x89PNG") {
             "image/png"
@@ -105,26 +461,170 @@ fn extract_cover_image(doc: &mut EpubDoc<std::io::BufReader<std::fs::File>>) ->
         } else {
             "image/jpeg" // Default fallback
         };
-        
+
         return Some(format!("data:{};base64,{}", mime_type, base64_image));
     }
-    
+
     None
 }
 
-fn strip_html_tags(html: &str) -> String {
-    let mut result = String::new();
-    let mut in_tag = false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAV: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<body>
+  <nav epub:type="landmarks"><ol><li><a href="text/cover.xhtml">Cover</a></li></ol></nav>
+  <nav epub:type="toc">
+    <ol>
+      <li><a href="text/preface.xhtml">
+        The   Preface
+      </a></li>
+      <li><span>Part One</span>
+        <ol>
+          <li><a href="text/ch1.xhtml">Chapter 1</a></li>
+          <li><a href="text/ch1.xhtml#sec%202">Section 2</a></li>
+        </ol>
+      </li>
+      <li><a href="../lost.xhtml">Lost</a></li>
+      <li><span>Appendices</span><ol><li><a href="text/gone.xhtml">Appendix A</a></li></ol></li>
+    </ol>
+  </nav>
+</body>
+</html>"#;
 
-    for ch in html.chars() {
-        match ch {
-            '<' => in_tag = true,
-            '>' => in_tag = false,
-            _ if !in_tag => result.push(ch),
-            _ => {}
+    const NCX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE ncx PUBLIC "-//NISO//DTD ncx 2005-1//EN" "http://www.daisy.org/z3986/2005/ncx-2005-1.dtd">
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <docTitle><text>Moby-Dick</text></docTitle>
+  <navMap>
+    <navPoint id="p1" playOrder="1">
+      <navLabel><text>Book I</text></navLabel>
+      <content src="book1.html"/>
+      <navPoint id="p2" playOrder="2">
+        <navLabel><text>Chapter
+          One</text></navLabel>
+        <content src="book1.html#ch1"/>
+        <navPoint id="p3" playOrder="3">
+          <navLabel><text>Loomings</text></navLabel>
+          <content src="extra/../book1.html#loomings"/>
+        </navPoint>
+      </navPoint>
+    </navPoint>
+    <navPoint id="p4" playOrder="4">
+      <navLabel><text>Book II</text></navLabel>
+      <content src="book2.html"/>
+    </navPoint>
+  </navMap>
+</ncx>"#;
+
+    // One line per entry, indented by depth: "label -> path#fragment"
+    fn outline(entries: &[TocEntry]) -> Vec<String> {
+        fn walk(entries: &[TocEntry], depth: usize, lines: &mut Vec<String>) {
+            for entry in entries {
+                let target = match &entry.target {
+                    Some((path, Some(fragment))) => format!("{}#{}", path, fragment),
+                    Some((path, None)) => path.clone(),
+                    None => "-".to_string(),
+                };
+                lines.push(format!("{}{} -> {}", "  ".repeat(depth), entry.label, target));
+                walk(&entry.children, depth + 1, lines);
+            }
         }
+        let mut lines = Vec::new();
+        walk(entries, 0, &mut lines);
+        lines
+    }
+
+    fn offsets(entries: &[(&str, usize)]) -> HashMap<String, usize> {
+        entries.iter().map(|(path, offset)| (path.to_string(), *offset)).collect()
+    }
+
+    fn anchors(entries: &[(&str, &str, usize)]) -> HashMap<(String, String), usize> {
+        entries
+            .iter()
+            .map(|(path, id, offset)| ((path.to_string(), id.to_string()), *offset))
+            .collect()
+    }
+
+    #[test]
+    fn nav_documents_give_the_toc_tree() {
+        assert_eq!(
+            outline(&parse_nav(NAV, "OEBPS")),
+            [
+                "The Preface -> OEBPS/text/preface.xhtml",
+                "Part One -> -",
+                "  Chapter 1 -> OEBPS/text/ch1.xhtml",
+                "  Section 2 -> OEBPS/text/ch1.xhtml#sec 2",
+                "Lost -> lost.xhtml",
+                "Appendices -> -",
+                "  Appendix A -> OEBPS/text/gone.xhtml",
+            ]
+        );
+    }
+
+    #[test]
+    fn ncx_files_give_the_toc_tree() {
+        assert_eq!(
+            outline(&parse_ncx(NCX, "OPS")),
+            [
+                "Book I -> OPS/book1.html",
+                "  Chapter One -> OPS/book1.html#ch1",
+                "    Loomings -> OPS/book1.html#loomings",
+                "Book II -> OPS/book2.html",
+            ]
+        );
+        assert!(parse_ncx("<ncx><navMap>", "OPS").is_empty());
+    }
+
+    #[test]
+    fn nested_entries_keep_their_level_and_parent() {
+        let entries = parse_ncx(NCX, "OPS");
+        let files = offsets(&[("OPS/book1.html", 0), ("OPS/book2.html", 500)]);
+        let fragments = anchors(&[("OPS/book1.html", "ch1", 20), ("OPS/book1.html", "loomings", 40)]);
+        let mut chapters = Vec::new();
+        flatten_toc(&entries, 0, None, &files, &fragments, &mut chapters);
+
+        let tree: Vec<_> = chapters
+            .iter()
+            .map(|c| (c.id.as_str(), c.level, c.parent_id.as_deref(), c.start_position))
+            .collect();
+        assert_eq!(
+            tree,
+            [
+                ("toc_0", 0, None, 0),
+                ("toc_1", 1, Some("toc_0"), 20),
+                ("toc_2", 2, Some("toc_1"), 40),
+                ("toc_3", 0, None, 500),
+            ]
+        );
+        assert_eq!(chapters[2].href.as_deref(), Some("OPS/book1.html#loomings"));
     }
 
-    // Clean up extra whitespace
-    result.split_whitespace().collect::<Vec<_>>().join(" ")
+    #[test]
+    fn entries_resolve_to_ranges_of_the_text() {
+        let entries = parse_nav(NAV, "OEBPS");
+        let files = offsets(&[("OEBPS/text/preface.xhtml", 0), ("OEBPS/text/ch1.xhtml", 100)]);
+        let fragments = anchors(&[("OEBPS/text/ch1.xhtml", "sec 2", 160)]);
+        let chapters = resolve_toc(&entries, &files, &fragments, 300);
+
+        let ranges: Vec<_> = chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.level, c.parent_id.as_deref(), c.start_position, c.end_position))
+            .collect();
+        // "Lost" and the empty "Appendices" point nowhere in the text and are dropped;
+        // "Part One" starts with its first chapter and runs to the end
+        assert_eq!(
+            ranges,
+            [
+                ("The Preface", 0, None, 0, 100),
+                ("Part One", 0, None, 100, 300),
+                ("Chapter 1", 1, Some("toc_1"), 100, 160),
+                ("Section 2", 1, Some("toc_1"), 160, 300),
+            ]
+        );
+        assert_eq!(chapters[1].href, None);
+        assert_eq!(chapters[3].href.as_deref(), Some("OEBPS/text/ch1.xhtml#sec 2"));
+    }
 }
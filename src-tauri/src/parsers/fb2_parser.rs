@@ -0,0 +1,720 @@
+use crate::error::{Error, Result};
+use crate::{Chapter, Document, DocumentKind, ReadingDirection};
+use super::progress::Progress;
+use super::{content_hash, DocumentParser, FileSignature, ParseOptions};
+use super::xhtml::ContentBuilder;
+use encoding_rs::{Encoding, UTF_8};
+use roxmltree::Node;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+// The whole book is converted as one file, so every link and anchor shares it
+const BOOK_PATH: &str = "book.fb2";
+
+pub struct Fb2Parser;
+
+impl DocumentParser for Fb2Parser {
+    fn detect(signature: &FileSignature) -> bool {
+        if signature.is_zip() {
+            return signature.zip_entries.iter().any(|name| name.to_lowercase().ends_with(".fb2"));
+        }
+        // Tag names are ASCII in every encoding FB2 files use
+        String::from_utf8_lossy(&signature.head).contains("<FictionBook")
+    }
+
+    fn parse(file_path: &PathBuf, _options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        parse_fb2(file_path, progress)
+    }
+
+    fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+        read_resource(file_path, resource_path)
+    }
+}
+
+/// Parses a FictionBook 2 file, or a `.fb2.zip` archive holding one.
+pub fn parse_fb2(file_path: &PathBuf, progress: &Progress) -> Result<Document> {
+    progress.update("Opening", 0.0)?;
+    let xml = read_fb2(file_path)?;
+    let content_hash = content_hash(file_path)?;
+    let book = parse_xml(&xml)?;
+    let root = book.root_element();
+
+    let title_info = root
+        .children()
+        .find(|n| n.has_tag_name("description"))
+        .and_then(|description| child(description, "title-info"));
+
+    let title = title_info
+        .and_then(|info| child(info, "book-title"))
+        .map(text_of)
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| "Unknown Title".to_string());
+
+    let authors: Vec<String> = title_info
+        .map(|info| info.children().filter(|n| n.has_tag_name("author")).filter_map(author_name).collect())
+        .unwrap_or_default();
+    let author = (!authors.is_empty()).then(|| authors.join(", "));
+
+    let sequence = title_info.and_then(|info| child(info, "sequence"));
+    let series = sequence
+        .and_then(|s| s.attribute("name"))
+        .map(collapse_whitespace)
+        .filter(|name| !name.is_empty());
+    let series_index = sequence
+        .and_then(|s| s.attribute("number"))
+        .and_then(|number| number.trim().parse().ok());
+
+    let genres = title_info
+        .map(|info| {
+            info.children()
+                .filter(|n| n.has_tag_name("genre"))
+                .map(text_of)
+                .filter(|genre| !genre.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let description = title_info
+        .and_then(|info| child(info, "annotation"))
+        .map(|annotation| {
+            annotation
+                .descendants()
+                .filter(|n| n.has_tag_name("p") || n.has_tag_name("v"))
+                .map(text_of)
+                .filter(|p| !p.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        })
+        .filter(|description| !description.is_empty());
+
+    let binaries: HashMap<&str, Node> = root
+        .children()
+        .filter(|n| n.has_tag_name("binary"))
+        .filter_map(|n| Some((n.attribute("id")?, n)))
+        .collect();
+    let cover_image = title_info
+        .and_then(|info| child(info, "coverpage"))
+        .and_then(|coverpage| child(coverpage, "image"))
+        .and_then(href)
+        .and_then(|href| binaries.get(href.trim_start_matches('#')))
+        .map(|binary| binary_data_url(*binary));
+
+    // Convert the bodies to XHTML, main text first and notes after it
+    progress.update("Converting chapters", 0.0)?;
+    let mut converter = Converter::default();
+    let bodies: Vec<Node> = root.children().filter(|n| n.has_tag_name("body")).collect();
+    let (notes, main): (Vec<(usize, Node)>, Vec<(usize, Node)>) = bodies.into_iter().enumerate().partition(|(index, body)| {
+        *index > 0 && body.attribute("name").is_some_and(|name| matches!(name, "notes" | "comments" | "footnotes"))
+    });
+    for (_, body) in &main {
+        converter.body(*body, false);
+    }
+    for (_, body) in &notes {
+        converter.body(*body, true);
+    }
+
+    progress.update("Converting chapters", 0.5)?;
+    let mut builder = ContentBuilder::new();
+    let anchors = builder.add_xhtml(&converter.finish(), BOOK_PATH);
+    let (content, blocks) = builder.finish();
+
+    let chapters = resolve_headings(&converter.headings, &anchors, content.len());
+
+    // Estimate pages (rough calculation: ~500 words per page)
+    let word_count = content.split_whitespace().count();
+    let estimated_pages = (word_count / 500).max(1);
+
+    Ok(Document {
+        id: content_hash.clone(),
+        title,
+        author,
+        file_path: file_path.clone(),
+        file_type: "fb2".to_string(),
+        content_hash,
+        kind: DocumentKind::Text,
+        reading_direction: ReadingDirection::LeftToRight,
+        encoding: None,
+        asin: None,
+        series,
+        series_index,
+        genres,
+        description,
+        content,
+        blocks,
+        current_position: 0,
+        total_pages: estimated_pages,
+        chapters,
+        pages: Vec::new(),
+        appendices: Vec::new(),
+        cover_image,
+    })
+}
+
+/// Reads an image referenced by an `Image` block (`binary/<id>`) as a data URL.
+pub fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+    let xml = read_fb2(file_path)?;
+    let book = parse_xml(&xml)?;
+    let id = resource_path.strip_prefix("binary/").unwrap_or(resource_path);
+
+    book.root_element()
+        .children()
+        .find(|n| n.has_tag_name("binary") && n.attribute("id") == Some(id))
+        .map(binary_data_url)
+        .ok_or(Error::NotFound("Resource"))
+}
+
+/// The book's XML as text, unzipping `.fb2.zip` files first.
+fn read_fb2(file_path: &PathBuf) -> Result<String> {
+    let bytes = fs::read(file_path).map_err(|e| Error::io(file_path, e))?;
+    if !bytes.starts_with(b"PK\x03\x04") {
+        return Ok(decode_xml(&bytes));
+    }
+
+    let mut archive =
+        zip::ZipArchive::new(Cursor::new(bytes)).map_err(|e| Error::corrupt(format!("Failed to read FB2 archive: {}", e)))?;
+    let index = (0..archive.len())
+        .find(|&i| {
+            archive
+                .by_index(i)
+                .is_ok_and(|entry| entry.name().to_lowercase().ends_with(".fb2"))
+        })
+        .ok_or_else(|| Error::corrupt("No .fb2 file found in the archive"))?;
+    let mut entry = archive
+        .by_index(index)
+        .map_err(|e| Error::corrupt(format!("Failed to read FB2 archive: {}", e)))?;
+    let mut xml = Vec::new();
+    entry
+        .read_to_end(&mut xml)
+        .map_err(|e| Error::corrupt(format!("Failed to read FB2 archive: {}", e)))?;
+
+    Ok(decode_xml(&xml))
+}
+
+/// Decodes by byte order mark, then by the XML declaration. Many FB2 files
+/// are windows-1251 and say so only in the declaration.
+fn decode_xml(bytes: &[u8]) -> String {
+    let declared = || {
+        let head = String::from_utf8_lossy(&bytes[..bytes.len().min(200)]).into_owned();
+        if !head.starts_with("<?xml") {
+            return None;
+        }
+        let declaration = &head[..head.find("?>")?];
+        let rest = &declaration[declaration.find("encoding")? + "encoding".len()..];
+        let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+        let quote = rest.chars().next().filter(|&q| q == '"' || q == '\'')?;
+        let label = &rest[1..][..rest[1..].find(quote)?];
+        Encoding::for_label(label.trim().as_bytes())
+    };
+
+    let (encoding, bom_length) = Encoding::for_bom(bytes).unwrap_or_else(|| (declared().unwrap_or(UTF_8), 0));
+    encoding.decode_without_bom_handling(&bytes[bom_length..]).0.into_owned()
+}
+
+fn parse_xml(xml: &str) -> Result<roxmltree::Document<'_>> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    roxmltree::Document::parse_with_options(xml, options).map_err(|e| Error::corrupt(format!("Failed to parse FB2: {}", e)))
+}
+
+fn binary_data_url(binary: Node) -> String {
+    let mime_type = binary.attribute("content-type").unwrap_or("image/jpeg");
+    // The base64 text is usually wrapped over many lines
+    let data: String = binary
+        .text()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    format!("data:{};base64,{}", mime_type, data)
+}
+
+/// A section title, recorded while converting, to become a chapter.
+struct Heading {
+    anchor: String,
+    title: String,
+    level: usize,
+}
+
+/// Writes FB2 body markup as XHTML for the content builder. Sections get
+/// ids so their titles can be found in the text afterwards.
+#[derive(Default)]
+struct Converter {
+    html: String,
+    headings: Vec<Heading>,
+    generated_ids: usize,
+}
+
+impl Converter {
+    fn finish(&self) -> String {
+        format!("<html><body>{}</body></html>", self.html)
+    }
+
+    fn body(&mut self, body: Node, notes: bool) {
+        let anchor = self.anchor(body);
+        self.html.push_str(&format!("<div id=\"{}\">", escape(&anchor)));
+
+        let title = child(body, "title").map(title_text).filter(|title| !title.is_empty());
+        if notes {
+            // Footnotes are one entry in the contents, not one per note
+            self.headings.push(Heading {
+                anchor,
+                title: title.unwrap_or_else(|| "Notes".to_string()),
+                level: 0,
+            });
+        }
+
+        for node in body.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "section" if notes => self.blocks(node, 2),
+                "section" => self.section(node, 0),
+                "title" => self.title(node, 1),
+                _ => self.block(node, 1),
+            }
+        }
+        self.html.push_str("</div>");
+    }
+
+    /// A section and its subsections. Untitled sections don't get a chapter
+    /// of their own; their subsections stay at the same level.
+    fn section(&mut self, section: Node, level: usize) {
+        let anchor = self.anchor(section);
+        self.html.push_str(&format!("<div id=\"{}\">", escape(&anchor)));
+
+        let title = child(section, "title").map(title_text).filter(|title| !title.is_empty());
+        let child_level = match title {
+            Some(title) => {
+                self.headings.push(Heading { anchor, title, level });
+                level + 1
+            }
+            None => level,
+        };
+
+        for node in section.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "section" => self.section(node, child_level),
+                "title" => self.title(node, (level + 2).min(6)),
+                _ => self.block(node, (level + 3).min(6)),
+            }
+        }
+        self.html.push_str("</div>");
+    }
+
+    /// A note's body: its subsections are plain content, not chapters.
+    fn blocks(&mut self, node: Node, heading_level: usize) {
+        let anchor = self.anchor(node);
+        self.html.push_str(&format!("<div id=\"{}\">", escape(&anchor)));
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "section" => self.blocks(child, heading_level),
+                "title" => self.title(child, heading_level),
+                _ => self.block(child, heading_level),
+            }
+        }
+        self.html.push_str("</div>");
+    }
+
+    fn title(&mut self, title: Node, level: usize) {
+        let lines: Vec<String> = title
+            .children()
+            .filter(|n| n.has_tag_name("p"))
+            .map(|p| self.inline_html(p))
+            .collect();
+        if !lines.is_empty() {
+            self.html.push_str(&format!("<h{0}>{1}</h{0}>", level, lines.join("<br/>")));
+        }
+    }
+
+    fn block(&mut self, node: Node, heading_level: usize) {
+        if !node.is_element() {
+            return;
+        }
+        let id = node
+            .attribute("id")
+            .map(|id| format!(" id=\"{}\"", escape(id)))
+            .unwrap_or_default();
+
+        match node.tag_name().name() {
+            "p" => {
+                let inner = self.inline_html(node);
+                self.html.push_str(&format!("<p{}>{}</p>", id, inner));
+            }
+            "subtitle" => {
+                let inner = self.inline_html(node);
+                self.html.push_str(&format!("<p{}><strong>{}</strong></p>", id, inner));
+            }
+            "text-author" | "date" => {
+                let inner = self.inline_html(node);
+                self.html.push_str(&format!("<p{}><em>{}</em></p>", id, inner));
+            }
+            "title" => self.title(node, heading_level),
+            "epigraph" | "cite" | "annotation" => {
+                self.html.push_str(&format!("<blockquote{}>", id));
+                for child in node.children() {
+                    self.block(child, heading_level);
+                }
+                self.html.push_str("</blockquote>");
+            }
+            "poem" => {
+                self.html.push_str(&format!("<div{}>", id));
+                for child in node.children() {
+                    self.block(child, heading_level);
+                }
+                self.html.push_str("</div>");
+            }
+            "stanza" => {
+                if let Some(title) = child(node, "title") {
+                    self.title(title, heading_level);
+                }
+                let lines: Vec<String> = node
+                    .children()
+                    .filter(|n| n.has_tag_name("v"))
+                    .map(|v| self.inline_html(v))
+                    .collect();
+                self.html.push_str(&format!("<p{}>{}</p>", id, lines.join("<br/>")));
+            }
+            "image" => self.html.push_str(&image_html(node)),
+            "table" => {
+                self.html.push_str(&format!("<table{}>", id));
+                for row in node.children().filter(|n| n.has_tag_name("tr")) {
+                    self.html.push_str("<tr>");
+                    for cell in row.children().filter(|n| n.has_tag_name("td") || n.has_tag_name("th")) {
+                        let name = cell.tag_name().name();
+                        let inner = self.inline_html(cell);
+                        self.html.push_str(&format!("<{0}>{1}</{0}>", name, inner));
+                    }
+                    self.html.push_str("</tr>");
+                }
+                self.html.push_str("</table>");
+            }
+            "section" => self.blocks(node, heading_level),
+            "empty-line" => {}
+            _ => {
+                for child in node.children() {
+                    self.block(child, heading_level);
+                }
+            }
+        }
+    }
+
+    fn inline_html(&self, node: Node) -> String {
+        let mut html = String::new();
+        for child in node.children() {
+            if child.is_text() {
+                html.push_str(&escape(child.text().unwrap_or_default()));
+                continue;
+            }
+            if !child.is_element() {
+                continue;
+            }
+            let inner = self.inline_html(child);
+            match child.tag_name().name() {
+                "emphasis" => html.push_str(&format!("<em>{}</em>", inner)),
+                "strong" => html.push_str(&format!("<strong>{}</strong>", inner)),
+                "strikethrough" => html.push_str(&format!("<s>{}</s>", inner)),
+                name @ ("sub" | "sup" | "code") => html.push_str(&format!("<{0}>{1}</{0}>", name, inner)),
+                "a" => match href(child) {
+                    Some(target) => html.push_str(&format!("<a href=\"{}\">{}</a>", escape(target), inner)),
+                    None => html.push_str(&inner),
+                },
+                "image" => html.push_str(&image_html(child)),
+                _ => html.push_str(&inner),
+            }
+        }
+        html
+    }
+
+    /// The element's own id, or a generated one.
+    fn anchor(&mut self, node: Node) -> String {
+        match node.attribute("id") {
+            Some(id) => id.to_string(),
+            None => {
+                self.generated_ids += 1;
+                format!("fb2_section_{}", self.generated_ids)
+            }
+        }
+    }
+}
+
+/// Turns section headings into chapters. Each ends where the next heading at
+/// the same or a shallower level begins.
+fn resolve_headings(headings: &[Heading], anchors: &HashMap<String, usize>, content_len: usize) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+    // Ids of the chapters enclosing the current one, by level
+    let mut open: Vec<String> = Vec::new();
+
+    for heading in headings {
+        let Some(&start) = anchors.get(&heading.anchor) else {
+            continue;
+        };
+        open.truncate(heading.level);
+        let id = format!("section_{}", chapters.len());
+        chapters.push(Chapter {
+            id: id.clone(),
+            title: heading.title.clone(),
+            start_position: start,
+            end_position: 0,
+            level: open.len(),
+            parent_id: open.last().cloned(),
+            href: Some(format!("{}#{}", BOOK_PATH, heading.anchor)),
+        });
+        open.push(id);
+    }
+
+    for i in 0..chapters.len() {
+        let level = chapters[i].level;
+        let end = chapters[i + 1..]
+            .iter()
+            .find(|next| next.level <= level)
+            .map(|next| next.start_position)
+            .unwrap_or(content_len);
+        chapters[i].end_position = end.max(chapters[i].start_position);
+    }
+
+    chapters
+}
+
+fn image_html(image: Node) -> String {
+    match href(image) {
+        Some(target) => format!(
+            "<img src=\"binary/{}\" alt=\"{}\"/>",
+            escape(target.trim_start_matches('#')),
+            escape(image.attribute("alt").unwrap_or_default())
+        ),
+        None => String::new(),
+    }
+}
+
+/// The `l:href` (xlink) attribute, whatever prefix the file binds xlink to.
+fn href<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    node.attributes().find(|a| a.name() == "href").map(|a| a.value())
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+/// A `<title>`'s paragraphs as one line: "Chapter 1: The Storm".
+fn title_text(title: Node) -> String {
+    let lines: Vec<String> = title
+        .children()
+        .filter(|n| n.has_tag_name("p"))
+        .map(text_of)
+        .filter(|line| !line.is_empty())
+        .collect();
+    match lines.split_first() {
+        Some((first, rest)) if !rest.is_empty() => {
+            format!("{}: {}", first.trim_end_matches(['.', ':']), rest.join(" "))
+        }
+        _ => lines.join(" "),
+    }
+}
+
+fn author_name(author: Node) -> Option<String> {
+    let parts: Vec<String> = ["first-name", "middle-name", "last-name"]
+        .iter()
+        .filter_map(|part| child(author, part).map(text_of))
+        .filter(|part| !part.is_empty())
+        .collect();
+    if !parts.is_empty() {
+        return Some(parts.join(" "));
+    }
+    child(author, "nickname").map(text_of).filter(|name| !name.is_empty())
+}
+
+fn text_of(node: Node) -> String {
+    collapse_whitespace(&node.descendants().filter_map(|n| n.text().filter(|_| n.is_text())).collect::<String>())
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::xhtml::{Block, BlockKind};
+    use std::io::Write;
+
+    const BOOK: &str = r##"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description>
+    <title-info>
+      <genre>sf</genre>
+      <genre>sf_space</genre>
+      <author><first-name>Isaac</first-name><last-name>Asimov</last-name></author>
+      <author><first-name>Robert</first-name><middle-name>A.</middle-name><last-name>Heinlein</last-name></author>
+      <author><nickname>Anon</nickname></author>
+      <book-title>Foundation  and
+        Empire</book-title>
+      <annotation><p>The Mule rises.</p><p>The <emphasis>Empire</emphasis> falls.</p></annotation>
+      <coverpage><image l:href="#cover.png"/></coverpage>
+      <sequence name="Foundation" number="2"/>
+    </title-info>
+  </description>
+  <body>
+    <section>
+      <title><p>Part One</p><p>The General</p></title>
+      <section id="ch1">
+        <title><p>Chapter 1</p></title>
+        <p>Bel Riose<a l:href="#n1" type="note">1</a> travelled.</p>
+      </section>
+      <section>
+        <title><p>Chapter 2</p></title>
+        <p>The Emperor waited.</p>
+      </section>
+    </section>
+    <section>
+      <section>
+        <title><p>Interlude</p></title>
+        <p>Meanwhile.</p>
+      </section>
+    </section>
+  </body>
+  <body name="notes">
+    <title><p>Notes</p></title>
+    <section id="n1">
+      <title><p>1</p></title>
+      <p>A general of the Empire.</p>
+    </section>
+  </body>
+  <binary id="cover.png" content-type="image/png">iVBORw0K
+    GgoAAAAN</binary>
+</FictionBook>
+"##;
+
+    /// A file in the temp directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(extension: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("book-{}.{}", uuid::Uuid::new_v4(), extension));
+            fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn zipped(name: &str, contents: &str) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn links(blocks: &[Block]) -> Vec<String> {
+        blocks
+            .iter()
+            .flat_map(|block| match &block.kind {
+                BlockKind::Paragraph { spans } | BlockKind::Heading { spans, .. } => spans.clone(),
+                _ => Vec::new(),
+            })
+            .filter_map(|span| span.link)
+            .collect()
+    }
+
+    #[test]
+    fn description_becomes_metadata() {
+        let file = TempFile::new("fb2", BOOK.as_bytes());
+        let document = parse_fb2(&file.0, &Progress::default()).unwrap();
+
+        assert_eq!(document.title, "Foundation and Empire");
+        assert_eq!(document.author.as_deref(), Some("Isaac Asimov, Robert A. Heinlein, Anon"));
+        assert_eq!(document.series.as_deref(), Some("Foundation"));
+        assert_eq!(document.series_index, Some(2.0));
+        assert_eq!(document.genres, ["sf", "sf_space"]);
+        assert_eq!(document.description.as_deref(), Some("The Mule rises.\n\nThe Empire falls."));
+        // The wrapped base64 is joined back up
+        assert_eq!(document.cover_image.as_deref(), Some("data:image/png;base64,iVBORw0KGgoAAAAN"));
+        assert_eq!(read_resource(&file.0, "binary/cover.png").unwrap(), document.cover_image.unwrap());
+    }
+
+    #[test]
+    fn sections_nest_into_chapters() {
+        let file = TempFile::new("fb2", BOOK.as_bytes());
+        let document = parse_fb2(&file.0, &Progress::default()).unwrap();
+
+        let chapters: Vec<(&str, usize, Option<&str>)> = document
+            .chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.level, c.parent_id.as_deref()))
+            .collect();
+        assert_eq!(
+            chapters,
+            [
+                ("Part One: The General", 0, None),
+                ("Chapter 1", 1, Some("section_0")),
+                ("Chapter 2", 1, Some("section_0")),
+                // Its untitled section adds no level
+                ("Interlude", 0, None),
+                // A notes body is one entry, whatever its sections
+                ("Notes", 0, None),
+            ]
+        );
+
+        let part = &document.chapters[0];
+        assert!(document.content[part.start_position..].starts_with("Part One"));
+        assert_eq!(part.end_position, document.chapters[3].start_position);
+        assert_eq!(document.chapters[1].href.as_deref(), Some("book.fb2#ch1"));
+        assert!(document.content[document.chapters[3].start_position..].starts_with("Interlude"));
+    }
+
+    #[test]
+    fn note_links_point_into_the_notes() {
+        let file = TempFile::new("fb2", BOOK.as_bytes());
+        let document = parse_fb2(&file.0, &Progress::default()).unwrap();
+
+        assert_eq!(links(&document.blocks), ["book.fb2#n1"]);
+
+        // The notes follow the main text, so the note is after the reference
+        let notes = &document.chapters[4];
+        let note = document.content.find("A general of the Empire.").unwrap();
+        assert!(note > document.content.find("Bel Riose").unwrap());
+        assert!((notes.start_position..notes.end_position).contains(&note));
+    }
+
+    #[test]
+    fn declared_encodings_are_used() {
+        let (cyrillic, _, _) = encoding_rs::WINDOWS_1251.encode("Война и мир");
+        let mut bytes = b"<?xml version='1.0' encoding = 'windows-1251'?><book-title>".to_vec();
+        bytes.extend_from_slice(&cyrillic);
+        assert_eq!(decode_xml(&bytes), "<?xml version='1.0' encoding = 'windows-1251'?><book-title>Война и мир");
+
+        // A byte order mark wins over the declaration
+        let mut bytes = b"\xEF\xBB\xBF<?xml version=\"1.0\" encoding=\"windows-1251\"?>".to_vec();
+        bytes.extend_from_slice("Мир".as_bytes());
+        assert!(decode_xml(&bytes).ends_with("?>Мир"));
+
+        // Without either, UTF-8
+        assert_eq!(decode_xml("<FictionBook>Мир".as_bytes()), "<FictionBook>Мир");
+        assert_eq!(decode_xml(b"<?xml encoding=\"bogus\"?>ok"), "<?xml encoding=\"bogus\"?>ok");
+    }
+
+    #[test]
+    fn zipped_books_are_read() {
+        let file = TempFile::new("fb2.zip", &zipped("Foundation and Empire.fb2", BOOK));
+        assert_eq!(read_fb2(&file.0).unwrap(), BOOK);
+
+        let document = parse_fb2(&file.0, &Progress::default()).unwrap();
+        assert_eq!(document.title, "Foundation and Empire");
+        assert_eq!(document.chapters.len(), 5);
+
+        let file = TempFile::new("fb2.zip", &zipped("readme.txt", "Not a book"));
+        assert!(matches!(read_fb2(&file.0), Err(Error::Corrupt(_))));
+    }
+}
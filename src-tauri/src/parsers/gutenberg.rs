@@ -0,0 +1,282 @@
+use crate::{Appendix, Chapter};
+use super::xhtml::Block;
+
+/// Title of the appendix holding the header cut from the book.
+pub const HEADER_TITLE: &str = "Project Gutenberg Header";
+
+/// Where the book sits between Project Gutenberg's header and licence, plus
+/// what the header says about it.
+pub struct Boilerplate {
+    pub start: usize, // The book is content[start..end]
+    pub end: usize,
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+impl Boilerplate {
+    /// The header and licence as appendices, so they stay readable.
+    pub fn appendices(&self, content: &str) -> Vec<Appendix> {
+        [
+            (HEADER_TITLE, 0..self.start),
+            ("Project Gutenberg License", self.end..content.len()),
+        ]
+        .into_iter()
+        .filter(|(_, source)| !content[source.clone()].trim().is_empty())
+        .map(|(title, source)| Appendix {
+            title: title.to_string(),
+            content: content[source.clone()].trim().to_string(),
+            source,
+        })
+        .collect()
+    }
+}
+
+/// Finds the "*** START OF THE PROJECT GUTENBERG EBOOK ... ***" and
+/// "*** END OF ..." markers. Returns `None` for books without them.
+pub fn find(content: &str) -> Option<Boilerplate> {
+    let lines = lines_with_offsets(content);
+
+    let start_line = lines.iter().position(|(_, line)| is_start_marker(line))?;
+    let (marker_start, marker) = lines[start_line];
+    let after_marker = marker_start + marker.len();
+    let start = content.len() - content[after_marker..].trim_start().len();
+
+    let end = lines[start_line + 1..]
+        .iter()
+        .find(|(_, line)| is_end_marker(line))
+        .map(|(offset, _)| *offset)
+        .unwrap_or(content.len());
+    let end = content[..end].trim_end().len().max(start);
+
+    let header = &lines[..start_line];
+    let field = |name: &str| {
+        header.iter().enumerate().find_map(|(i, (_, line))| {
+            let value = line.trim().strip_prefix(name)?.trim();
+            // Long titles continue on indented lines
+            let continued = header[i + 1..]
+                .iter()
+                .take_while(|(_, line)| line.starts_with([' ', '\t']) && !line.trim().is_empty())
+                .map(|(_, line)| line.trim());
+            let value = std::iter::once(value).chain(continued).collect::<Vec<_>>().join(" ");
+            (!value.is_empty()).then_some(value)
+        })
+    };
+    let (banner_title, banner_author) = banner(header);
+
+    Some(Boilerplate {
+        start,
+        end,
+        title: field("Title:").or(banner_title),
+        author: field("Author:").or(banner_author),
+    })
+}
+
+/// Cuts EPUB content down to the book, moving block and chapter positions
+/// with it. Chapters that only covered boilerplate are dropped.
+pub fn strip_structured(
+    content: &mut String,
+    blocks: &mut Vec<Block>,
+    chapters: &mut Vec<Chapter>,
+    boilerplate: &Boilerplate,
+) {
+    let (start, end) = (boilerplate.start, boilerplate.end);
+
+    blocks.retain(|block| block.position >= start && block.position < end);
+    for block in blocks.iter_mut() {
+        block.shift_back(start);
+    }
+
+    chapters.retain(|chapter| chapter.end_position > start && chapter.start_position < end);
+    for chapter in chapters.iter_mut() {
+        chapter.start_position = chapter.start_position.clamp(start, end) - start;
+        chapter.end_position = chapter.end_position.clamp(start, end) - start;
+    }
+
+    *content = content[start..end].to_string();
+}
+
+fn is_start_marker(line: &str) -> bool {
+    let line = line.trim().to_uppercase();
+    (line.starts_with('*') && line.contains("START OF") && line.contains("PROJECT GUTENBERG"))
+        // Pre-2000s texts end their header with the small print instead
+        || line.contains("*END*THE SMALL PRINT")
+}
+
+fn is_end_marker(line: &str) -> bool {
+    let line = line.trim().to_uppercase();
+    (line.starts_with('*') && line.contains("END OF") && line.contains("PROJECT GUTENBERG"))
+        || line.starts_with("END OF THE PROJECT GUTENBERG")
+        || line.starts_with("END OF PROJECT GUTENBERG")
+}
+
+/// Title and author from the opening "The Project Gutenberg eBook of
+/// <title>, by <author>" line.
+fn banner(header: &[(usize, &str)]) -> (Option<String>, Option<String>) {
+    let Some(line) = header.iter().map(|(_, line)| line.trim()).find(|line| !line.is_empty()) else {
+        return (None, None);
+    };
+    let lower = line.to_ascii_lowercase();
+    let Some(index) = lower.find("project gutenberg ebook of ").or_else(|| lower.find("project gutenberg etext of ")) else {
+        return (None, None);
+    };
+
+    let rest = line[index + "project gutenberg ebook of ".len()..].trim();
+    match rest.rsplit_once(", by ") {
+        Some((title, author)) => (Some(title.trim().to_string()), Some(author.trim().to_string())),
+        None => (Some(rest.to_string()).filter(|title| !title.is_empty()), None),
+    }
+}
+
+/// Each line, line ending included, with the offset it starts at.
+fn lines_with_offsets(content: &str) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len();
+            (start, line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::xhtml::BlockKind;
+
+    const BOOK: &str = "\
+The Project Gutenberg eBook of Moby-Dick; or The Whale, by Herman Melville
+
+Title: Moby-Dick;
+       or The Whale
+Author: Herman Melville
+
+*** START OF THE PROJECT GUTENBERG EBOOK MOBY-DICK ***
+
+Call me Ishmael.
+
+*** END OF THE PROJECT GUTENBERG EBOOK MOBY-DICK ***
+Section 1. General Terms of Use
+";
+
+    fn chapter(id: &str, start: usize, end: usize) -> Chapter {
+        Chapter {
+            id: id.to_string(),
+            title: id.to_string(),
+            start_position: start,
+            end_position: end,
+            level: 0,
+            parent_id: None,
+            href: None,
+        }
+    }
+
+    fn paragraph(position: usize) -> Block {
+        Block {
+            position,
+            kind: BlockKind::Preformatted { text: String::new() },
+        }
+    }
+
+    #[test]
+    fn book_sits_between_the_markers() {
+        let boilerplate = find(BOOK).unwrap();
+
+        assert_eq!(&BOOK[boilerplate.start..boilerplate.end], "Call me Ishmael.");
+        assert_eq!(boilerplate.title.as_deref(), Some("Moby-Dick; or The Whale"));
+        assert_eq!(boilerplate.author.as_deref(), Some("Herman Melville"));
+
+        let appendices = boilerplate.appendices(BOOK);
+        assert_eq!(appendices[0].title, HEADER_TITLE);
+        assert!(appendices[0].content.ends_with("MOBY-DICK ***"));
+        assert!(appendices[1].content.starts_with("*** END OF"));
+        // Offsets into the whole text move back by all of the header, blank lines included
+        assert_eq!(appendices[0].source, 0..boilerplate.start);
+        assert!(appendices[0].content.len() < boilerplate.start);
+    }
+
+    #[test]
+    fn marker_wordings() {
+        for (start, end) in [
+            ("*** START OF THIS PROJECT GUTENBERG EBOOK ALICE ***", "*** END OF THIS PROJECT GUTENBERG EBOOK ALICE ***"),
+            ("***START OF THE PROJECT GUTENBERG EBOOK ALICE***", "***END OF THE PROJECT GUTENBERG EBOOK ALICE***"),
+            ("*** start of the project gutenberg ebook alice ***", "End of the Project Gutenberg EBook of Alice"),
+            ("*END*THE SMALL PRINT! FOR PUBLIC DOMAIN ETEXTS*Ver.04.29.93*END*", "End of Project Gutenberg's Alice"),
+        ] {
+            let content = format!("Header\n{}\n\nAlice was beginning\n\n{}\nLicence\n", start, end);
+            let boilerplate = find(&content).unwrap_or_else(|| panic!("no markers in {:?}", start));
+            assert_eq!(&content[boilerplate.start..boilerplate.end], "Alice was beginning", "{}", start);
+        }
+    }
+
+    #[test]
+    fn missing_end_marker_keeps_the_rest() {
+        let content = "*** START OF THE PROJECT GUTENBERG EBOOK ALICE ***\nAlice was beginning\n\n";
+        let boilerplate = find(content).unwrap();
+
+        assert_eq!(&content[boilerplate.start..boilerplate.end], "Alice was beginning");
+        assert_eq!(boilerplate.title, None);
+    }
+
+    #[test]
+    fn books_without_markers_are_left_alone() {
+        assert!(find("Chapter 1\n\nThe Project Gutenberg eBook of Alice is mentioned here.\n").is_none());
+        // The markers are lines of their own, not phrases in the text
+        assert!(find("He read the START OF THE PROJECT GUTENBERG EBOOK aloud.\n").is_none());
+        assert!(find("").is_none());
+    }
+
+    #[test]
+    fn banner_names_title_and_author() {
+        let header = |line| vec![(0, "\n"), (1, line)];
+
+        assert_eq!(
+            banner(&header("The Project Gutenberg eBook of Pride and Prejudice, by Jane Austen\n")),
+            (Some("Pride and Prejudice".to_string()), Some("Jane Austen".to_string()))
+        );
+        // Only the last ", by " separates the author
+        assert_eq!(
+            banner(&header("The Project Gutenberg Etext of Stand by, by Me, by Anonymous\n")),
+            (Some("Stand by, by Me".to_string()), Some("Anonymous".to_string()))
+        );
+        assert_eq!(
+            banner(&header("The Project Gutenberg EBook of Beowulf\n")),
+            (Some("Beowulf".to_string()), None)
+        );
+        assert_eq!(banner(&header("Produced by volunteers\n")), (None, None));
+    }
+
+    #[test]
+    fn header_fields_win_over_the_banner() {
+        let content = "The Project Gutenberg eBook of Emma, by J. Austen\n\nTitle: Emma\nAuthor: Jane Austen\n\n\
+            *** START OF THE PROJECT GUTENBERG EBOOK EMMA ***\nEmma Woodhouse\n";
+        let boilerplate = find(content).unwrap();
+
+        assert_eq!(boilerplate.title.as_deref(), Some("Emma"));
+        assert_eq!(boilerplate.author.as_deref(), Some("Jane Austen"));
+    }
+
+    #[test]
+    fn structure_moves_with_the_content() {
+        let mut content = BOOK.to_string();
+        let boilerplate = find(&content).unwrap();
+        let (start, end) = (boilerplate.start, boilerplate.end);
+
+        let mut blocks = vec![paragraph(0), paragraph(start), paragraph(start + 8), paragraph(end + 1)];
+        let mut chapters = vec![
+            chapter("header", 0, start),
+            chapter("straddles", 10, start + 8),
+            chapter("book", start + 8, end),
+            chapter("licence", end, BOOK.len()),
+        ];
+        strip_structured(&mut content, &mut blocks, &mut chapters, &boilerplate);
+
+        assert_eq!(content, "Call me Ishmael.");
+        assert_eq!(blocks.iter().map(|b| b.position).collect::<Vec<_>>(), [0, 8]);
+        assert_eq!(
+            chapters.iter().map(|c| (c.id.as_str(), c.start_position, c.end_position)).collect::<Vec<_>>(),
+            [("straddles", 0, 8), ("book", 8, 16)]
+        );
+    }
+}
@@ -0,0 +1,1360 @@
+use crate::error::{Error, Result};
+use crate::{Chapter, Document, DocumentKind, ReadingDirection};
+use super::progress::Progress;
+use super::{content_hash, DocumentParser, FileSignature, ParseOptions};
+use super::xhtml::{BlockKind, ContentBuilder};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// Text compression schemes named in record 0
+const NO_COMPRESSION: u16 = 1;
+const PALMDOC_COMPRESSION: u16 = 2;
+const HUFF_CDIC_COMPRESSION: u16 = 17480;
+
+// Marks an unused record index in the MOBI header
+const NO_INDEX: u32 = 0xFFFF_FFFF;
+
+// EXTH metadata record types
+const EXTH_AUTHOR: u32 = 100;
+const EXTH_ASIN: u32 = 113;
+const EXTH_KF8_BOUNDARY: u32 = 121;
+const EXTH_COVER_OFFSET: u32 = 201;
+const EXTH_UPDATED_TITLE: u32 = 503;
+
+// HUFF/CDIC phrases may refer to other phrases; real files nest a few levels
+const MAX_PHRASE_DEPTH: usize = 32;
+
+// A text record holds 4 KiB once decompressed. Far more than that means
+// phrases crafted to expand into each other.
+const MAX_RECORD_TEXT: usize = 64 * 1024;
+
+// Far beyond any real book, but small enough to stay in memory
+const MAX_TEXT_LENGTH: usize = 256 * 1024 * 1024;
+
+/// The records of a PalmDB container, which every MOBI/AZW3 file is.
+struct PalmDb<'a> {
+    data: &'a [u8],
+    name: String,
+    offsets: Vec<usize>,
+}
+
+impl<'a> PalmDb<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        let count = be16(data, 76).ok_or_else(|| Error::corrupt("Not a MOBI file: truncated header"))? as usize;
+        let offsets = (0..count)
+            .map(|i| be32(data, 78 + i * 8).map(|offset| offset as usize))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Error::corrupt("Not a MOBI file: truncated record list"))?;
+        let name = data[..32].split(|&b| b == 0).next().unwrap_or_default();
+
+        Ok(Self {
+            data,
+            name: String::from_utf8_lossy(name).replace('_', " "),
+            offsets,
+        })
+    }
+
+    fn record(&self, index: usize) -> Option<&'a [u8]> {
+        let start = *self.offsets.get(index)?;
+        let end = self.offsets.get(index + 1).copied().unwrap_or(self.data.len());
+        self.data.get(start..end.max(start))
+    }
+}
+
+/// Record 0 of a book section. A file holds a MOBI 6 section, a KF8 section,
+/// or both one after the other; record indices here are absolute.
+struct MobiHeader {
+    compression: u16,
+    text_length: usize,
+    text_records: std::ops::RangeInclusive<usize>,
+    encoding: &'static Encoding,
+    version: u32,
+    full_name: Option<String>,
+    first_image: Option<usize>,
+    huffman_records: Option<(usize, usize)>, // (first, count)
+    extra_flags: u16,
+    ncx_index: Option<usize>,
+    fragment_index: Option<usize>, // KF8 only, as are the two below
+    skeleton_index: Option<usize>,
+    fdst_index: Option<usize>,
+    exth: HashMap<u32, Vec<Vec<u8>>>,
+}
+
+impl MobiHeader {
+    fn parse(db: &PalmDb, base: usize) -> Result<Self> {
+        let record = db.record(base).ok_or_else(|| Error::corrupt("MOBI header record is missing"))?;
+        if record.get(16..20) != Some(b"MOBI".as_slice()) {
+            return Err(Error::corrupt("Not a MOBI file: missing MOBI header"));
+        }
+        if be16(record, 12).unwrap_or(0) != 0 {
+            return Err(Error::DrmProtected);
+        }
+
+        let header_length = be32(record, 20).unwrap_or(0) as usize;
+        let field = |offset: usize| {
+            // Older, shorter headers simply don't have the later fields
+            if offset + 4 <= 16 + header_length {
+                be32(record, offset)
+            } else {
+                None
+            }
+        };
+        let index = |offset: usize| {
+            field(offset)
+                .filter(|&i| i != NO_INDEX && i != 0)
+                .map(|i| base + i as usize)
+        };
+
+        let encoding = match field(0x1C) {
+            Some(65001) => UTF_8,
+            _ => WINDOWS_1252,
+        };
+        let version = field(0x24).unwrap_or(0);
+        let text_record_count = be16(record, 8).unwrap_or(0) as usize;
+
+        let full_name = match (field(0x54), field(0x58)) {
+            (Some(offset), Some(length)) => record
+                .get(offset as usize..(offset + length) as usize)
+                .map(|name| encoding.decode_without_bom_handling(name).0.trim().to_string())
+                .filter(|name| !name.is_empty()),
+            _ => None,
+        };
+
+        let huffman_records = match (index(0x70), field(0x74)) {
+            (Some(first), Some(count)) if count > 0 => Some((first, count as usize)),
+            _ => None,
+        };
+
+        let extra_flags = if version >= 5 && header_length >= 0xE4 {
+            be16(record, 0xF2).unwrap_or(0)
+        } else {
+            0
+        };
+
+        let exth = if field(0x80).is_some_and(|flags| flags & 0x40 != 0) {
+            parse_exth(record.get(16 + header_length..).unwrap_or_default())
+        } else {
+            HashMap::new()
+        };
+
+        let kf8 = version >= 8;
+        Ok(Self {
+            compression: be16(record, 0).unwrap_or(NO_COMPRESSION),
+            text_length: be32(record, 4).unwrap_or(0) as usize,
+            text_records: base + 1..=base + text_record_count,
+            encoding,
+            version,
+            full_name,
+            first_image: index(0x6C),
+            huffman_records,
+            extra_flags,
+            ncx_index: index(0xF4),
+            fragment_index: index(0xF8).filter(|_| kf8),
+            skeleton_index: index(0xFC).filter(|_| kf8),
+            fdst_index: index(0xC0).filter(|_| kf8),
+            exth,
+        })
+    }
+
+    fn exth_string(&self, record_type: u32) -> Option<String> {
+        self.exth
+            .get(&record_type)?
+            .iter()
+            .map(|value| self.encoding.decode_without_bom_handling(value).0.trim().to_string())
+            .find(|value| !value.is_empty())
+    }
+
+    fn exth_number(&self, record_type: u32) -> Option<u32> {
+        self.exth.get(&record_type)?.first().and_then(|value| be32(value, 0))
+    }
+
+    /// Every author, as books with several creators list one per record.
+    fn authors(&self) -> Option<String> {
+        let authors: Vec<String> = self
+            .exth
+            .get(&EXTH_AUTHOR)?
+            .iter()
+            .map(|value| self.encoding.decode_without_bom_handling(value).0.trim().to_string())
+            .filter(|author| !author.is_empty())
+            .collect();
+        (!authors.is_empty()).then(|| authors.join(", "))
+    }
+
+    /// The record holding the `number`th image (1-based, as used by
+    /// `recindex` and `kindle:embed` references).
+    fn image_record(&self, number: usize) -> Option<usize> {
+        Some(self.first_image? + number.checked_sub(1)?)
+    }
+}
+
+/// Opens the book section to read: KF8 when the file has it, since its markup
+/// is closer to the original, otherwise MOBI 6.
+fn book_header(db: &PalmDb) -> Result<MobiHeader> {
+    let header = MobiHeader::parse(db, 0)?;
+    if header.version >= 8 {
+        return Ok(header);
+    }
+
+    // Combined MOBI 6 + KF8 files point at their KF8 half
+    if let Some(boundary) = header.exth_number(EXTH_KF8_BOUNDARY).filter(|&b| b != NO_INDEX) {
+        if let Ok(kf8) = MobiHeader::parse(db, boundary as usize) {
+            if kf8.version >= 8 && kf8.skeleton_index.is_some() {
+                return Ok(kf8);
+            }
+        }
+    }
+
+    Ok(header)
+}
+
+pub struct MobiParser;
+
+impl DocumentParser for MobiParser {
+    fn detect(signature: &FileSignature) -> bool {
+        // PalmDB type and creator
+        signature.head.get(60..68) == Some(b"BOOKMOBI".as_slice())
+    }
+
+    fn parse(file_path: &PathBuf, _options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        parse_mobi(file_path, progress)
+    }
+
+    fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+        read_resource(file_path, resource_path)
+    }
+}
+
+pub fn parse_mobi(file_path: &PathBuf, progress: &Progress) -> Result<Document> {
+    progress.update("Opening", 0.0)?;
+    let data = fs::read(file_path).map_err(|e| Error::io(file_path, e))?;
+    let content_hash = content_hash(file_path)?;
+    let db = PalmDb::parse(&data)?;
+    let header = book_header(&db)?;
+    let kf8 = header.version >= 8;
+
+    progress.update("Decompressing", 0.0)?;
+    let text = read_text(&db, &header)?;
+    let files = if kf8 { kf8_files(&db, &header, text) } else { mobi6_files(&db, &header, text) };
+
+    // Convert every part into blocks plus a plain-text projection
+    let mut builder = ContentBuilder::new();
+    let mut part_chapters = Vec::new();
+    let mut anchor_offsets: HashMap<String, usize> = HashMap::new();
+
+    for (index, file) in files.parts.iter().enumerate() {
+        progress.update("Converting chapters", index as f32 / files.parts.len() as f32)?;
+        let first_block = builder.blocks.len();
+        let html = header.encoding.decode_without_bom_handling(&file.html).0;
+        anchor_offsets.extend(builder.add_xhtml(&html, &file.name));
+
+        let Some(start_position) = builder.blocks.get(first_block).map(|block| block.position) else {
+            continue;
+        };
+
+        // Only used when the book has no table of contents
+        let title = builder.blocks[first_block..]
+            .iter()
+            .find_map(|block| match &block.kind {
+                BlockKind::Heading { spans, .. } => {
+                    Some(collapse_whitespace(&spans.iter().map(|s| s.text.as_str()).collect::<String>()))
+                }
+                _ => None,
+            })
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| format!("Chapter {}", part_chapters.len() + 1));
+
+        part_chapters.push(Chapter {
+            id: format!("part_{}", index),
+            title,
+            start_position,
+            end_position: builder.content.len(),
+            level: 0,
+            parent_id: None,
+            href: Some(file.name.clone()),
+        });
+    }
+
+    let (content, blocks) = builder.finish();
+
+    let mut chapters = resolve_toc(&files.toc, &anchor_offsets, content.len());
+    if chapters.is_empty() {
+        chapters = part_chapters;
+    }
+
+    let title = header
+        .exth_string(EXTH_UPDATED_TITLE)
+        .or_else(|| header.full_name.clone())
+        .or_else(|| Some(db.name.trim().to_string()).filter(|name| !name.is_empty()))
+        .unwrap_or_else(|| "Unknown Title".to_string());
+    let author = header.authors();
+    let asin = header.exth_string(EXTH_ASIN);
+
+    // Estimate pages (rough calculation: ~500 words per page)
+    let word_count = content.split_whitespace().count();
+    let estimated_pages = (word_count / 500).max(1);
+
+    let cover_image = header
+        .exth_number(EXTH_COVER_OFFSET)
+        .filter(|&offset| offset != NO_INDEX)
+        .and_then(|offset| header.image_record(offset as usize + 1))
+        .and_then(|record| db.record(record))
+        .map(image_data_url);
+
+    Ok(Document {
+        id: content_hash.clone(),
+        title,
+        author,
+        file_path: file_path.clone(),
+        file_type: if kf8 { "azw3" } else { "mobi" }.to_string(),
+        content_hash,
+        kind: DocumentKind::Text,
+        reading_direction: ReadingDirection::LeftToRight,
+        encoding: None,
+        asin,
+        series: None,
+        series_index: None,
+        genres: Vec::new(),
+        description: None,
+        content,
+        blocks,
+        current_position: 0,
+        total_pages: estimated_pages,
+        chapters,
+        pages: Vec::new(),
+        appendices: Vec::new(),
+        cover_image,
+    })
+}
+
+/// Reads an image referenced by an `Image` block (`images/<number>`) as a data URL.
+pub fn read_resource(file_path: &PathBuf, resource_path: &str) -> Result<String> {
+    let data = fs::read(file_path).map_err(|e| Error::io(file_path, e))?;
+    let db = PalmDb::parse(&data)?;
+    let header = book_header(&db)?;
+
+    resource_path
+        .strip_prefix("images/")
+        .and_then(|number| number.parse::<usize>().ok())
+        .and_then(|number| header.image_record(number))
+        .and_then(|record| db.record(record))
+        .map(image_data_url)
+        .ok_or(Error::NotFound("Resource"))
+}
+
+fn image_data_url(data: &[u8]) -> String {
+    let mime_type = if data.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.starts_with(b"BM") {
+        "image/bmp"
+    } else {
+        "image/jpeg"
+    };
+    format!("data:{};base64,{}", mime_type, BASE64.encode(data))
+}
+
+/// A table of contents entry from the NCX index, pointing at an anchor that
+/// was inserted into the markup.
+struct TocEntry {
+    label: String,
+    target: Option<(String, String)>, // (part file, anchor id)
+    level: usize,
+    parent: Option<usize>, // Index into the entry list
+}
+
+/// The book's markup split into files, ready for the content builder.
+struct BookFiles {
+    parts: Vec<Part>,
+    toc: Vec<TocEntry>,
+}
+
+struct Part {
+    name: String,
+    html: Vec<u8>,
+}
+
+fn part_name(index: usize) -> String {
+    format!("part{:04}.html", index)
+}
+
+/// Concatenates and decompresses the text records.
+fn read_text(db: &PalmDb, header: &MobiHeader) -> Result<Vec<u8>> {
+    let mut huffman = match (header.compression, header.huffman_records) {
+        (HUFF_CDIC_COMPRESSION, Some((first, count))) => Some(HuffCdic::load(db, first, count)?),
+        (HUFF_CDIC_COMPRESSION, None) => return Err(Error::corrupt("MOBI file is missing its Huffman tables")),
+        _ => None,
+    };
+
+    // The declared length is only trusted to cut the text short, never to
+    // size an allocation
+    let mut text = Vec::new();
+    for index in header.text_records.clone() {
+        let Some(record) = db.record(index) else {
+            break;
+        };
+        if header.text_length > 0 && text.len() >= header.text_length {
+            break;
+        }
+        if text.len() > MAX_TEXT_LENGTH {
+            return Err(Error::corrupt("MOBI text is too large"));
+        }
+        let record = &record[..record.len() - trailing_entries_size(record, header.extra_flags)];
+
+        match header.compression {
+            NO_COMPRESSION => text.extend_from_slice(record),
+            PALMDOC_COMPRESSION => palmdoc_decompress(record, &mut text),
+            HUFF_CDIC_COMPRESSION => {
+                if let Some(huffman) = huffman.as_mut() {
+                    text.extend(huffman.decompress(record, 0)?);
+                }
+            }
+            other => return Err(Error::corrupt(format!("Unsupported MOBI compression type: {}", other))),
+        }
+    }
+
+    if header.text_length > 0 {
+        text.truncate(header.text_length);
+    }
+    Ok(text)
+}
+
+/// Size of the extra data some writers append to each text record, which is
+/// not part of the compressed text.
+fn trailing_entries_size(record: &[u8], flags: u16) -> usize {
+    let mut size = 0;
+    for bit in 1..16 {
+        if flags & (1 << bit) != 0 {
+            size += backward_int(&record[..record.len().saturating_sub(size)]);
+        }
+    }
+    // Multibyte character overlap, whose own size is in its low bits
+    if flags & 1 != 0 {
+        if let Some(&last) = record.len().checked_sub(size + 1).and_then(|i| record.get(i)) {
+            size += (last & 0x3) as usize + 1;
+        }
+    }
+    size.min(record.len())
+}
+
+/// A variable-width integer read backwards from the end of `data`.
+fn backward_int(data: &[u8]) -> usize {
+    let mut value = 0;
+    for &byte in &data[data.len().saturating_sub(4)..] {
+        if byte & 0x80 != 0 {
+            value = 0;
+        }
+        value = (value << 7) | (byte & 0x7F) as usize;
+    }
+    value
+}
+
+/// A variable-width integer read forwards, returning bytes consumed and value.
+fn forward_int(data: &[u8]) -> (usize, u32) {
+    let mut value = 0u32;
+    for (i, &byte) in data.iter().take(5).enumerate() {
+        value = (value << 7) | (byte & 0x7F) as u32;
+        if byte & 0x80 != 0 {
+            return (i + 1, value);
+        }
+    }
+    (data.len().min(5), value)
+}
+
+/// PalmDOC's LZ77 variant.
+fn palmdoc_decompress(data: &[u8], out: &mut Vec<u8>) {
+    let start = out.len();
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        i += 1;
+        match byte {
+            // The next 1-8 bytes are literals
+            0x01..=0x08 => {
+                let end = (i + byte as usize).min(data.len());
+                out.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+            0x00 | 0x09..=0x7F => out.push(byte),
+            // A distance/length pair copying earlier output
+            0x80..=0xBF => {
+                let Some(&next) = data.get(i) else {
+                    break;
+                };
+                i += 1;
+                let pair = (((byte as usize) << 8) | next as usize) & 0x3FFF;
+                let distance = pair >> 3;
+                let length = (pair & 0x7) + 3;
+                if distance == 0 || distance > out.len() - start {
+                    continue;
+                }
+                for _ in 0..length {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+            // A space followed by a character
+            0xC0..=0xFF => {
+                out.push(b' ');
+                out.push(byte ^ 0x80);
+            }
+        }
+    }
+}
+
+/// The HUFF/CDIC dictionary compression used by some larger books.
+struct HuffCdic {
+    lookup: Vec<(u32, bool, u64)>, // (code length, terminal, max code) by top byte
+    min_codes: [u64; 33],
+    max_codes: [u64; 33],
+    phrases: Vec<(Vec<u8>, bool)>, // (bytes, already decompressed)
+}
+
+impl HuffCdic {
+    fn load(db: &PalmDb, first: usize, count: usize) -> Result<Self> {
+        let invalid = || Error::corrupt("MOBI file has invalid Huffman tables");
+        let huff = db.record(first).ok_or_else(invalid)?;
+        if !huff.starts_with(b"HUFF") {
+            return Err(invalid());
+        }
+
+        let lookup_offset = be32(huff, 8).ok_or_else(invalid)? as usize;
+        let codes_offset = be32(huff, 12).ok_or_else(invalid)? as usize;
+        let lookup = (0..256)
+            .map(|i| {
+                let value = be32(huff, lookup_offset + i * 4)?;
+                let length = value & 0x1F;
+                if length == 0 {
+                    return None;
+                }
+                let max_code = (((value >> 8) as u64 + 1) << (32 - length)) - 1;
+                Some((length, value & 0x80 != 0, max_code))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+
+        let mut min_codes = [0u64; 33];
+        let mut max_codes = [u32::MAX as u64; 33];
+        for length in 1..=32 {
+            let min = be32(huff, codes_offset + (length - 1) * 8).ok_or_else(invalid)?;
+            let max = be32(huff, codes_offset + (length - 1) * 8 + 4).ok_or_else(invalid)?;
+            min_codes[length] = (min as u64) << (32 - length);
+            max_codes[length] = ((max as u64 + 1) << (32 - length)) - 1;
+        }
+
+        let mut phrases = Vec::new();
+        for index in first + 1..first + count {
+            let cdic = db.record(index).ok_or_else(invalid)?;
+            if !cdic.starts_with(b"CDIC") {
+                return Err(invalid());
+            }
+            let total = be32(cdic, 8).ok_or_else(invalid)? as usize;
+            let bits = be32(cdic, 12).ok_or_else(invalid)?.min(31);
+            let in_record = (1usize << bits).min(total.saturating_sub(phrases.len()));
+
+            for i in 0..in_record {
+                let offset = be16(cdic, 16 + i * 2).ok_or_else(invalid)? as usize;
+                let length = be16(cdic, 16 + offset).ok_or_else(invalid)?;
+                let start = 18 + offset;
+                let bytes = cdic
+                    .get(start..start + (length & 0x7FFF) as usize)
+                    .ok_or_else(invalid)?;
+                phrases.push((bytes.to_vec(), length & 0x8000 != 0));
+            }
+        }
+
+        Ok(Self { lookup, min_codes, max_codes, phrases })
+    }
+
+    fn decompress(&mut self, data: &[u8], depth: usize) -> Result<Vec<u8>> {
+        let invalid = || Error::corrupt("MOBI text could not be decompressed");
+        if depth > MAX_PHRASE_DEPTH {
+            return Err(invalid());
+        }
+
+        let mut padded = data.to_vec();
+        padded.extend_from_slice(&[0; 8]);
+        let mut bits_left = data.len() as i64 * 8;
+        let mut position = 0;
+        let mut window = be64(&padded, 0).ok_or_else(invalid)?;
+        let mut available: i64 = 32;
+        let mut out = Vec::new();
+
+        loop {
+            if available <= 0 {
+                position += 4;
+                window = be64(&padded, position).ok_or_else(invalid)?;
+                available += 32;
+            }
+            let code = (window >> available) & 0xFFFF_FFFF;
+            let (mut length, terminal, mut max_code) = self.lookup[(code >> 24) as usize];
+            if !terminal {
+                while (length as usize) < 32 && code < self.min_codes[length as usize] {
+                    length += 1;
+                }
+                max_code = self.max_codes[length as usize];
+            }
+
+            available -= length as i64;
+            bits_left -= length as i64;
+            if bits_left < 0 {
+                break;
+            }
+
+            let index = (max_code.checked_sub(code).ok_or_else(invalid)? >> (32 - length)) as usize;
+            let (phrase, done) = self.phrases.get(index).cloned().ok_or_else(invalid)?;
+            let phrase = if done {
+                phrase
+            } else {
+                let expanded = self.decompress(&phrase, depth + 1)?;
+                self.phrases[index] = (expanded.clone(), true);
+                expanded
+            };
+            out.extend_from_slice(&phrase);
+            if out.len() > MAX_RECORD_TEXT {
+                return Err(invalid());
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+fn parse_exth(data: &[u8]) -> HashMap<u32, Vec<Vec<u8>>> {
+    let mut records: HashMap<u32, Vec<Vec<u8>>> = HashMap::new();
+    if !data.starts_with(b"EXTH") {
+        return records;
+    }
+
+    let count = be32(data, 8).unwrap_or(0);
+    let mut offset = 12;
+    for _ in 0..count {
+        let (Some(record_type), Some(length)) = (be32(data, offset), be32(data, offset + 4)) else {
+            break;
+        };
+        let length = length as usize;
+        let Some(value) = data.get(offset + 8..offset + length.max(8)) else {
+            break;
+        };
+        records.entry(record_type).or_default().push(value.to_vec());
+        offset += length.max(8);
+    }
+    records
+}
+
+/// One entry of an INDX table: its identifying text and tag values.
+struct IndexEntry {
+    ident: String,
+    tags: HashMap<u8, Vec<u32>>,
+}
+
+impl IndexEntry {
+    fn tag(&self, tag: u8, index: usize) -> Option<u32> {
+        self.tags.get(&tag)?.get(index).copied()
+    }
+}
+
+/// Reads an INDX table (the NCX, skeleton and fragment indexes) and the
+/// strings its entries refer to.
+fn read_index(db: &PalmDb, first: usize) -> Option<(Vec<IndexEntry>, HashMap<u32, String>)> {
+    let header = db.record(first)?;
+    if !header.starts_with(b"INDX") {
+        return None;
+    }
+    let header_length = be32(header, 4)? as usize;
+    let record_count = be32(header, 24)? as usize;
+    let string_record_count = be32(header, 52).unwrap_or(0) as usize;
+
+    // TAGX describes how each entry's control bytes map to tag values
+    let tagx = header.get(header_length..)?;
+    if !tagx.starts_with(b"TAGX") {
+        return None;
+    }
+    let tagx_length = be32(tagx, 4)? as usize;
+    let control_bytes = be32(tagx, 8)? as usize;
+    let tag_table: Vec<[u8; 4]> = tagx
+        .get(12..tagx_length)?
+        .chunks_exact(4)
+        .map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]])
+        .collect();
+
+    let mut entries = Vec::new();
+    for index in first + 1..=first + record_count {
+        let record = db.record(index)?;
+        if !record.starts_with(b"INDX") {
+            continue;
+        }
+        let idxt = be32(record, 20)? as usize;
+        let count = be32(record, 24)? as usize;
+        let offsets: Vec<usize> = (0..count)
+            .map(|i| be16(record, idxt + 4 + i * 2).map(|o| o as usize))
+            .collect::<Option<_>>()?;
+
+        for (i, &start) in offsets.iter().enumerate() {
+            let end = offsets.get(i + 1).copied().unwrap_or(idxt);
+            let entry = record.get(start..end)?;
+            let ident_length = *entry.first()? as usize;
+            let ident = entry.get(1..1 + ident_length)?;
+            let rest = entry.get(1 + ident_length..)?;
+            entries.push(IndexEntry {
+                ident: String::from_utf8_lossy(ident).into_owned(),
+                tags: tag_values(control_bytes, &tag_table, rest),
+            });
+        }
+    }
+
+    let mut strings = HashMap::new();
+    for i in 0..string_record_count {
+        let Some(record) = db.record(first + record_count + 1 + i) else {
+            break;
+        };
+        let mut offset = 0;
+        while offset < record.len() {
+            let (consumed, length) = forward_int(&record[offset..]);
+            if consumed == 0 {
+                break;
+            }
+            let start = offset + consumed;
+            let end = (start + length as usize).min(record.len());
+            let text = String::from_utf8_lossy(&record[start..end]).into_owned();
+            strings.insert(((i as u32) << 16) | offset as u32, text);
+            offset = end;
+        }
+    }
+
+    Some((entries, strings))
+}
+
+/// Decodes an index entry's tag values. Each tag's presence and count is
+/// encoded in the control bytes under the tag's mask.
+fn tag_values(control_bytes: usize, tag_table: &[[u8; 4]], data: &[u8]) -> HashMap<u8, Vec<u32>> {
+    let (controls, mut data) = data.split_at(control_bytes.min(data.len()));
+    let mut control = 0;
+    // (tag, value count, byte count, values per entry)
+    let mut present = Vec::new();
+
+    for &[tag, per_entry, mask, end_flag] in tag_table {
+        if end_flag == 1 {
+            control += 1;
+            continue;
+        }
+        let Some(&byte) = controls.get(control) else {
+            break;
+        };
+        let mut value = byte & mask;
+        if value == 0 {
+            continue;
+        }
+        if value == mask && mask.count_ones() > 1 {
+            // The values' total size in bytes follows instead of a count
+            let (consumed, bytes) = forward_int(data);
+            data = &data[consumed..];
+            present.push((tag, None, bytes as usize, per_entry as usize));
+        } else {
+            let mut mask = mask;
+            while mask & 1 == 0 {
+                mask >>= 1;
+                value >>= 1;
+            }
+            present.push((tag, Some(value as usize), 0, per_entry as usize));
+        }
+    }
+
+    let mut tags = HashMap::new();
+    for (tag, count, bytes, per_entry) in present {
+        let mut values = Vec::new();
+        match count {
+            Some(count) => {
+                for _ in 0..count * per_entry {
+                    let (consumed, value) = forward_int(data);
+                    if consumed == 0 {
+                        break;
+                    }
+                    data = &data[consumed..];
+                    values.push(value);
+                }
+            }
+            None => {
+                let mut read = 0;
+                while read < bytes {
+                    let (consumed, value) = forward_int(data);
+                    if consumed == 0 {
+                        break;
+                    }
+                    data = &data[consumed..];
+                    read += consumed;
+                    values.push(value);
+                }
+            }
+        }
+        tags.insert(tag, values);
+    }
+    tags
+}
+
+/// MOBI 6 markup is one HTML stream addressed by byte offset ("filepos").
+/// Anchors go in at every linked offset, then the stream is split at page
+/// breaks so each part acts like an EPUB spine file.
+fn mobi6_files(db: &PalmDb, header: &MobiHeader, mut text: Vec<u8>) -> BookFiles {
+    let ncx = header.ncx_index.and_then(|index| read_index(db, index));
+
+    let mut targets: Vec<usize> = find_attributes(&text, "filepos")
+        .iter()
+        .filter_map(|attribute| attribute.value.parse().ok())
+        .collect();
+    if let Some((entries, _)) = &ncx {
+        targets.extend(entries.iter().filter_map(|entry| entry.tag(1, 0)).map(|o| o as usize));
+    }
+
+    let anchors = targets.iter().map(|&offset| (offset, format!("filepos{}", offset))).collect();
+    let positions = insert_anchors(&mut text, anchors);
+
+    // Split before every page break
+    let mut splits: Vec<usize> = find_ignoring_ascii_case(&text, b"<mbp:pagebreak");
+    splits.retain(|&split| split > 0);
+    let part_of = |position: usize| splits.partition_point(|&split| split <= position);
+
+    let toc = ncx
+        .map(|(entries, strings)| {
+            toc_entries(&entries, &strings, |entry| {
+                let id = format!("filepos{}", entry.tag(1, 0)?);
+                let position = *positions.get(&id)?;
+                Some((part_name(part_of(position)), id))
+            })
+        })
+        .unwrap_or_default();
+
+    // Parsers keep the unknown <mbp:pagebreak/> open around what follows, so
+    // each part starts after the tag
+    let starts = std::iter::once(0).chain(splits.iter().map(|&split| {
+        text[split..].iter().position(|&b| b == b'>').map_or(text.len(), |end| split + end + 1)
+    }));
+    let ends = splits.iter().copied().chain([text.len()]);
+    let parts = starts
+        .zip(ends)
+        .enumerate()
+        .map(|(index, (start, end))| {
+            let html = &text[start.min(end)..end];
+            let html = replace_attributes(html, "filepos", |value| {
+                let id = format!("filepos{}", value.parse::<usize>().ok()?);
+                let target = part_of(*positions.get(&id)?);
+                Some(format!("href=\"{}#{}\"", part_name(target), id))
+            });
+            let html = replace_attributes(&html, "recindex", |value| {
+                Some(format!("src=\"images/{:05}\"", value.parse::<usize>().ok()?))
+            });
+            Part { name: part_name(index), html }
+        })
+        .collect();
+
+    BookFiles { parts, toc }
+}
+
+/// KF8 markup is stored as skeleton files with fragments to insert into
+/// them; positions are given as (fragment, offset) pairs.
+fn kf8_files(db: &PalmDb, header: &MobiHeader, text: Vec<u8>) -> BookFiles {
+    // The first flow is the book's markup; the others hold CSS and SVG
+    let flow_end = header
+        .fdst_index
+        .and_then(|index| db.record(index))
+        .filter(|fdst| fdst.starts_with(b"FDST"))
+        .and_then(|fdst| be32(fdst, 4).and_then(|table| be32(fdst, table as usize + 4)))
+        .map_or(text.len(), |end| (end as usize).min(text.len()));
+    let text = &text[..flow_end];
+
+    let skeletons = header.skeleton_index.and_then(|index| read_index(db, index)).map(|(e, _)| e);
+    let fragments = header.fragment_index.and_then(|index| read_index(db, index)).map(|(e, _)| e);
+    let (Some(skeletons), Some(fragments)) = (skeletons, fragments) else {
+        return BookFiles {
+            parts: vec![Part { name: part_name(0), html: text.to_vec() }],
+            toc: Vec::new(),
+        };
+    };
+
+    // Rebuild each file, remembering where each fragment landed in it
+    let mut parts = Vec::new();
+    let mut fragment_positions: Vec<(usize, usize)> = Vec::new(); // (part, offset)
+    let mut next_fragment = 0;
+    for skeleton in &skeletons {
+        let (Some(start), Some(length)) = (skeleton.tag(6, 0), skeleton.tag(6, 1)) else {
+            continue;
+        };
+        let (start, length) = (start as usize, length as usize);
+        let Some(mut html) = text.get(start..start + length).map(<[u8]>::to_vec) else {
+            continue;
+        };
+        let mut cursor = start + length;
+
+        for _ in 0..skeleton.tag(1, 0).unwrap_or(0) {
+            let Some(fragment) = fragments.get(next_fragment) else {
+                break;
+            };
+            next_fragment += 1;
+            let insert_at = fragment
+                .ident
+                .parse::<usize>()
+                .unwrap_or(start)
+                .saturating_sub(start)
+                .min(html.len());
+            let length = fragment.tag(6, 1).unwrap_or(0) as usize;
+            let Some(body) = text.get(cursor..cursor + length) else {
+                break;
+            };
+            html.splice(insert_at..insert_at, body.iter().copied());
+            fragment_positions.push((parts.len(), insert_at));
+            cursor += length;
+        }
+
+        parts.push(Part { name: part_name(parts.len()), html });
+    }
+
+    let locate = |fid: u32, offset: u32| -> Option<(usize, usize, String)> {
+        let (part, start) = *fragment_positions.get(fid as usize)?;
+        Some((part, start + offset as usize, format!("pos_{}_{}", fid, offset)))
+    };
+
+    let ncx = header.ncx_index.and_then(|index| read_index(db, index));
+
+    // Anchor every link target and TOC entry
+    let mut anchors: Vec<Vec<(usize, String)>> = vec![Vec::new(); parts.len()];
+    for part in &parts {
+        for attribute in find_attributes(&part.html, "href") {
+            if let Some((target, offset, id)) = kindle_pos(&attribute.value).and_then(|(f, o)| locate(f, o)) {
+                anchors[target].push((offset, id));
+            }
+        }
+    }
+    if let Some((entries, _)) = &ncx {
+        for entry in entries {
+            if let Some((target, offset, id)) = entry.tag(6, 0).zip(entry.tag(6, 1)).and_then(|(f, o)| locate(f, o)) {
+                anchors[target].push((offset, id));
+            }
+        }
+    }
+    for (part, anchors) in parts.iter_mut().zip(anchors) {
+        insert_anchors(&mut part.html, anchors);
+    }
+
+    for part in &mut parts {
+        part.html = replace_attributes(&part.html, "href", |value| {
+            let (fid, offset) = kindle_pos(value)?;
+            let (target, _, id) = locate(fid, offset)?;
+            Some(format!("href=\"{}#{}\"", part_name(target), id))
+        });
+        part.html = replace_attributes(&part.html, "src", |value| {
+            let number = value.strip_prefix("kindle:embed:")?.split('?').next()?;
+            Some(format!("src=\"images/{:05}\"", base32(number)?))
+        });
+    }
+
+    let toc = ncx
+        .map(|(entries, strings)| {
+            toc_entries(&entries, &strings, |entry| {
+                let (fid, offset) = entry.tag(6, 0).zip(entry.tag(6, 1))?;
+                locate(fid, offset).map(|(target, _, id)| (part_name(target), id))
+            })
+        })
+        .unwrap_or_default();
+
+    BookFiles { parts, toc }
+}
+
+/// Reads `kindle:pos:fid:XXXX:off:YYYYYYYYYY` link targets.
+fn kindle_pos(href: &str) -> Option<(u32, u32)> {
+    let rest = href.strip_prefix("kindle:pos:fid:")?;
+    let (fid, rest) = rest.split_once(':')?;
+    let offset = rest.strip_prefix("off:")?;
+    Some((base32(fid)? as u32, base32(offset)? as u32))
+}
+
+/// KF8's base-32 numbers use the digits 0-9 then A-V.
+fn base32(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 32).ok()
+}
+
+fn toc_entries(
+    entries: &[IndexEntry],
+    strings: &HashMap<u32, String>,
+    target: impl Fn(&IndexEntry) -> Option<(String, String)>,
+) -> Vec<TocEntry> {
+    entries
+        .iter()
+        .map(|entry| TocEntry {
+            label: entry
+                .tag(3, 0)
+                .and_then(|offset| strings.get(&offset))
+                .map(|label| collapse_whitespace(label))
+                .unwrap_or_default(),
+            target: target(entry),
+            level: entry.tag(4, 0).unwrap_or(0) as usize,
+            parent: entry.tag(21, 0).map(|parent| parent as usize),
+        })
+        .collect()
+}
+
+/// Turns NCX entries into chapters in reading order. Each entry ends where
+/// the next entry at the same or a shallower level begins.
+fn resolve_toc(entries: &[TocEntry], anchor_offsets: &HashMap<String, usize>, content_len: usize) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = Vec::new();
+    let mut chapter_ids: HashMap<usize, String> = HashMap::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(start) = entry.target.as_ref().and_then(|(_, id)| anchor_offsets.get(id)) else {
+            continue;
+        };
+        let id = format!("toc_{}", chapters.len());
+        chapter_ids.insert(index, id.clone());
+        chapters.push(Chapter {
+            id,
+            title: if entry.label.is_empty() {
+                format!("Section {}", chapters.len() + 1)
+            } else {
+                entry.label.clone()
+            },
+            start_position: *start,
+            end_position: 0,
+            level: entry.level,
+            parent_id: entry.parent.and_then(|parent| chapter_ids.get(&parent).cloned()),
+            href: entry.target.as_ref().map(|(part, id)| format!("{}#{}", part, id)),
+        });
+    }
+
+    for i in 0..chapters.len() {
+        let level = chapters[i].level;
+        let end = chapters[i + 1..]
+            .iter()
+            .find(|next| next.level <= level)
+            .map(|next| next.start_position)
+            .unwrap_or(content_len);
+        chapters[i].end_position = end.max(chapters[i].start_position);
+    }
+
+    chapters
+}
+
+/// Inserts an empty `<a id>` at each offset, moving offsets that fall inside
+/// a tag to the tag's start and offsets before the body into it. Returns
+/// where each anchor ended up.
+fn insert_anchors(html: &mut Vec<u8>, mut anchors: Vec<(usize, String)>) -> HashMap<String, usize> {
+    let body_start = find_ignoring_ascii_case(html, b"<body")
+        .first()
+        .and_then(|&tag| html[tag..].iter().position(|&b| b == b'>').map(|end| tag + end + 1))
+        .unwrap_or(0);
+
+    for (offset, _) in anchors.iter_mut() {
+        *offset = (*offset).clamp(body_start, html.len());
+        let before = &html[..*offset];
+        if let Some(open) = before.iter().rposition(|&b| b == b'<') {
+            if before[open..].iter().all(|&b| b != b'>') {
+                *offset = open;
+            }
+        }
+        // Never split a UTF-8 sequence
+        while *offset > 0 && *offset < html.len() && html[*offset] & 0xC0 == 0x80 {
+            *offset -= 1;
+        }
+    }
+    anchors.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+    anchors.dedup_by(|a, b| a.1 == b.1);
+
+    let mut positions = HashMap::new();
+    for (offset, id) in &anchors {
+        let tag = format!("<a id=\"{}\"></a>", id);
+        html.splice(*offset..*offset, tag.bytes());
+        // Anchors after this one move along by the inserted length
+        for position in positions.values_mut() {
+            if *position >= *offset {
+                *position += tag.len();
+            }
+        }
+        positions.insert(id.clone(), *offset);
+    }
+    positions
+}
+
+/// An HTML attribute found by byte scanning, with the range of its whole
+/// `name=value` text.
+struct Attribute {
+    start: usize,
+    end: usize,
+    value: String,
+}
+
+/// Finds `name=value` attributes inside tags, quoted or not. MOBI markup is
+/// too loose for an HTML parser to preserve byte offsets, so this scans.
+fn find_attributes(html: &[u8], name: &str) -> Vec<Attribute> {
+    let needle = format!("{}=", name);
+    let mut attributes = Vec::new();
+
+    for start in find_ignoring_ascii_case(html, needle.as_bytes()) {
+        let preceded_by_space = start > 0 && html[start - 1].is_ascii_whitespace();
+        if !preceded_by_space {
+            continue;
+        }
+        let mut position = start + needle.len();
+        let (value_start, value_end) = match html.get(position) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                position += 1;
+                let Some(length) = html[position..].iter().position(|&b| b == quote) else {
+                    continue;
+                };
+                (position, position + length)
+            }
+            _ => {
+                let length = html[position..]
+                    .iter()
+                    .position(|&b| b.is_ascii_whitespace() || b == b'>' || b == b'/')
+                    .unwrap_or(html.len() - position);
+                (position, position + length)
+            }
+        };
+        let end = if html.get(value_end).is_some_and(|&b| b == b'"' || b == b'\'') {
+            value_end + 1
+        } else {
+            value_end
+        };
+        attributes.push(Attribute {
+            start,
+            end,
+            value: String::from_utf8_lossy(&html[value_start..value_end]).into_owned(),
+        });
+    }
+    attributes
+}
+
+/// Replaces each `name=value` attribute for which `replace` returns new text.
+fn replace_attributes(html: &[u8], name: &str, replace: impl Fn(&str) -> Option<String>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(html.len());
+    let mut copied = 0;
+    for attribute in find_attributes(html, name) {
+        if let Some(replacement) = replace(&attribute.value) {
+            out.extend_from_slice(&html[copied..attribute.start]);
+            out.extend_from_slice(replacement.as_bytes());
+            copied = attribute.end;
+        }
+    }
+    out.extend_from_slice(&html[copied..]);
+    out
+}
+
+fn find_ignoring_ascii_case(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return Vec::new();
+    }
+    (0..=haystack.len() - needle.len())
+        .filter(|&i| haystack[i..i + needle.len()].eq_ignore_ascii_case(needle))
+        .collect()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn be32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn be64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PalmDB file holding `records`.
+    fn palmdb(name: &str, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0u8; 78];
+        data[..name.len()].copy_from_slice(name.as_bytes());
+        data[60..68].copy_from_slice(b"BOOKMOBI");
+        data[76..78].copy_from_slice(&(records.len() as u16).to_be_bytes());
+
+        let mut offset = 78 + records.len() * 8 + 2;
+        for record in records {
+            data.extend((offset as u32).to_be_bytes());
+            data.extend([0; 4]);
+            offset += record.len();
+        }
+        data.extend([0; 2]);
+        for record in records {
+            data.extend(record);
+        }
+        data
+    }
+
+    /// Record 0 of a MOBI 6 book in UTF-8, with EXTH metadata and a full name.
+    fn mobi_header(compression: u16, text_length: u32, text_records: u16, full_name: &str, exth: &[(u32, &str)]) -> Vec<u8> {
+        let header_length = 0xE8;
+        let mut record = vec![0u8; 16 + header_length];
+        let mut put = |offset: usize, bytes: &[u8]| record[offset..offset + bytes.len()].copy_from_slice(bytes);
+        put(0, &compression.to_be_bytes());
+        put(4, &text_length.to_be_bytes());
+        put(8, &text_records.to_be_bytes());
+        put(10, &4096u16.to_be_bytes());
+        put(16, b"MOBI");
+        put(20, &(header_length as u32).to_be_bytes());
+        put(0x1C, &65001u32.to_be_bytes());
+        put(0x24, &6u32.to_be_bytes());
+        put(0x80, &0x40u32.to_be_bytes());
+        for offset in [0x6C, 0x70, 0xC0, 0xF4] {
+            put(offset, &NO_INDEX.to_be_bytes());
+        }
+
+        let mut exth_records = Vec::new();
+        for (record_type, value) in exth {
+            exth_records.extend(record_type.to_be_bytes());
+            exth_records.extend((value.len() as u32 + 8).to_be_bytes());
+            exth_records.extend(value.as_bytes());
+        }
+        record.extend(b"EXTH");
+        record.extend((exth_records.len() as u32 + 12).to_be_bytes());
+        record.extend((exth.len() as u32).to_be_bytes());
+        record.extend(exth_records);
+
+        let name_offset = record.len() as u32;
+        record[0x54..0x58].copy_from_slice(&name_offset.to_be_bytes());
+        record[0x58..0x5C].copy_from_slice(&(full_name.len() as u32).to_be_bytes());
+        record.extend(full_name.as_bytes());
+        record
+    }
+
+    #[test]
+    fn palmdoc_literals_and_back_references() {
+        let mut out = Vec::new();
+        // Plain ASCII, a literal run of high bytes, then space + character
+        palmdoc_decompress(&[b'H', b'i', 0x02, 0xC3, 0xA9, 0xE1], &mut out);
+        assert_eq!(out, b"Hi\xC3\xA9 a");
+
+        // "abc" then distance 3, length 3; "x" then an overlapping distance 1, length 5
+        let mut out = Vec::new();
+        palmdoc_decompress(&[b'a', b'b', b'c', 0x80, 0x18, b'x', 0x80, 0x0A], &mut out);
+        assert_eq!(out, b"abcabcxxxxxx");
+    }
+
+    #[test]
+    fn palmdoc_ignores_references_outside_its_record() {
+        // Earlier records' output can't be copied from, nor can a truncated pair be read
+        let mut out = b"xyz".to_vec();
+        palmdoc_decompress(&[0x80, 0x18, b'a', 0x80], &mut out);
+        assert_eq!(out, b"xyza");
+    }
+
+    #[test]
+    fn variable_width_integers() {
+        assert_eq!(forward_int(&[0x85]), (1, 5));
+        assert_eq!(forward_int(&[0x05, 0x81, 0x99]), (2, 641));
+        assert_eq!(forward_int(&[0x01, 0x02]), (2, 130)); // Unterminated
+        assert_eq!(forward_int(&[]), (0, 0));
+
+        // Read backwards, the integer starts at the byte with the high bit set
+        assert_eq!(backward_int(&[0x41, 0x82, 0x03]), 259);
+        assert_eq!(backward_int(&[0x41, 0x83]), 3);
+    }
+
+    #[test]
+    fn trailing_entries_are_measured_from_the_end() {
+        // One trailing entry of three bytes, its size last
+        let record = b"hello\x01xy\x83";
+        assert_eq!(trailing_entries_size(record, 0b10), 3);
+        // Plus a multibyte overlap of one byte, before the entries
+        assert_eq!(trailing_entries_size(record, 0b11), 5);
+        assert_eq!(trailing_entries_size(record, 0), 0);
+        // Never more than the record
+        assert_eq!(trailing_entries_size(b"\x8F", 0b10), 1);
+    }
+
+    #[test]
+    fn tag_values_follow_the_control_byte() {
+        // (tag, values per entry, mask, end of control byte)
+        let table = [[1, 1, 0x01, 0], [2, 1, 0x02, 0], [3, 2, 0x0C, 0], [0, 0, 0, 1]];
+
+        // Tags 1 and 2 once each, tag 3 twice (0x08 under mask 0x0C) with two values per entry
+        let tags = tag_values(1, &table, &[0x0B, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86]);
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[&1], vec![1]);
+        assert_eq!(tags[&2], vec![2]);
+        assert_eq!(tags[&3], vec![3, 4, 5, 6]);
+
+        // A full multi-bit mask gives the values' size in bytes instead
+        let tags = tag_values(1, &table, &[0x0C, 0x83, 0x01, 0x80, 0x85]);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[&3], vec![128, 5]);
+    }
+
+    #[test]
+    fn huffman_output_is_capped() {
+        // Every 8-bit code 0xFF is phrase 0, a kilobyte of text
+        let mut huffman = HuffCdic {
+            lookup: vec![(8, true, 0xFFFF_FFFF); 256],
+            min_codes: [0; 33],
+            max_codes: [0; 33],
+            phrases: vec![(vec![b'a'; 1024], true)],
+        };
+        assert_eq!(huffman.decompress(&[0xFF; 4], 0).unwrap().len(), 4 * 1024);
+        assert!(huffman.decompress(&[0xFF; 100], 0).is_err());
+    }
+
+    #[test]
+    fn parses_a_mobi6_book() {
+        let mut html = "<html><head><title>x</title></head><body><h1>Chapter One</h1>\
+                        <p>Call me Ishmael. See <a filepos=0000000000>the storm</a>.</p>\
+                        <mbp:pagebreak/><h1>Chapter Two</h1><p>The storm came.</p></body></html>"
+            .to_string();
+        let target = html.find("<h1>Chapter Two").unwrap();
+        html = html.replace("0000000000", &format!("{:010}", target));
+
+        // PalmDOC passes plain ASCII through, split over two records
+        let (first, second) = html.as_bytes().split_at(70);
+        let exth = [(EXTH_AUTHOR, "Herman Melville"), (EXTH_AUTHOR, "Anon"), (EXTH_ASIN, "B000TEST")];
+        let records = vec![
+            mobi_header(PALMDOC_COMPRESSION, u32::MAX, 2, "Moby Dick", &exth),
+            first.to_vec(),
+            second.to_vec(),
+        ];
+
+        let path = std::env::temp_dir().join(format!("mobi-{}.mobi", uuid::Uuid::new_v4()));
+        fs::write(&path, palmdb("Moby_Dick", &records)).unwrap();
+        let document = parse_mobi(&path, &Progress::default());
+        fs::remove_file(&path).ok();
+        let document = document.unwrap();
+
+        assert_eq!(document.title, "Moby Dick");
+        assert_eq!(document.author.as_deref(), Some("Herman Melville, Anon"));
+        assert_eq!(document.asin.as_deref(), Some("B000TEST"));
+        assert_eq!(document.file_type, "mobi");
+
+        let titles: Vec<&str> = document.chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, ["Chapter One", "Chapter Two"]);
+        assert_eq!(document.chapters[1].start_position, document.content.find("Chapter Two").unwrap());
+        assert!(document.content.contains("Call me Ishmael."));
+
+        // The filepos link points at the anchor put in front of chapter two
+        let link = document
+            .blocks
+            .iter()
+            .find_map(|block| match &block.kind {
+                BlockKind::Paragraph { spans } => spans.iter().find_map(|span| span.link.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(link, format!("part0001.html#filepos{}", target));
+    }
+
+    #[test]
+    fn drm_and_truncated_files_are_refused() {
+        assert!(matches!(PalmDb::parse(&[0; 40]), Err(Error::Corrupt(_))));
+
+        let mut header = mobi_header(NO_COMPRESSION, 0, 0, "", &[]);
+        header[12..14].copy_from_slice(&2u16.to_be_bytes());
+        let data = palmdb("Locked", &[header]);
+        let db = PalmDb::parse(&data).unwrap();
+        assert!(matches!(book_header(&db), Err(Error::DrmProtected)));
+    }
+}
@@ -1,10 +1,84 @@
+pub mod comic_parser;
 pub mod epub_parser;
+pub mod fb2_parser;
+pub mod gutenberg;
+pub mod mobi_parser;
 pub mod pdf_parser;
+pub mod progress;
+pub mod registry;
+pub mod txt_chapters;
 pub mod txt_parser;
+pub mod xhtml;
 
+use crate::error::{Error, Result};
 use crate::Document;
-use std::path::PathBuf;
+use progress::Progress;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
+/// Choices from the library and user settings that change how a file parses.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub encoding: Option<String>, // Replaces charset detection for text files
+    pub chapter_detection: txt_chapters::ChapterDetection,
+}
+
+/// A document format. `registry` asks each format in turn whether it
+/// recognises a file, then parses with the first that does.
 pub trait DocumentParser {
-    async fn parse(file_path: &PathBuf) -> Result<Document, String>;
+    /// Whether the file is in this format, judged by its content.
+    fn detect(signature: &FileSignature) -> bool;
+
+    fn parse(file_path: &PathBuf, options: &ParseOptions, progress: &Progress) -> Result<Document>;
+
+    /// An embedded resource, such as an image referenced by a block, as a
+    /// data URL.
+    fn read_resource(_file_path: &PathBuf, _resource_path: &str) -> Result<String> {
+        Err(Error::NotFound("Resource"))
+    }
+}
+
+// Enough of the file for every format's magic bytes and XML root element
+const SIGNATURE_BYTES: u64 = 4096;
+
+/// What format detection looks at: the start of the file and, for zip
+/// containers, the names of the entries inside.
+pub struct FileSignature {
+    pub head: Vec<u8>,
+    pub zip_entries: Vec<String>, // Empty unless the file is a zip
+}
+
+impl FileSignature {
+    pub fn read(file_path: &Path) -> Result<Self> {
+        let mut head = Vec::new();
+        File::open(file_path)
+            .and_then(|file| file.take(SIGNATURE_BYTES).read_to_end(&mut head))
+            .map_err(|e| Error::io(file_path, e))?;
+
+        let zip_entries = if head.starts_with(b"PK\x03\x04") {
+            let file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+            zip::ZipArchive::new(file)
+                .map(|archive| archive.file_names().map(str::to_string).collect())
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self { head, zip_entries })
+    }
+
+    pub fn is_zip(&self) -> bool {
+        !self.zip_entries.is_empty()
+    }
+}
+
+/// Hex SHA-256 of the file's bytes. This is the document's identity, so the
+/// same book keeps its id, progress and cached data across re-opens and moves.
+pub fn content_hash(file_path: &Path) -> Result<String> {
+    let mut file = File::open(file_path).map_err(|e| Error::io(file_path, e))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| Error::io(file_path, e))?;
+    Ok(format!("{:x}", hasher.finalize()))
 }
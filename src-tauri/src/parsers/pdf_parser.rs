@@ -1,47 +1,451 @@
-use crate::{Document, Chapter};
-use lopdf::Document as PdfDocument;
+use crate::error::{Error, Result};
+use crate::{Chapter, Document, DocumentKind, Page, ReadingDirection};
+use super::progress::Progress;
+use super::{content_hash, DocumentParser, FileSignature, ParseOptions};
+use lopdf::{Document as PdfDocument, Object, ObjectId};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use uuid::Uuid;
 
-pub async fn parse_pdf(file_path: &PathBuf) -> Result<Document, String> {
-    let doc = PdfDocument::load(file_path).map_err(|e| format!("Failed to open PDF: {}", e))?;
+// Outlines and name trees come from untrusted files; stop following them
+// past this depth rather than recursing forever on a malformed one
+const MAX_TREE_DEPTH: usize = 32;
+
+struct OutlineEntry {
+    title: String,
+    page: Option<usize>, // 0-based page index
+    children: Vec<OutlineEntry>,
+}
+
+pub struct PdfParser;
+
+impl DocumentParser for PdfParser {
+    fn detect(signature: &FileSignature) -> bool {
+        // Readers accept junk before the header, up to a point
+        let head = &signature.head[..signature.head.len().min(1024)];
+        head.windows(5).any(|window| window == b"%PDF-")
+    }
+
+    fn parse(file_path: &PathBuf, _options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        parse_pdf(file_path, progress)
+    }
+}
+
+pub fn parse_pdf(file_path: &PathBuf, progress: &Progress) -> Result<Document> {
+    progress.update("Opening", 0.0)?;
+    let doc = PdfDocument::load(file_path).map_err(|e| Error::corrupt(format!("Failed to open PDF: {}", e)))?;
+    let content_hash = content_hash(file_path)?;
 
     let title = extract_pdf_title(&doc);
     let author = extract_pdf_author(&doc);
 
-    // Extract text from all pages
+    // Extract text page by page, remembering where each page starts
+    let pages = doc.get_pages();
+    let page_count = pages.len();
     let mut content = String::new();
-    let page_count = doc.get_pages().len();
+    let mut page_ranges = Vec::with_capacity(page_count);
 
-    for (page_id, _) in doc.get_pages() {
-        if let Ok(text) = doc.extract_text(&[page_id]) {
-            content.push_str(&text);
-            content.push_str("\n\n");
+    for (index, page_number) in pages.keys().enumerate() {
+        progress.update("Extracting text", index as f32 / page_count as f32)?;
+        let text = doc
+            .extract_text(&[*page_number])
+            .map(|text| clean_page_text(&text))
+            .unwrap_or_default();
+
+        if !text.is_empty() && !content.is_empty() {
+            content.push('\n');
         }
+        let start = content.len();
+        content.push_str(&text);
+        page_ranges.push((start, content.len()));
     }
 
-    // Clean up the content
-    content = content
-        .lines()
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<_>>()
-        .join("\n");
+    let page_indices: HashMap<ObjectId, usize> = pages
+        .values()
+        .enumerate()
+        .map(|(index, id)| (*id, index))
+        .collect();
+    progress.update("Reading outline", 0.0)?;
+    let outline = read_outline(&doc, &page_indices);
+    let page_starts: Vec<usize> = page_ranges.iter().map(|(start, _)| *start).collect();
+    let chapters = resolve_outline(&outline, &page_starts, content.len());
+
+    let labels = page_labels(&doc, page_count);
+    let pages = page_ranges
+        .into_iter()
+        .zip(labels)
+        .enumerate()
+        .map(|(index, ((start, end), label))| Page {
+            index,
+            label,
+            start_position: start,
+            end_position: end,
+            image: None,
+        })
+        .collect();
 
     Ok(Document {
-        id: Uuid::new_v4().to_string(),
+        id: content_hash.clone(),
         title,
         author,
         file_path: file_path.clone(),
         file_type: "pdf".to_string(),
+        content_hash,
+        kind: DocumentKind::Text,
+        reading_direction: ReadingDirection::LeftToRight,
+        encoding: None,
+        asin: None,
+        series: None,
+        series_index: None,
+        genres: Vec::new(),
+        description: None,
         content,
+        blocks: Vec::new(),
         current_position: 0,
         total_pages: page_count,
-        chapters: Vec::new(), // PDF chapter extraction can be added later
+        chapters,
+        pages,
+        appendices: Vec::new(),
         cover_image: None, // PDF cover extraction can be added later
     })
 }
 
+fn clean_page_text(text: &str) -> String {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Reads the document outline (`/Outlines`), resolving each item to the page
+/// it points at.
+fn read_outline(doc: &PdfDocument, page_indices: &HashMap<ObjectId, usize>) -> Vec<OutlineEntry> {
+    let Some(outlines) = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| resolve(doc, outlines)?.as_dict().ok())
+    else {
+        return Vec::new();
+    };
+
+    let named = named_destinations(doc);
+    let mut visited = HashSet::new();
+    read_outline_items(doc, outlines.get(b"First").ok(), page_indices, &named, &mut visited, 0)
+}
+
+fn read_outline_items(
+    doc: &PdfDocument,
+    first: Option<&Object>,
+    page_indices: &HashMap<ObjectId, usize>,
+    named: &HashMap<Vec<u8>, &Object>,
+    visited: &mut HashSet<ObjectId>,
+    depth: usize,
+) -> Vec<OutlineEntry> {
+    let mut entries = Vec::new();
+    let mut next = first;
+
+    while let Some(object) = next {
+        // Malformed files can link outline items into a loop
+        if let Object::Reference(id) = object {
+            if !visited.insert(*id) {
+                break;
+            }
+        }
+        let Some(item) = resolve(doc, object).and_then(|item| item.as_dict().ok()) else {
+            break;
+        };
+
+        let title = item
+            .get(b"Title")
+            .ok()
+            .and_then(|title| resolve(doc, title)?.as_str().ok())
+            .map(|title| decode_text_string(title).split_whitespace().collect::<Vec<_>>().join(" "))
+            .unwrap_or_default();
+
+        // Either a direct destination or a GoTo action wrapping one
+        let target = item.get(b"Dest").ok().or_else(|| {
+            let action = resolve(doc, item.get(b"A").ok()?)?.as_dict().ok()?;
+            if action.get(b"S").ok()?.as_name().ok()? == b"GoTo" {
+                action.get(b"D").ok()
+            } else {
+                None
+            }
+        });
+        let page = target.and_then(|target| destination_page(doc, target, page_indices, Some(named)));
+
+        let children = if depth < MAX_TREE_DEPTH {
+            read_outline_items(doc, item.get(b"First").ok(), page_indices, named, visited, depth + 1)
+        } else {
+            Vec::new()
+        };
+
+        entries.push(OutlineEntry { title, page, children });
+        next = item.get(b"Next").ok();
+    }
+
+    entries
+}
+
+/// The page index a destination points at. Destinations are an array
+/// starting with the page, a dictionary wrapping that array, or the name of
+/// one of those.
+fn destination_page(
+    doc: &PdfDocument,
+    destination: &Object,
+    page_indices: &HashMap<ObjectId, usize>,
+    named: Option<&HashMap<Vec<u8>, &Object>>,
+) -> Option<usize> {
+    match resolve(doc, destination)? {
+        Object::Array(items) => match items.first()? {
+            Object::Reference(id) => page_indices.get(id).copied(),
+            Object::Integer(index) => usize::try_from(*index)
+                .ok()
+                .filter(|&index| index < page_indices.len()),
+            _ => None,
+        },
+        Object::Dictionary(dict) => destination_page(doc, dict.get(b"D").ok()?, page_indices, named),
+        // A name may only be looked up once, so named destinations can't loop
+        Object::Name(name) | Object::String(name, _) => {
+            destination_page(doc, named?.get(name)?, page_indices, None)
+        }
+        _ => None,
+    }
+}
+
+/// Named destinations from both the PDF 1.1 `/Dests` dictionary and the
+/// `/Names` name tree used since PDF 1.2.
+fn named_destinations(doc: &PdfDocument) -> HashMap<Vec<u8>, &Object> {
+    let mut named = HashMap::new();
+    let Ok(catalog) = doc.catalog() else {
+        return named;
+    };
+
+    if let Some(dests) = catalog
+        .get(b"Dests")
+        .ok()
+        .and_then(|dests| resolve(doc, dests)?.as_dict().ok())
+    {
+        for (name, destination) in dests.iter() {
+            named.insert(name.clone(), destination);
+        }
+    }
+
+    if let Some(tree) = catalog
+        .get(b"Names")
+        .ok()
+        .and_then(|names| resolve(doc, names)?.as_dict().ok())
+        .and_then(|names| names.get(b"Dests").ok())
+    {
+        let mut entries = Vec::new();
+        read_tree(doc, tree, b"Names", &mut entries, &mut HashSet::new(), 0);
+        for (key, destination) in entries {
+            if let Ok(name) = key.as_str() {
+                named.insert(name.to_vec(), destination);
+            }
+        }
+    }
+
+    named
+}
+
+/// Collects the key/value pairs of a name tree (`leaf_key` = `Names`) or
+/// number tree (`leaf_key` = `Nums`).
+fn read_tree<'a>(
+    doc: &'a PdfDocument,
+    node: &'a Object,
+    leaf_key: &[u8],
+    entries: &mut Vec<(&'a Object, &'a Object)>,
+    visited: &mut HashSet<ObjectId>,
+    depth: usize,
+) {
+    if let Object::Reference(id) = node {
+        if !visited.insert(*id) {
+            return;
+        }
+    }
+    let Some(node) = resolve(doc, node).and_then(|node| node.as_dict().ok()) else {
+        return;
+    };
+
+    if let Some(pairs) = node
+        .get(leaf_key)
+        .ok()
+        .and_then(|pairs| resolve(doc, pairs)?.as_array().ok())
+    {
+        for pair in pairs.chunks_exact(2) {
+            entries.push((&pair[0], &pair[1]));
+        }
+    }
+
+    if depth < MAX_TREE_DEPTH {
+        if let Some(kids) = node.get(b"Kids").ok().and_then(|kids| resolve(doc, kids)?.as_array().ok()) {
+            for kid in kids {
+                read_tree(doc, kid, leaf_key, entries, visited, depth + 1);
+            }
+        }
+    }
+}
+
+/// Printed page numbers from the `/PageLabels` number tree, falling back to
+/// 1, 2, 3... for pages it doesn't cover.
+fn page_labels(doc: &PdfDocument, page_count: usize) -> Vec<String> {
+    let mut labels: Vec<String> = (1..=page_count).map(|n| n.to_string()).collect();
+
+    let Some(tree) = doc.catalog().ok().and_then(|catalog| catalog.get(b"PageLabels").ok()) else {
+        return labels;
+    };
+    let mut entries = Vec::new();
+    read_tree(doc, tree, b"Nums", &mut entries, &mut HashSet::new(), 0);
+
+    // Each range starts at its key and runs until the next range
+    let mut ranges: Vec<(usize, &lopdf::Dictionary)> = entries
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let first = usize::try_from(key.as_i64().ok()?).ok()?;
+            Some((first, resolve(doc, value)?.as_dict().ok()?))
+        })
+        .filter(|(first, _)| *first < page_count)
+        .collect();
+    ranges.sort_by_key(|(first, _)| *first);
+
+    for (i, (first, range)) in ranges.iter().enumerate() {
+        let last = ranges.get(i + 1).map(|(next, _)| *next).unwrap_or(page_count);
+        let style = range.get(b"S").ok().and_then(|style| style.as_name().ok());
+        let prefix = range
+            .get(b"P")
+            .ok()
+            .and_then(|prefix| resolve(doc, prefix)?.as_str().ok())
+            .map(decode_text_string)
+            .unwrap_or_default();
+        let start = range
+            .get(b"St")
+            .ok()
+            .and_then(|start| start.as_i64().ok())
+            .filter(|&start| start >= 1)
+            .unwrap_or(1) as usize;
+
+        for (offset, label) in labels[*first..last].iter_mut().enumerate() {
+            let number = start + offset;
+            let numbering = match style {
+                Some(b"D") => number.to_string(),
+                Some(b"R") => roman_numeral(number).to_uppercase(),
+                Some(b"r") => roman_numeral(number),
+                Some(b"A") => alphabetic_label(number).to_uppercase(),
+                Some(b"a") => alphabetic_label(number),
+                _ => String::new(), // No style: the prefix alone
+            };
+            *label = format!("{}{}", prefix, numbering);
+        }
+    }
+
+    labels
+}
+
+/// Lowercase Roman numeral for `number`, e.g. "xiv".
+pub(super) fn roman_numeral(mut number: usize) -> String {
+    const NUMERALS: [(usize, &str); 13] = [
+        (1000, "m"), (900, "cm"), (500, "d"), (400, "cd"), (100, "c"), (90, "xc"),
+        (50, "l"), (40, "xl"), (10, "x"), (9, "ix"), (5, "v"), (4, "iv"), (1, "i"),
+    ];
+
+    let mut numeral = String::new();
+    for (value, letters) in NUMERALS {
+        while number >= value {
+            numeral.push_str(letters);
+            number -= value;
+        }
+    }
+    numeral
+}
+
+/// a..z, then aa..zz, aaa..zzz and so on, as the PDF spec numbers pages.
+fn alphabetic_label(number: usize) -> String {
+    let letter = (b'a' + ((number - 1) % 26) as u8) as char;
+    letter.to_string().repeat((number - 1) / 26 + 1)
+}
+
+fn resolve_outline(entries: &[OutlineEntry], page_starts: &[usize], content_len: usize) -> Vec<Chapter> {
+    let mut chapters = Vec::new();
+    flatten_outline(entries, 0, None, page_starts, &mut chapters);
+
+    for i in 0..chapters.len() {
+        let level = chapters[i].level;
+        let end = chapters[i + 1..]
+            .iter()
+            .find(|next| next.level <= level)
+            .map(|next| next.start_position)
+            .unwrap_or(content_len);
+        chapters[i].end_position = end.max(chapters[i].start_position);
+    }
+
+    chapters
+}
+
+fn flatten_outline(
+    entries: &[OutlineEntry],
+    level: usize,
+    parent_id: Option<&str>,
+    page_starts: &[usize],
+    chapters: &mut Vec<Chapter>,
+) {
+    for entry in entries {
+        let start = entry.page.and_then(|page| page_starts.get(page)).copied();
+        if start.is_none() && entry.children.is_empty() {
+            continue;
+        }
+
+        let index = chapters.len();
+        let id = format!("outline_{}", index);
+        let title = if entry.title.is_empty() {
+            format!("Section {}", index + 1)
+        } else {
+            entry.title.clone()
+        };
+        chapters.push(Chapter {
+            id: id.clone(),
+            title,
+            start_position: start.unwrap_or(0),
+            end_position: 0,
+            level,
+            parent_id: parent_id.map(str::to_string),
+            href: entry.page.map(|page| format!("#page={}", page + 1)),
+        });
+
+        flatten_outline(&entry.children, level + 1, Some(&id), page_starts, chapters);
+
+        // Items without a destination start where their first child does
+        if start.is_none() {
+            match chapters.get(index + 1) {
+                Some(child) => chapters[index].start_position = child.start_position,
+                None => {
+                    chapters.remove(index);
+                }
+            }
+        }
+    }
+}
+
+fn resolve<'a>(doc: &'a PdfDocument, object: &'a Object) -> Option<&'a Object> {
+    doc.dereference(object).ok().map(|(_, object)| object)
+}
+
+/// Decodes a PDF text string: UTF-16BE or UTF-8 with a byte order mark,
+/// otherwise PDFDocEncoding (close enough to Latin-1 for titles).
+fn decode_text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        String::from_utf8_lossy(utf8).to_string()
+    } else {
+        bytes.iter().map(|&byte| byte as char).collect()
+    }
+}
+
 fn extract_pdf_title(doc: &PdfDocument) -> String {
     if let Ok(info) = doc.trailer.get(b"Info") {
         if let Ok(info_dict) = doc.get_dictionary(info.as_reference().unwrap()) {
@@ -67,3 +471,233 @@ fn extract_pdf_author(doc: &PdfDocument) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Dictionary};
+
+    fn with_page_labels(nums: Vec<Object>) -> PdfDocument {
+        let mut doc = PdfDocument::with_version("1.7");
+        let labels = doc.add_object(dictionary! { "Nums" => nums });
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "PageLabels" => labels });
+        doc.trailer.set("Root", catalog);
+        doc
+    }
+
+    fn range(entries: Dictionary) -> Object {
+        Object::Dictionary(entries)
+    }
+
+    /// A document of `pages` empty pages, with the index of each page object.
+    fn with_pages(pages: usize) -> (PdfDocument, Vec<ObjectId>, HashMap<ObjectId, usize>) {
+        let mut doc = PdfDocument::with_version("1.7");
+        let ids: Vec<ObjectId> = (0..pages).map(|_| doc.add_object(dictionary! { "Type" => "Page" })).collect();
+        let indices = ids.iter().enumerate().map(|(index, id)| (*id, index)).collect();
+        (doc, ids, indices)
+    }
+
+    /// An outline item titled `title`, linked to the items given by id.
+    fn item(title: &str, links: Vec<(&str, ObjectId)>) -> Dictionary {
+        let mut item = dictionary! { "Title" => Object::string_literal(title) };
+        for (key, id) in links {
+            item.set(key, id);
+        }
+        item
+    }
+
+    fn set_catalog(doc: &mut PdfDocument, catalog: Dictionary) {
+        let catalog = doc.add_object(catalog);
+        doc.trailer.set("Root", catalog);
+    }
+
+    // One line per entry, indented by depth: "title -> page"
+    fn outline(entries: &[OutlineEntry]) -> Vec<String> {
+        fn walk(entries: &[OutlineEntry], depth: usize, lines: &mut Vec<String>) {
+            for entry in entries {
+                let page = entry.page.map_or("-".to_string(), |page| page.to_string());
+                lines.push(format!("{}{} -> {}", "  ".repeat(depth), entry.title, page));
+                walk(&entry.children, depth + 1, lines);
+            }
+        }
+        let mut lines = Vec::new();
+        walk(entries, 0, &mut lines);
+        lines
+    }
+
+    #[test]
+    fn roman_numerals() {
+        let numerals: Vec<String> = [1, 2, 4, 9, 14, 40, 90, 400, 1994, 3999].into_iter().map(roman_numeral).collect();
+        assert_eq!(numerals, ["i", "ii", "iv", "ix", "xiv", "xl", "xc", "cd", "mcmxciv", "mmmcmxcix"]);
+    }
+
+    #[test]
+    fn alphabetic_labels_repeat_the_letter() {
+        let labels: Vec<String> = [1, 2, 26, 27, 28, 52, 53].into_iter().map(alphabetic_label).collect();
+        assert_eq!(labels, ["a", "b", "z", "aa", "bb", "zz", "aaa"]);
+    }
+
+    #[test]
+    fn page_labels_follow_their_ranges() {
+        // Front matter in roman numerals, the book from 1, appendices as A-7 on,
+        // then an unstyled range given as a later key first
+        let doc = with_page_labels(vec![
+            9.into(),
+            range(dictionary! { "P" => Object::string_literal("Plate") }),
+            0.into(),
+            range(dictionary! { "S" => "r" }),
+            3.into(),
+            range(dictionary! { "S" => "D" }),
+            6.into(),
+            range(dictionary! { "S" => "A", "P" => Object::string_literal("A-"), "St" => 7 }),
+            // Past the last page, so ignored
+            40.into(),
+            range(dictionary! { "S" => "D" }),
+        ]);
+
+        assert_eq!(
+            page_labels(&doc, 10),
+            ["i", "ii", "iii", "1", "2", "3", "A-G", "A-H", "A-I", "Plate"]
+        );
+    }
+
+    #[test]
+    fn pages_without_labels_count_from_one() {
+        let doc = PdfDocument::with_version("1.7");
+        assert_eq!(page_labels(&doc, 3), ["1", "2", "3"]);
+
+        // Ranges only start where they say; pages before the first keep numbers
+        let doc = with_page_labels(vec![2.into(), range(dictionary! { "S" => "a" })]);
+        assert_eq!(page_labels(&doc, 4), ["1", "2", "a", "b"]);
+    }
+
+    #[test]
+    fn labels_find_their_pages() {
+        let pages: Vec<Page> = ["i", "ii", "1", "2", "A-7"]
+            .into_iter()
+            .enumerate()
+            .map(|(index, label)| Page {
+                index,
+                label: label.to_string(),
+                start_position: index * 100,
+                end_position: index * 100 + 100,
+                image: None,
+            })
+            .collect();
+        let find = |label| crate::page_labelled(&pages, label).map(|page| page.index);
+
+        assert_eq!(find("ii"), Some(1));
+        assert_eq!(find(" a-7 "), Some(4));
+        assert_eq!(find("2"), Some(3)); // The printed 2, not the second page
+        assert_eq!(find("5"), Some(4)); // No page is printed 5: the fifth page
+        assert_eq!(find("0"), None);
+        assert_eq!(find("6"), None);
+        assert_eq!(find("xiv"), None);
+    }
+
+    #[test]
+    fn outlines_nest_and_resolve_their_destinations() {
+        let (mut doc, pages, indices) = with_pages(4);
+        let [part_one, chapter_one, chapter_two, part_two, appendix, missing, outlines] =
+            [(); 7].map(|_| doc.new_object_id());
+
+        let part = item("Part  One", vec![("First", chapter_one), ("Next", part_two)]);
+        let mut first = item("Chapter 1", vec![("Next", chapter_two)]);
+        first.set("Dest", vec![pages[0].into(), "Fit".into()]);
+        // Names from the /Names tree and the older /Dests dictionary
+        let mut second = item("Chapter 2", vec![]);
+        second.set("Dest", Object::string_literal("chapter-two"));
+        let mut part_two_item = item("Part Two", vec![("Next", appendix)]);
+        part_two_item.set("A", dictionary! { "S" => "GoTo", "D" => vec![pages[2].into(), "XYZ".into()] });
+        let mut appendix_item = item("Appendix", vec![("Next", missing)]);
+        appendix_item.set("Dest", Object::Name(b"appendix".to_vec()));
+        // Points at an object that isn't a page
+        let mut missing_item = item("Errata", vec![]);
+        missing_item.set("Dest", vec![outlines.into(), "Fit".into()]);
+
+        for (id, object) in [
+            (part_one, part),
+            (chapter_one, first),
+            (chapter_two, second),
+            (part_two, part_two_item),
+            (appendix, appendix_item),
+            (missing, missing_item),
+            (outlines, dictionary! { "Type" => "Outlines", "First" => part_one }),
+        ] {
+            doc.objects.insert(id, Object::Dictionary(object));
+        }
+        let names = vec![Object::string_literal("chapter-two"), vec![pages[1].into(), "Fit".into()].into()];
+        set_catalog(
+            &mut doc,
+            dictionary! {
+                "Type" => "Catalog",
+                "Outlines" => outlines,
+                "Dests" => dictionary! { "appendix" => vec![pages[3].into(), "Fit".into()] },
+                "Names" => dictionary! { "Dests" => dictionary! { "Names" => names } },
+            },
+        );
+
+        let entries = read_outline(&doc, &indices);
+        assert_eq!(
+            outline(&entries),
+            [
+                "Part One -> -",
+                "  Chapter 1 -> 0",
+                "  Chapter 2 -> 1",
+                "Part Two -> 2",
+                "Appendix -> 3",
+                "Errata -> -",
+            ]
+        );
+
+        let chapters = resolve_outline(&entries, &[0, 100, 200, 300], 400);
+        let ranges: Vec<_> = chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.level, c.parent_id.as_deref(), c.start_position, c.end_position))
+            .collect();
+        // The part starts with its first chapter; the item without a page is dropped
+        assert_eq!(
+            ranges,
+            [
+                ("Part One", 0, None, 0, 200),
+                ("Chapter 1", 1, Some("outline_0"), 0, 100),
+                ("Chapter 2", 1, Some("outline_0"), 100, 200),
+                ("Part Two", 0, None, 200, 300),
+                ("Appendix", 0, None, 300, 400),
+            ]
+        );
+        assert_eq!(chapters[2].href.as_deref(), Some("#page=2"));
+    }
+
+    #[test]
+    fn looping_outlines_end() {
+        let (mut doc, pages, indices) = with_pages(2);
+        let [first, second, outlines] = [(); 3].map(|_| doc.new_object_id());
+
+        // The second item leads back to the first, which lists itself as a child
+        let mut first_item = item("One", vec![("Next", second), ("First", first)]);
+        first_item.set("Dest", vec![pages[0].into(), "Fit".into()]);
+        let mut second_item = item("Two", vec![("Next", first)]);
+        second_item.set("Dest", vec![Object::Integer(1), "Fit".into()]);
+        doc.objects.insert(first, Object::Dictionary(first_item));
+        doc.objects.insert(second, Object::Dictionary(second_item));
+        doc.objects.insert(outlines, Object::Dictionary(dictionary! { "First" => first }));
+        set_catalog(&mut doc, dictionary! { "Type" => "Catalog", "Outlines" => outlines });
+
+        assert_eq!(outline(&read_outline(&doc, &indices)), ["One -> 0", "Two -> 1"]);
+    }
+
+    #[test]
+    fn destinations_outside_the_document_have_no_page() {
+        let (doc, pages, indices) = with_pages(2);
+        let named = HashMap::new();
+        let page = |destination: Object| destination_page(&doc, &destination, &indices, Some(&named));
+
+        assert_eq!(page(vec![pages[1].into(), "Fit".into()].into()), Some(1));
+        assert_eq!(page(dictionary! { "D" => vec![Object::Integer(0)] }.into()), Some(0));
+        assert_eq!(page(vec![Object::Integer(2), "Fit".into()].into()), None);
+        assert_eq!(page(vec![Object::Integer(-1)].into()), None);
+        assert_eq!(page(vec![Object::Reference((99, 0)), "Fit".into()].into()), None);
+        assert_eq!(page(Object::string_literal("nowhere")), None);
+    }
+}
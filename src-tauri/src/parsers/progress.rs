@@ -0,0 +1,102 @@
+use crate::error::{Error, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+type Sink = dyn Fn(&str, u8) + Send + Sync;
+
+/// Passed to a parser to report how far it has got and to tell it to stop.
+/// The default reports nowhere and is never cancelled.
+#[derive(Clone, Default)]
+pub struct Progress {
+    sink: Option<Arc<Sink>>,
+    cancelled: Arc<AtomicBool>,
+    last: Arc<Mutex<Option<(String, u8)>>>, // Last update sent, to skip repeats
+}
+
+impl Progress {
+    /// Reports each change of stage or whole percent to `sink`, and stops the
+    /// parse once `cancelled` is set.
+    pub fn new(sink: impl Fn(&str, u8) + Send + Sync + 'static, cancelled: Arc<AtomicBool>) -> Self {
+        Self {
+            sink: Some(Arc::new(sink)),
+            cancelled,
+            last: Arc::default(),
+        }
+    }
+
+    /// Records that `fraction` (0.0 to 1.0) of `stage` is done. Fails once the
+    /// parse is cancelled, so loops can stop with `?`.
+    pub fn update(&self, stage: &str, fraction: f32) -> Result<()> {
+        self.check()?;
+
+        let Some(sink) = &self.sink else {
+            return Ok(());
+        };
+        let percent = (fraction.clamp(0.0, 1.0) * 100.0) as u8;
+        let mut last = self.last.lock().unwrap();
+        if last.as_ref().is_some_and(|(s, p)| s == stage && *p == percent) {
+            return Ok(());
+        }
+        *last = Some((stage.to_string(), percent));
+        drop(last);
+
+        sink(stage, percent);
+        Ok(())
+    }
+
+    /// Fails if the parse has been cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A progress reporter and every update it has sent
+    fn recorded() -> (Progress, Arc<AtomicBool>, Arc<Mutex<Vec<(String, u8)>>>) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let sent = sent.clone();
+            move |stage: &str, percent: u8| sent.lock().unwrap().push((stage.to_string(), percent))
+        };
+        (Progress::new(sink, cancelled.clone()), cancelled, sent)
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        let (progress, _, sent) = recorded();
+        for fraction in [0.0, 0.001, 0.004, 0.01, 0.015, 0.5, 1.0, 2.0] {
+            progress.update("Reading", fraction).unwrap();
+        }
+        progress.update("Indexing", 1.0).unwrap();
+        progress.update("Indexing", -1.0).unwrap();
+
+        let sent = sent.lock().unwrap();
+        let expected = [("Reading", 0), ("Reading", 1), ("Reading", 50), ("Reading", 100), ("Indexing", 100), ("Indexing", 0)];
+        assert_eq!(*sent, expected.map(|(stage, percent)| (stage.to_string(), percent)));
+    }
+
+    #[test]
+    fn cancelling_stops_checks_and_updates() {
+        let (progress, cancelled, sent) = recorded();
+        assert!(progress.check().is_ok());
+        progress.update("Reading", 0.2).unwrap();
+
+        // Clones share the flag, as a parser's worker threads do
+        let worker = progress.clone();
+        cancelled.store(true, Ordering::Relaxed);
+        assert!(matches!(progress.check(), Err(Error::Cancelled)));
+        assert!(matches!(worker.update("Reading", 0.3), Err(Error::Cancelled)));
+        assert_eq!(sent.lock().unwrap().len(), 1);
+
+        let silent = Progress::default();
+        assert!(silent.update("Reading", 0.5).is_ok() && silent.check().is_ok());
+    }
+}
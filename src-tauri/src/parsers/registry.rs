@@ -0,0 +1,199 @@
+use crate::error::{Error, Result};
+use crate::Document;
+use super::comic_parser::ComicParser;
+use super::epub_parser::EpubParser;
+use super::fb2_parser::Fb2Parser;
+use super::mobi_parser::MobiParser;
+use super::pdf_parser::PdfParser;
+use super::txt_parser::TxtParser;
+use super::progress::Progress;
+use super::{DocumentParser, FileSignature, ParseOptions};
+use std::path::{Path, PathBuf};
+
+/// Every format the reader opens. This is the only place files are matched
+/// to parsers; extensions play no part, so mislabelled files still open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Pdf,
+    Mobi,
+    Epub,
+    Fb2,
+    Comic,
+    Txt,
+}
+
+// Detection order. EPUBs can be full of images, so they're checked before
+// comics, and nearly anything passes as text, so that goes last.
+const FORMATS: [Format; 6] = [
+    Format::Pdf,
+    Format::Mobi,
+    Format::Epub,
+    Format::Fb2,
+    Format::Comic,
+    Format::Txt,
+];
+
+impl Format {
+    fn detect(self, signature: &FileSignature) -> bool {
+        match self {
+            Format::Pdf => PdfParser::detect(signature),
+            Format::Mobi => MobiParser::detect(signature),
+            Format::Epub => EpubParser::detect(signature),
+            Format::Fb2 => Fb2Parser::detect(signature),
+            Format::Comic => ComicParser::detect(signature),
+            Format::Txt => TxtParser::detect(signature),
+        }
+    }
+
+    pub fn parse(self, file_path: &PathBuf, options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        match self {
+            Format::Pdf => PdfParser::parse(file_path, options, progress),
+            Format::Mobi => MobiParser::parse(file_path, options, progress),
+            Format::Epub => EpubParser::parse(file_path, options, progress),
+            Format::Fb2 => Fb2Parser::parse(file_path, options, progress),
+            Format::Comic => ComicParser::parse(file_path, options, progress),
+            Format::Txt => TxtParser::parse(file_path, options, progress),
+        }
+    }
+
+    pub fn read_resource(self, file_path: &PathBuf, resource_path: &str) -> Result<String> {
+        match self {
+            Format::Pdf => PdfParser::read_resource(file_path, resource_path),
+            Format::Mobi => MobiParser::read_resource(file_path, resource_path),
+            Format::Epub => EpubParser::read_resource(file_path, resource_path),
+            Format::Fb2 => Fb2Parser::read_resource(file_path, resource_path),
+            Format::Comic => ComicParser::read_resource(file_path, resource_path),
+            Format::Txt => TxtParser::read_resource(file_path, resource_path),
+        }
+    }
+}
+
+/// The format of a file, from its content.
+pub fn detect(file_path: &Path) -> Result<Format> {
+    let signature = FileSignature::read(file_path)?;
+    FORMATS
+        .into_iter()
+        .find(|format| format.detect(&signature))
+        .ok_or(Error::UnsupportedFormat)
+}
+
+/// Parses a file with whichever parser recognises it. The parsers do blocking
+/// file and decompression work, so they run on the blocking thread pool
+/// rather than holding up the runtime's workers.
+pub async fn parse(file_path: &Path, options: &ParseOptions, progress: &Progress) -> Result<Document> {
+    let (file_path, options, progress) = (file_path.to_path_buf(), options.clone(), progress.clone());
+
+    tokio::task::spawn_blocking(move || {
+        progress.update("Detecting format", 0.0)?;
+        let format = detect(&file_path)?;
+        format.parse(&file_path, &options, &progress)
+    })
+    .await?
+}
+
+/// Reads a resource referenced by a document, such as an image, on the
+/// blocking thread pool.
+pub async fn read_resource(file_path: &Path, resource_path: &str) -> Result<String> {
+    let (file_path, resource_path) = (file_path.to_path_buf(), resource_path.to_string());
+
+    tokio::task::spawn_blocking(move || detect(&file_path)?.read_resource(&file_path, &resource_path))
+        .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::CompressionMethod;
+
+    /// A file in the temp directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{}", uuid::Uuid::new_v4(), name));
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    /// A zip of the named entries, the first stored uncompressed.
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (i, (name, contents)) in entries.iter().enumerate() {
+            let method = if i == 0 { CompressionMethod::Stored } else { CompressionMethod::Deflated };
+            zip.start_file(*name, FileOptions::default().compression_method(method)).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn format_of(name: &str, bytes: &[u8]) -> Result<Format> {
+        let file = TempFile::new(name, bytes);
+        detect(&file.0)
+    }
+
+    fn epub(with_container: bool, images: usize) -> Vec<u8> {
+        let pages: Vec<String> = (1..=images).map(|n| format!("OEBPS/images/{:03}.jpg", n)).collect();
+        let mut entries: Vec<(&str, &[u8])> = vec![("mimetype", b"application/epub+zip")];
+        if with_container {
+            entries.push(("META-INF/container.xml", b"<container/>"));
+        }
+        entries.push(("OEBPS/content.opf", b"<package/>"));
+        entries.extend(pages.iter().map(|page| (page.as_str(), b"\xFF\xD8\xFF".as_slice())));
+        zip(&entries)
+    }
+
+    #[test]
+    fn content_decides_not_the_extension() {
+        assert_eq!(format_of("book.epub", b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n").unwrap(), Format::Pdf);
+        assert_eq!(format_of("book", &epub(true, 0)).unwrap(), Format::Epub);
+        assert_eq!(format_of("book.pdf", b"Chapter 1\n\nIt was a dark and stormy night.").unwrap(), Format::Txt);
+
+        let mut mobi = vec![0; 78];
+        mobi[60..68].copy_from_slice(b"BOOKMOBI");
+        assert_eq!(format_of("book.txt", &mobi).unwrap(), Format::Mobi);
+    }
+
+    #[test]
+    fn epubs_full_of_images_are_not_comics() {
+        assert_eq!(format_of("art.epub", &epub(true, 40)).unwrap(), Format::Epub);
+        // Some EPUBs in the wild lack the container; the mimetype still says so
+        assert_eq!(format_of("art.epub", &epub(false, 40)).unwrap(), Format::Epub);
+
+        let cbz = zip(&[("001.jpg", b"\xFF\xD8\xFF"), ("002.jpg", b"\xFF\xD8\xFF"), ("ComicInfo.xml", b"<ComicInfo/>")]);
+        assert_eq!(format_of("issue.cbz", &cbz).unwrap(), Format::Comic);
+    }
+
+    #[test]
+    fn other_zip_documents_are_not_epubs() {
+        let odt = zip(&[
+            ("mimetype", b"application/vnd.oasis.opendocument.text"),
+            ("META-INF/manifest.xml", b"<manifest/>"),
+            ("content.xml", b"<office:document-content/>"),
+        ]);
+        assert!(matches!(format_of("letter.odt", &odt), Err(Error::UnsupportedFormat)));
+
+        // A mimetype that isn't the first, stored entry isn't looked at
+        let late = zip(&[("content.opf", b"<package/>"), ("mimetype", b"application/epub+zip")]);
+        assert!(matches!(format_of("book.epub", &late), Err(Error::UnsupportedFormat)));
+    }
+
+    #[test]
+    fn binary_files_are_not_text() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x10\0\0\0\x10\x08\x06\0\0\0";
+        assert!(matches!(format_of("cover.png", png), Err(Error::UnsupportedFormat)));
+        assert!(matches!(format_of("a.out", b"\x7FELF\x02\x01\x01\0\0\0\0\0\0\0\0\0"), Err(Error::UnsupportedFormat)));
+
+        // UTF-16 has NULs too, but in a pattern
+        let utf16: Vec<u8> = "Plain text in UTF-16".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(format_of("notes", &utf16).unwrap(), Format::Txt);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::Chapter;
+use super::pdf_parser::roman_numeral;
+
+// Longer lines are prose, not headings
+const MAX_HEADING_CHARS: usize = 80;
+const MAX_SUBTITLE_CHARS: usize = 60;
+
+// Chapters numbered in Roman numerals stop well short of this. Past it, words
+// like "mix" and "dim" would read as numbers.
+const MAX_ROMAN_NUMERAL: usize = 399;
+
+/// Which kinds of line are treated as chapter headings in plain text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChapterDetection {
+    pub numbered: bool, // "CHAPTER XII", "Chapter 3: The Storm"
+    pub parts: bool,    // "Part One", "BOOK II", "Volume 3"
+    pub all_caps: bool, // A short all-caps line between blank lines
+    pub markdown: bool, // "# Heading", "## Sub-heading"
+}
+
+impl Default for ChapterDetection {
+    fn default() -> Self {
+        Self {
+            numbered: true,
+            parts: true,
+            all_caps: true,
+            markdown: true,
+        }
+    }
+}
+
+struct Heading {
+    position: usize,
+    title: String,
+    rank: usize, // Lower ranks contain higher ones: parts > chapters > sub-headings
+}
+
+/// Finds chapter headings in plain text and turns them into a nested TOC.
+pub fn detect_chapters(content: &str, detection: &ChapterDetection) -> Vec<Chapter> {
+    let lines = lines_with_offsets(content);
+    let mut headings = Vec::new();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let (position, line) = lines[i];
+        let blank_before = i == 0 || lines[i - 1].1.is_empty();
+        let blank_after = i + 1 == lines.len() || lines[i + 1].1.is_empty();
+
+        let Some((title, rank, numbered)) = classify(line, blank_before, blank_after, detection) else {
+            i += 1;
+            continue;
+        };
+
+        // "CHAPTER IV." followed by its name on the next line
+        let mut title = title;
+        if numbered {
+            if let Some(subtitle) = subtitle(&lines, i + 1, detection) {
+                title = format!(
+                    "{}: {}",
+                    title.trim_end_matches(['.', ':', '-', '—']),
+                    subtitle
+                );
+                i += 1;
+            }
+        }
+
+        headings.push(Heading { position, title, rank });
+        i += 1;
+    }
+
+    build_chapters(&headings, content.len())
+}
+
+/// Each line without its line ending, with the offset it starts at.
+fn lines_with_offsets(content: &str) -> Vec<(usize, &str)> {
+    let mut offset = 0;
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len();
+            (start, line.trim())
+        })
+        .collect()
+}
+
+/// The heading title and rank for a line, and whether it is a numbered
+/// heading that may carry its name on the following line.
+fn classify(
+    line: &str,
+    blank_before: bool,
+    blank_after: bool,
+    detection: &ChapterDetection,
+) -> Option<(String, usize, bool)> {
+    if line.is_empty() || line.chars().count() > MAX_HEADING_CHARS {
+        return None;
+    }
+
+    if detection.markdown {
+        let hashes = line.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+            let title = line[hashes..].trim().trim_end_matches('#').trim();
+            if !title.is_empty() {
+                return Some((title.to_string(), hashes, false));
+            }
+        }
+    }
+
+    // Everything else has to stand apart from the surrounding prose
+    if !blank_before {
+        return None;
+    }
+
+    let mut words = line.split_whitespace();
+    let keyword = words.next()?.to_lowercase();
+    let number = words.next().map(|word| word.trim_end_matches(['.', ':', ',', '-', '—']));
+    let is_numbered = number.is_some_and(is_number);
+
+    if detection.parts && is_numbered && matches!(keyword.as_str(), "part" | "book" | "volume") {
+        return Some((line.to_string(), 0, false));
+    }
+    if detection.numbered && is_numbered && matches!(keyword.as_str(), "chapter" | "chap." | "chap") {
+        // Only a bare "CHAPTER IV" can have its name on the next line
+        let bare = words.next().is_none();
+        return Some((line.to_string(), 1, bare));
+    }
+
+    if detection.all_caps && blank_after && is_all_caps(line) {
+        return Some((line.to_string(), 1, false));
+    }
+
+    None
+}
+
+/// The short line naming a numbered chapter, allowing one blank line
+/// between them.
+fn subtitle(lines: &[(usize, &str)], start: usize, detection: &ChapterDetection) -> Option<String> {
+    let mut index = start;
+    if lines.get(index)?.1.is_empty() {
+        index += 1;
+    }
+    let line = lines.get(index)?.1;
+    let followed_by_blank = lines.get(index + 1).map_or(true, |(_, next)| next.is_empty());
+
+    if line.is_empty()
+        || !followed_by_blank
+        || line.chars().count() > MAX_SUBTITLE_CHARS
+        || (classify(line, true, true, detection).is_some() && !is_all_caps(line))
+    {
+        return None;
+    }
+    Some(line.to_string())
+}
+
+fn is_number(word: &str) -> bool {
+    const SPELLED: [&str; 37] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+        "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen",
+        "eighteen", "nineteen", "twenty", "thirty", "forty", "fifty", "sixty", "seventy",
+        "eighty", "ninety", "first", "second", "third", "fourth", "fifth", "sixth",
+        "seventh", "eighth", "ninth", "tenth",
+    ];
+
+    if word.is_empty() {
+        return false;
+    }
+    let lower = word.to_lowercase();
+    word.chars().all(|c| c.is_ascii_digit())
+        || roman_value(&lower).is_some_and(|value| value <= MAX_ROMAN_NUMERAL)
+        || lower.split('-').all(|part| SPELLED.contains(&part)) // "twenty-one"
+}
+
+/// The value of a lowercase Roman numeral written the standard way, so
+/// "xiv" is 14 but "iiii", "vx" and "did" aren't numerals.
+fn roman_value(numeral: &str) -> Option<usize> {
+    let digit = |c: char| match c {
+        'i' => Some(1),
+        'v' => Some(5),
+        'x' => Some(10),
+        'l' => Some(50),
+        'c' => Some(100),
+        'd' => Some(500),
+        'm' => Some(1000),
+        _ => None,
+    };
+    let digits = numeral.chars().map(digit).collect::<Option<Vec<usize>>>()?;
+
+    let value = digits.iter().enumerate().fold(0, |value, (i, &digit)| match digits.get(i + 1) {
+        Some(&next) if next > digit => value - digit as isize,
+        _ => value + digit as isize,
+    });
+    // Only the standard spelling of the value counts
+    let value = usize::try_from(value).ok().filter(|&value| value > 0)?;
+    (roman_numeral(value) == numeral).then_some(value)
+}
+
+fn is_all_caps(line: &str) -> bool {
+    line.split_whitespace()
+        .any(|word| word.chars().filter(|c| c.is_alphabetic()).count() >= 3)
+        && line.chars().filter(|c| c.is_alphabetic()).all(char::is_uppercase)
+        && !line.ends_with([',', ';'])
+}
+
+fn build_chapters(headings: &[Heading], content_len: usize) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = Vec::with_capacity(headings.len());
+    // Indices of the headings that enclose the current one
+    let mut open: Vec<usize> = Vec::new();
+
+    for (index, heading) in headings.iter().enumerate() {
+        while open.last().is_some_and(|&parent| headings[parent].rank >= heading.rank) {
+            open.pop();
+        }
+
+        chapters.push(Chapter {
+            id: format!("heading_{}", index),
+            title: heading.title.clone(),
+            start_position: heading.position,
+            end_position: 0,
+            level: open.len(),
+            parent_id: open.last().map(|&parent| chapters[parent].id.clone()),
+            href: None,
+        });
+        open.push(index);
+    }
+
+    for i in 0..chapters.len() {
+        let level = chapters[i].level;
+        chapters[i].end_position = chapters[i + 1..]
+            .iter()
+            .find(|next| next.level <= level)
+            .map(|next| next.start_position)
+            .unwrap_or(content_len);
+    }
+
+    chapters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles(content: &str, detection: &ChapterDetection) -> Vec<(String, usize)> {
+        detect_chapters(content, detection)
+            .into_iter()
+            .map(|c| (c.title, c.level))
+            .collect()
+    }
+
+    fn owned(expected: &[(&str, usize)]) -> Vec<(String, usize)> {
+        expected.iter().map(|(title, level)| (title.to_string(), *level)).collect()
+    }
+
+    #[test]
+    fn numbered_chapters() {
+        let text = "Preface text.\n\nCHAPTER XII\n\nIt was late, and the house had long since gone quiet around them.\n\n\
+                    Chapter 3: The Storm\nRain fell.\n\nChap. 4\nMore.\nAnd more.\n";
+        assert_eq!(
+            titles(text, &ChapterDetection::default()),
+            owned(&[("CHAPTER XII", 0), ("Chapter 3: The Storm", 0), ("Chap. 4", 0)])
+        );
+    }
+
+    #[test]
+    fn chapter_name_on_following_line() {
+        let text = "CHAPTER I.\n\nLoomings.\n\nCall me Ishmael. Some years ago, never mind how long precisely.\n";
+        assert_eq!(titles(text, &ChapterDetection::default()), owned(&[("CHAPTER I: Loomings.", 0)]));
+    }
+
+    #[test]
+    fn parts_contain_chapters() {
+        let prose = "The rain had not stopped for three days, and nobody expected it to.\n\n";
+        let text = format!(
+            "Part One\n\nChapter 1\n\n{prose}Chapter 2\n\n{prose}PART TWO\n\nChapter 3\n\n{prose}"
+        );
+        let text = text.as_str();
+        let chapters = detect_chapters(text, &ChapterDetection::default());
+        assert_eq!(
+            chapters.iter().map(|c| (c.title.as_str(), c.level)).collect::<Vec<_>>(),
+            [("Part One", 0), ("Chapter 1", 1), ("Chapter 2", 1), ("PART TWO", 0), ("Chapter 3", 1)]
+        );
+        assert_eq!(chapters[1].parent_id.as_deref(), Some("heading_0"));
+        assert_eq!(chapters[4].parent_id.as_deref(), Some("heading_3"));
+        assert_eq!(chapters[0].end_position, chapters[3].start_position);
+        assert_eq!(chapters[4].end_position, text.len());
+    }
+
+    #[test]
+    fn all_caps_lines_between_blank_lines() {
+        let text = "THE BEGINNING\n\nOnce upon a time.\nSHOUTED MID-PARAGRAPH\nand more.\n\nA, B, C\n\nTHE END\n";
+        assert_eq!(
+            titles(text, &ChapterDetection::default()),
+            owned(&[("THE BEGINNING", 0), ("THE END", 0)])
+        );
+    }
+
+    #[test]
+    fn markdown_headings() {
+        let text = "# Book Title\n\nIntro.\n\n## First Section\nBody.\n### Detail ###\nBody.\n## Second Section\n#hashtag\n";
+        assert_eq!(
+            titles(text, &ChapterDetection::default()),
+            owned(&[("Book Title", 0), ("First Section", 1), ("Detail", 2), ("Second Section", 1)])
+        );
+    }
+
+    #[test]
+    fn roman_numerals_must_be_well_formed() {
+        for (numeral, value) in [("i", 1), ("iv", 4), ("ix", 9), ("xiv", 14), ("xlii", 42), ("xcix", 99), ("cccxcix", 399)] {
+            assert_eq!(roman_value(numeral), Some(value), "{}", numeral);
+            assert!(is_number(&numeral.to_uppercase()), "{}", numeral);
+        }
+        for word in ["did", "mid", "mild", "civil", "dim", "iiii", "vx", "ic", "xxxx", "lil", "vv"] {
+            assert!(!is_number(word), "{}", word);
+        }
+        // Well formed, but too large to be a chapter number
+        assert_eq!(roman_value("mix"), Some(1009));
+        assert!(!is_number("mix"));
+        assert!(!is_number("CD"));
+    }
+
+    #[test]
+    fn words_made_of_numeral_letters_are_not_headings() {
+        let prose = "The rain had not stopped for three days, and nobody expected it to.\n\n";
+        let text = format!("Chapter did\n\n{prose}Part mild\n\n{prose}Chapter XIV\n\n{prose}");
+        assert_eq!(titles(&text, &ChapterDetection::default()), owned(&[("Chapter XIV", 0)]));
+    }
+
+    #[test]
+    fn prose_is_not_a_heading() {
+        let text = "He read\nChapter 3 of the manual before bed.\n\nChapter and verse were quoted at length.\n";
+        assert!(detect_chapters(text, &ChapterDetection::default()).is_empty());
+    }
+
+    #[test]
+    fn heuristics_can_be_turned_off() {
+        let text = "# Notes\n\nCHAPTER 1\n\nText.\n\nINTERLUDE\n\nPart Two\n\nText.\n";
+        let detection = ChapterDetection {
+            numbered: false,
+            parts: true,
+            all_caps: false,
+            markdown: false,
+        };
+        assert_eq!(titles(text, &detection), owned(&[("Part Two", 0)]));
+    }
+
+    #[test]
+    fn positions_point_at_heading_lines() {
+        let text = "Intro\r\n\r\nChapter 1\r\n\r\nText.\r\n";
+        let chapters = detect_chapters(text, &ChapterDetection::default());
+        assert_eq!(chapters.len(), 1);
+        assert!(text[chapters[0].start_position..].starts_with("Chapter 1"));
+    }
+}
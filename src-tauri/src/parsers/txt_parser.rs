@@ -1,36 +1,228 @@
-use crate::{Document, Chapter};
+use crate::error::{Error, Result};
+use crate::{Document, DocumentKind, ReadingDirection};
+use super::txt_chapters::detect_chapters;
+use super::progress::Progress;
+use super::{content_hash, gutenberg, DocumentParser, FileSignature, ParseOptions};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
 use std::fs;
 use std::path::PathBuf;
-use uuid::Uuid;
 
-pub async fn parse_txt(file_path: &PathBuf) -> Result<Document, String> {
-    let content =
-        fs::read_to_string(file_path).map_err(|e| format!("Failed to read text file: {}", e))?;
+// Bytes sampled when looking for the NULs of BOM-less UTF-16
+const UTF16_SAMPLE_BYTES: usize = 4096;
 
-    // Extract title from filename
-    let title = file_path
-        .file_stem()
-        .and_then(|name| name.to_str())
-        .unwrap_or("Unknown Title")
-        .to_string();
+pub struct TxtParser;
 
-    // For text files, we don't have author information
-    let author = None;
+impl DocumentParser for TxtParser {
+    /// Anything without NUL bytes, or that looks like UTF-16 (which has them).
+    fn detect(signature: &FileSignature) -> bool {
+        let head = &signature.head;
+        Encoding::for_bom(head).is_some() || detect_utf16(head).is_some() || !head.contains(&0)
+    }
+
+    fn parse(file_path: &PathBuf, options: &ParseOptions, progress: &Progress) -> Result<Document> {
+        parse_txt(file_path, options, progress)
+    }
+}
+
+/// Parses a text file, detecting its encoding unless the options name one
+/// (any WHATWG label, e.g. "windows-1252" or "shift_jis").
+pub fn parse_txt(file_path: &PathBuf, options: &ParseOptions, progress: &Progress) -> Result<Document> {
+    progress.update("Opening", 0.0)?;
+    let bytes = fs::read(file_path).map_err(|e| Error::io(file_path, e))?;
+    let (mut content, encoding) = decode_text(&bytes, options.encoding.as_deref())?;
+    let content_hash = content_hash(file_path)?;
+
+    // Project Gutenberg's header and licence are kept out of the reading flow
+    let mut appendices = Vec::new();
+    let mut header = (None, None);
+    if let Some(boilerplate) = gutenberg::find(&content) {
+        appendices = boilerplate.appendices(&content);
+        content = content[boilerplate.start..boilerplate.end].to_string();
+        header = (boilerplate.title, boilerplate.author);
+    }
+
+    // Extract title from the Gutenberg header or the filename
+    let title = header.0.unwrap_or_else(|| {
+        file_path
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("Unknown Title")
+            .to_string()
+    });
+
+    // Plain text carries no author information beyond that header
+    let author = header.1;
 
     // Estimate pages (rough calculation: ~500 words per page)
     let word_count = content.split_whitespace().count();
     let estimated_pages = (word_count / 500).max(1);
 
+    progress.update("Detecting chapters", 0.0)?;
+    let chapters = detect_chapters(&content, &options.chapter_detection);
+
     Ok(Document {
-        id: Uuid::new_v4().to_string(),
+        id: content_hash.clone(),
         title,
         author,
         file_path: file_path.clone(),
         file_type: "txt".to_string(),
+        content_hash,
+        kind: DocumentKind::Text,
+        reading_direction: ReadingDirection::LeftToRight,
+        encoding: Some(encoding.name().to_string()),
+        asin: None,
+        series: None,
+        series_index: None,
+        genres: Vec::new(),
+        description: None,
         content,
+        blocks: Vec::new(),
         current_position: 0,
         total_pages: estimated_pages,
-        chapters: Vec::new(), // TXT files don't have chapters by default
+        chapters,
+        pages: Vec::new(),
+        appendices,
         cover_image: None, // TXT files don't have cover images
     })
 }
+
+/// Looks up an encoding by label, as accepted for overrides.
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| Error::InvalidInput(format!("Unknown encoding: {}", label)))
+}
+
+/// Decodes a text file with the override if given, otherwise by its byte
+/// order mark or detection. Undecodable bytes become U+FFFD rather than
+/// failing the open.
+fn decode_text(bytes: &[u8], encoding: Option<&str>) -> Result<(String, &'static Encoding)> {
+    let (encoding, bom_length) = match encoding {
+        Some(label) => {
+            let encoding = encoding_for_label(label)?;
+            let bom_length = Encoding::for_bom(bytes)
+                .filter(|(found, _)| *found == encoding)
+                .map_or(0, |(_, length)| length);
+            (encoding, bom_length)
+        }
+        None => Encoding::for_bom(bytes).unwrap_or_else(|| (detect_encoding(bytes), 0)),
+    };
+
+    let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+    Ok((text.into_owned(), encoding))
+}
+
+fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some(utf16) = detect_utf16(bytes) {
+        return utf16;
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(None, true)
+}
+
+/// UTF-16 without a BOM: mostly-ASCII text leaves every other byte NUL.
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(UTF16_SAMPLE_BYTES) & !1];
+    if sample.is_empty() {
+        return None;
+    }
+
+    let pairs = sample.len() / 2;
+    let even_nuls = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_nuls = sample.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+
+    if odd_nuls * 10 > pairs * 4 && even_nuls * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_nuls * 10 > pairs * 4 && odd_nuls * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| match big_endian {
+                true => unit.to_be_bytes(),
+                false => unit.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    fn decoded(bytes: &[u8]) -> (String, &'static str) {
+        let (text, encoding) = decode_text(bytes, None).unwrap();
+        (text, encoding.name())
+    }
+
+    #[test]
+    fn byte_order_marks_are_used_and_dropped() {
+        assert_eq!(decoded(b"\xEF\xBB\xBFna\xC3\xAFve"), ("naïve".to_string(), "UTF-8"));
+
+        let mut le = vec![0xFF, 0xFE];
+        le.extend(utf16("naïve", false));
+        assert_eq!(decoded(&le), ("naïve".to_string(), "UTF-16LE"));
+
+        let mut be = vec![0xFE, 0xFF];
+        be.extend(utf16("naïve", true));
+        assert_eq!(decoded(&be), ("naïve".to_string(), "UTF-16BE"));
+    }
+
+    #[test]
+    fn utf16_without_a_bom_is_recognised() {
+        let text = "It was the best of times, it was the worst of times.";
+        assert_eq!(detect_utf16(&utf16(text, false)), Some(UTF_16LE));
+        assert_eq!(detect_utf16(&utf16(text, true)), Some(UTF_16BE));
+        assert_eq!(decoded(&utf16(text, true)), (text.to_string(), "UTF-16BE"));
+
+        assert_eq!(detect_utf16(text.as_bytes()), None);
+        assert_eq!(detect_utf16(&[0, 0, 0, 0]), None);
+        assert_eq!(detect_utf16(&[]), None);
+    }
+
+    #[test]
+    fn legacy_encodings_are_detected() {
+        let french = "Le cœur a ses raisons que la raison ne connaît point. « Déjà vu », dit-il, l’été dernier, à Noël.";
+        let (bytes, _, _) = WINDOWS_1252.encode(french);
+        assert_eq!(decoded(&bytes), (french.to_string(), "windows-1252"));
+
+        let japanese = "吾輩は猫である。名前はまだ無い。どこで生れたかとんと見当がつかぬ。何でも薄暗いじめじめした所でニャーニャー泣いていた事だけは記憶している。";
+        let (bytes, _, _) = SHIFT_JIS.encode(japanese);
+        assert_eq!(decoded(&bytes), (japanese.to_string(), "Shift_JIS"));
+
+        // Valid UTF-8 is taken as UTF-8
+        assert_eq!(decoded("Déjà vu".as_bytes()), ("Déjà vu".to_string(), "UTF-8"));
+    }
+
+    #[test]
+    fn overrides_win_over_detection() {
+        let (bytes, _, _) = WINDOWS_1252.encode("Déjà vu");
+        let (text, encoding) = decode_text(&bytes, Some(" ISO-8859-1 ")).unwrap();
+        // WHATWG treats Latin-1 as windows-1252
+        assert_eq!((text.as_str(), encoding.name()), ("Déjà vu", "windows-1252"));
+
+        // A BOM for the chosen encoding is dropped; any other is decoded as text
+        let (text, _) = decode_text(b"\xEF\xBB\xBFabc", Some("utf-8")).unwrap();
+        assert_eq!(text, "abc");
+        let (text, _) = decode_text(b"\xEF\xBB\xBFabc", Some("windows-1252")).unwrap();
+        assert_eq!(text, "ï»¿abc");
+    }
+
+    #[test]
+    fn unknown_labels_are_refused() {
+        assert_eq!(encoding_for_label("Shift-JIS").unwrap().name(), "Shift_JIS");
+        assert_eq!(encoding_for_label("latin1").unwrap().name(), "windows-1252");
+        for label in ["", "utf-9", "klingon", "windows 1252"] {
+            assert!(matches!(encoding_for_label(label), Err(Error::InvalidInput(_))), "{:?}", label);
+            assert!(decode_text(b"abc", Some(label)).is_err());
+        }
+    }
+}
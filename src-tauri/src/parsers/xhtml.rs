@@ -0,0 +1,576 @@
+use ego_tree::NodeRef;
+use percent_encoding::percent_decode_str;
+use scraper::{Html, Node};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A block of structured content. `position` is where the block's text starts
+/// in the plain-text projection (`Document.content`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Block {
+    pub position: usize,
+    #[serde(flatten)]
+    pub kind: BlockKind,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BlockKind {
+    Paragraph { spans: Vec<Span> },
+    Heading { level: u8, spans: Vec<Span> },
+    List { ordered: bool, items: Vec<Vec<Block>> },
+    Blockquote { blocks: Vec<Block> },
+    Preformatted { text: String },
+    Image { src: String, alt: Option<String> },
+}
+
+impl Block {
+    /// Moves this block and everything inside it `offset` bytes earlier, for
+    /// when text in front of it is cut from the content.
+    pub fn shift_back(&mut self, offset: usize) {
+        self.position -= offset;
+        match &mut self.kind {
+            BlockKind::List { items, .. } => {
+                for block in items.iter_mut().flatten() {
+                    block.shift_back(offset);
+                }
+            }
+            BlockKind::Blockquote { blocks } => {
+                for block in blocks {
+                    block.shift_back(offset);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A run of inline text sharing the same formatting.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Span {
+    pub text: String,
+    pub emphasis: bool,
+    pub strong: bool,
+    pub link: Option<String>,
+}
+
+#[derive(Clone, Default)]
+struct Style {
+    emphasis: bool,
+    strong: bool,
+    link: Option<String>,
+}
+
+/// Converts XHTML files into blocks while writing their plain-text projection,
+/// so that block positions, anchors and chapter offsets all share one space.
+#[derive(Default)]
+pub struct ContentBuilder {
+    pub content: String,
+    pub blocks: Vec<Block>,
+    file_path: String,
+    separator: Option<&'static str>,
+    space: bool,
+    block_start: Option<usize>,
+    pending_anchors: Vec<String>,
+    anchors: HashMap<String, usize>,
+    deferred: Vec<Block>, // Images found inside paragraphs, emitted after them
+}
+
+impl ContentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends one XHTML file. `file_path` is the file's path inside the book,
+    /// used to resolve image and link targets. Returns the text offset of every
+    /// element `id` in the file.
+    pub fn add_xhtml(&mut self, xhtml: &str, file_path: &str) -> HashMap<String, usize> {
+        let html = Html::parse_document(xhtml);
+        let root = html.root_element();
+        let body = root
+            .children()
+            .find(|n| n.value().as_element().is_some_and(|e| e.name() == "body"))
+            .unwrap_or(*root);
+
+        self.file_path = file_path.to_string();
+        self.separator = Some("\n\n");
+        let blocks = self.walk_blocks(body);
+        self.blocks.extend(blocks);
+
+        let end = self.content.len();
+        for id in self.pending_anchors.drain(..) {
+            self.anchors.entry(id).or_insert(end);
+        }
+        std::mem::take(&mut self.anchors)
+    }
+
+    pub fn finish(self) -> (String, Vec<Block>) {
+        (self.content, self.blocks)
+    }
+
+    fn walk_blocks(&mut self, parent: NodeRef<Node>) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut spans = Vec::new();
+
+        for child in parent.children() {
+            match child.value() {
+                Node::Element(element) if is_block(element.name()) => {
+                    self.flush_paragraph(&mut spans, &mut blocks);
+                    self.block(child, element.name(), &mut blocks);
+                }
+                _ => self.inline(child, &Style::default(), &mut spans),
+            }
+        }
+
+        self.flush_paragraph(&mut spans, &mut blocks);
+        blocks
+    }
+
+    fn block(&mut self, node: NodeRef<Node>, name: &str, blocks: &mut Vec<Block>) {
+        self.record_anchor(node);
+
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let spans = self.inline_block(node);
+                let level = name[1..].parse().unwrap_or(1);
+                let heading = self
+                    .take_block_start()
+                    .map(|position| Block { position, kind: BlockKind::Heading { level, spans } });
+                self.emit(heading, blocks);
+            }
+            "p" | "dt" | "dd" | "td" | "th" | "figcaption" | "caption" | "address" => {
+                let spans = self.inline_block(node);
+                let paragraph = self
+                    .take_block_start()
+                    .map(|position| Block { position, kind: BlockKind::Paragraph { spans } });
+                self.emit(paragraph, blocks);
+            }
+            "ul" | "ol" => {
+                let position = self.content.len();
+                let mut items = Vec::new();
+                for item in node.children() {
+                    let is_item = item.value().as_element().is_some_and(|e| e.name() == "li");
+                    if !is_item {
+                        continue;
+                    }
+                    self.record_anchor(item);
+                    // List items are separated by single line breaks in the text
+                    self.separator = Some(if items.is_empty() { "\n\n" } else { "\n" });
+                    let item_blocks = self.walk_blocks(item);
+                    if !item_blocks.is_empty() {
+                        items.push(item_blocks);
+                    }
+                }
+                if let Some(first) = items.first().and_then(|item| item.first()) {
+                    let position = first.position.max(position);
+                    blocks.push(Block { position, kind: BlockKind::List { ordered: name == "ol", items } });
+                }
+                self.separator = Some("\n\n");
+            }
+            "blockquote" => {
+                let inner = self.walk_blocks(node);
+                if let Some(first) = inner.first() {
+                    blocks.push(Block { position: first.position, kind: BlockKind::Blockquote { blocks: inner } });
+                }
+            }
+            "pre" => {
+                self.separator = Some("\n\n");
+                let text: String = node_text(node);
+                let position = self.write_preformatted(text.trim_matches('\n'));
+                if let Some(position) = position {
+                    blocks.push(Block {
+                        position,
+                        kind: BlockKind::Preformatted { text: text.trim_matches('\n').to_string() },
+                    });
+                }
+                self.separator = Some("\n\n");
+            }
+            "img" | "image" => {
+                if let Some(image) = self.image(node) {
+                    blocks.push(image);
+                }
+            }
+            "head" | "script" | "style" | "hr" => {}
+            // Containers such as div, section, figure and tables contribute their children
+            _ => {
+                let inner = self.walk_blocks(node);
+                blocks.extend(inner);
+            }
+        }
+    }
+
+    /// Collects the inline content of a paragraph-like element.
+    fn inline_block(&mut self, node: NodeRef<Node>) -> Vec<Span> {
+        self.separator = Some("\n\n");
+        self.block_start = None;
+        let mut spans = Vec::new();
+        for child in node.children() {
+            self.inline(child, &Style::default(), &mut spans);
+        }
+        spans
+    }
+
+    fn inline(&mut self, node: NodeRef<Node>, style: &Style, spans: &mut Vec<Span>) {
+        match node.value() {
+            Node::Text(text) => {
+                let written = self.write_text(text);
+                push_span(spans, written, style);
+            }
+            Node::Element(element) => {
+                self.record_anchor(node);
+                let mut style = style.clone();
+                match element.name() {
+                    "em" | "i" | "cite" => style.emphasis = true,
+                    "strong" | "b" => style.strong = true,
+                    "a" => {
+                        if let Some(href) = element.attr("href") {
+                            style.link = Some(self.resolve(href));
+                        }
+                    }
+                    "img" | "image" => {
+                        if let Some(image) = self.image(node) {
+                            self.deferred.push(image);
+                        }
+                        return;
+                    }
+                    "br" => {
+                        if self.block_start.is_some() {
+                            self.content.push('\n');
+                            self.space = false;
+                            push_span(spans, "\n".to_string(), &style);
+                        }
+                        return;
+                    }
+                    "script" | "style" => return,
+                    _ => {}
+                }
+                for child in node.children() {
+                    self.inline(child, &style, spans);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn flush_paragraph(&mut self, spans: &mut Vec<Span>, blocks: &mut Vec<Block>) {
+        let paragraph = self.take_block_start().map(|position| Block {
+            position,
+            kind: BlockKind::Paragraph { spans: std::mem::take(spans) },
+        });
+        self.emit(paragraph, blocks);
+        spans.clear();
+        self.separator = Some("\n\n");
+    }
+
+    fn emit(&mut self, block: Option<Block>, blocks: &mut Vec<Block>) {
+        blocks.extend(block);
+        blocks.append(&mut self.deferred);
+    }
+
+    fn image(&self, node: NodeRef<Node>) -> Option<Block> {
+        let element = node.value().as_element()?;
+        let src = element.attr("src").or_else(|| element.attr("href"))?;
+        Some(Block {
+            position: self.content.len(),
+            kind: BlockKind::Image {
+                src: self.resolve(src),
+                alt: element.attr("alt").map(str::to_string),
+            },
+        })
+    }
+
+    fn take_block_start(&mut self) -> Option<usize> {
+        self.space = false;
+        self.block_start.take()
+    }
+
+    /// Writes inline text with collapsed whitespace, returning what was written.
+    fn write_text(&mut self, text: &str) -> String {
+        let mut written = String::new();
+        for ch in text.chars() {
+            if ch.is_whitespace() {
+                self.space = self.block_start.is_some();
+                continue;
+            }
+            if self.space {
+                self.content.push(' ');
+                written.push(' ');
+                self.space = false;
+            }
+            self.start_text();
+            self.content.push(ch);
+            written.push(ch);
+        }
+        written
+    }
+
+    fn write_preformatted(&mut self, text: &str) -> Option<usize> {
+        if text.trim().is_empty() {
+            return None;
+        }
+        self.start_text();
+        self.content.push_str(text);
+        self.take_block_start()
+    }
+
+    /// Called before the first character of a block: emits the pending block
+    /// separator and resolves anchors that were waiting for text.
+    fn start_text(&mut self) {
+        if self.block_start.is_some() {
+            return;
+        }
+        if let Some(separator) = self.separator.take() {
+            if !self.content.is_empty() && !self.content.ends_with("\n\n") {
+                self.content.push_str(separator);
+            }
+        }
+        let position = self.content.len();
+        self.block_start = Some(position);
+        for id in self.pending_anchors.drain(..) {
+            self.anchors.entry(id).or_insert(position);
+        }
+    }
+
+    fn record_anchor(&mut self, node: NodeRef<Node>) {
+        let Some(id) = node.value().as_element().and_then(|e| e.attr("id")) else {
+            return;
+        };
+        match self.block_start {
+            // Within a block's text, the anchor is where the text has got to
+            Some(_) => {
+                self.anchors.entry(id.to_string()).or_insert(self.content.len());
+            }
+            None => self.pending_anchors.push(id.to_string()),
+        }
+    }
+
+    /// Resolves a relative href against the current file, leaving external
+    /// links as they are.
+    fn resolve(&self, href: &str) -> String {
+        if href.contains("://") || href.starts_with("data:") || href.starts_with("mailto:") {
+            return href.to_string();
+        }
+        let (path, fragment) = match href.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (href, None),
+        };
+        let resolved = if path.is_empty() {
+            self.file_path.clone()
+        } else {
+            let base_dir = self.file_path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+            let path = percent_decode_str(path).decode_utf8_lossy();
+            normalize_path(&format!("{}/{}", base_dir, path))
+        };
+        match fragment {
+            Some(fragment) => format!("{}#{}", resolved, fragment),
+            None => resolved,
+        }
+    }
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol" | "blockquote" | "pre"
+            | "div" | "section" | "article" | "main" | "aside" | "header" | "footer" | "nav"
+            | "figure" | "figcaption" | "table" | "thead" | "tbody" | "tfoot" | "tr" | "td"
+            | "th" | "caption" | "dl" | "dt" | "dd" | "hr" | "img" | "svg" | "image"
+            | "address" | "center" | "body" | "head" | "script" | "style"
+    )
+}
+
+fn push_span(spans: &mut Vec<Span>, mut text: String, style: &Style) {
+    if let Some(last) = spans.last_mut() {
+        if last.emphasis == style.emphasis && last.strong == style.strong && last.link == style.link {
+            last.text.push_str(&text);
+            return;
+        }
+        // Keep the space between two differently formatted runs outside the new one
+        if let Some(rest) = text.strip_prefix(' ') {
+            last.text.push(' ');
+            text = rest.to_string();
+        }
+    }
+    if text.is_empty() {
+        return;
+    }
+    spans.push(Span {
+        text,
+        emphasis: style.emphasis,
+        strong: style.strong,
+        link: style.link.clone(),
+    });
+}
+
+fn node_text(node: NodeRef<Node>) -> String {
+    node.descendants()
+        .filter_map(|n| n.value().as_text().map(|t| t.to_string()))
+        .collect()
+}
+
+/// Collapses `.`, `..` and separators so paths from different files compare equal.
+pub fn normalize_path(path: &str) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(files: &[(&str, &str)]) -> (String, Vec<Block>, Vec<HashMap<String, usize>>) {
+        let mut builder = ContentBuilder::new();
+        let anchors = files.iter().map(|(path, xhtml)| builder.add_xhtml(xhtml, path)).collect();
+        let (content, blocks) = builder.finish();
+        (content, blocks, anchors)
+    }
+
+    fn text(spans: &[Span]) -> String {
+        spans.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    /// Every text block's spans must read the same as `content` at its position.
+    fn assert_positions_match(content: &str, blocks: &[Block]) {
+        for block in blocks {
+            match &block.kind {
+                BlockKind::Paragraph { spans } | BlockKind::Heading { spans, .. } => {
+                    assert!(content[block.position..].starts_with(&text(spans)), "{:?}", block);
+                }
+                BlockKind::Preformatted { text } => assert!(content[block.position..].starts_with(text.as_str())),
+                BlockKind::List { items, .. } => items.iter().for_each(|item| assert_positions_match(content, item)),
+                BlockKind::Blockquote { blocks } => assert_positions_match(content, blocks),
+                BlockKind::Image { .. } => assert!(block.position <= content.len()),
+            }
+        }
+    }
+
+    #[test]
+    fn whitespace_collapses_and_entities_decode() {
+        let (content, blocks, _) = build(&[(
+            "text/ch1.xhtml",
+            "<html><head><title>Skipped</title><style>p {}</style></head><body>
+               <h1>  Call\n   Me </h1>
+               <p>Some <em>very</em> <b>bold</b>
+                  caf\u{e9} &amp; more&#8212;here.</p>
+               <script>ignored()</script>
+             </body></html>",
+        )]);
+
+        assert_eq!(content, "Call Me\n\nSome very bold caf\u{e9} & more\u{2014}here.");
+        assert_eq!(
+            blocks[0],
+            Block {
+                position: 0,
+                kind: BlockKind::Heading { level: 1, spans: vec![Span { text: "Call Me".into(), ..Default::default() }] },
+            }
+        );
+        let BlockKind::Paragraph { spans } = &blocks[1].kind else {
+            panic!("expected a paragraph, got {:?}", blocks[1]);
+        };
+        // Spaces between differently styled runs stay with the earlier run
+        assert_eq!(
+            spans.iter().map(|s| (s.text.as_str(), s.emphasis, s.strong)).collect::<Vec<_>>(),
+            [("Some ", false, false), ("very ", true, false), ("bold ", false, true), ("caf\u{e9} & more\u{2014}here.", false, false)]
+        );
+        assert_eq!(blocks.len(), 2);
+        assert_positions_match(&content, &blocks);
+    }
+
+    #[test]
+    fn blocks_are_separated_in_the_text() {
+        let (content, blocks, _) = build(&[(
+            "ch.xhtml",
+            "<body><ul><li>One</li><li>Two <i>too</i></li></ul>
+             <blockquote><p>Quoted</p></blockquote>
+             <pre>
+  code
+    here
+</pre><div>Loose <br/>text</div></body>",
+        )]);
+
+        assert_eq!(content, "One\nTwo too\n\nQuoted\n\n  code\n    here\n\nLoose\ntext");
+        let kinds: Vec<&str> = blocks
+            .iter()
+            .map(|block| match &block.kind {
+                BlockKind::List { .. } => "list",
+                BlockKind::Blockquote { .. } => "blockquote",
+                BlockKind::Preformatted { .. } => "pre",
+                BlockKind::Paragraph { .. } => "paragraph",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["list", "blockquote", "pre", "paragraph"]);
+        assert_positions_match(&content, &blocks);
+    }
+
+    #[test]
+    fn anchors_point_into_the_text() {
+        let (content, _, anchors) = build(&[
+            ("a.xhtml", "<body><p>First</p></body>"),
+            (
+                "b.xhtml",
+                "<body><h2 id=\"top\">Second</h2><div id=\"empty\"></div>
+                 <p>Words <span id=\"mid\">inside</span> a paragraph.</p><a id=\"end\"/></body>",
+            ),
+        ]);
+
+        assert_eq!(content, "First\n\nSecond\n\nWords inside a paragraph.");
+        let b = &anchors[1];
+        assert_eq!(b["top"], content.find("Second").unwrap());
+        // An id with no text of its own points at the text that follows it
+        assert_eq!(b["empty"], content.find("Words").unwrap());
+        assert_eq!(&content[b["mid"]..], " inside a paragraph.");
+        assert_eq!(b["end"], content.len());
+        assert!(anchors[0].is_empty());
+    }
+
+    #[test]
+    fn links_and_images_resolve_against_the_file() {
+        let (content, blocks, _) = build(&[(
+            "OEBPS/text/ch1.xhtml",
+            "<body><p>See <a href=\"../notes/n.xhtml#fn1\">note</a>, <a href=\"#here\">here</a>,
+             <a href=\"ch%202.xhtml\">next</a> and <a href=\"https://example.com/a\">the web</a>.
+             Before <img src=\"../images/fig.png\" alt=\"Figure\"/> after.</p>
+             <img src=\"./cover.jpg\"/></body>",
+        )]);
+
+        assert!(content.ends_with("Before after."));
+        let BlockKind::Paragraph { spans } = &blocks[0].kind else {
+            panic!("expected a paragraph, got {:?}", blocks[0]);
+        };
+        let links: Vec<&str> = spans.iter().filter_map(|span| span.link.as_deref()).collect();
+        assert_eq!(
+            links,
+            ["OEBPS/notes/n.xhtml#fn1", "OEBPS/text/ch1.xhtml#here", "OEBPS/text/ch 2.xhtml", "https://example.com/a"]
+        );
+
+        // Images inside a paragraph come after it, at the end of its text
+        let images: Vec<(&str, Option<&str>, usize)> = blocks[1..]
+            .iter()
+            .map(|block| match &block.kind {
+                BlockKind::Image { src, alt } => (src.as_str(), alt.as_deref(), block.position),
+                other => panic!("expected an image, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            images,
+            [("OEBPS/images/fig.png", Some("Figure"), content.find(" after").unwrap()), ("OEBPS/text/cover.jpg", None, content.len())]
+        );
+    }
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize_path("OEBPS/text/../images/./a.png"), "OEBPS/images/a.png");
+        assert_eq!(normalize_path("/a\\b//c"), "a/b/c");
+        assert_eq!(normalize_path("../../a"), "a");
+    }
+}
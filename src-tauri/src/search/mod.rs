@@ -0,0 +1,405 @@
+use crate::annotations::char_boundary;
+use crate::{chapter_at, page_at, Chapter, Page};
+use serde::{Deserialize, Serialize};
+
+// Target size of one indexed passage; results point inside passages, so
+// smaller passages rank more precisely at the cost of more rows
+const PASSAGE_BYTES: usize = 1500;
+
+// Characters of surrounding text returned on each side of an in-document match
+const CONTEXT_CHARS: usize = 60;
+
+/// A piece of a document stored in the full-text index.
+pub struct Passage<'a> {
+    pub start_position: usize,
+    pub chapter_id: Option<String>,
+    pub chapter_title: Option<String>,
+    pub page_label: Option<String>,
+    pub text: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibrarySearchHit {
+    pub document_id: String,
+    pub document_title: String,
+    pub chapter_id: Option<String>,
+    pub chapter_title: Option<String>,
+    pub page_label: Option<String>, // Printed page number, for paginated formats
+    pub start_position: usize, // First match, as offsets into Document.content
+    pub end_position: usize,
+    pub snippet: String,
+    pub highlights: Vec<(usize, usize)>, // Matched ranges within `snippet`
+    pub rank: f64,                       // bm25 score, lower is better
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSearchMatch {
+    pub start_position: usize, // Byte offsets into Document.content, like every stored position
+    pub end_position: usize,
+    pub chapter_id: Option<String>,
+    pub chapter_title: Option<String>,
+    pub page: Option<usize>, // Page index, for paginated formats
+    pub page_label: Option<String>,
+    pub context: String,
+    pub context_start: usize, // Byte offsets of the match within `context`
+    pub context_end: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentSearchResults {
+    pub total: usize, // Matches in the whole document, not just this page
+    pub offset: usize,
+    pub matches: Vec<DocumentSearchMatch>,
+}
+
+/// Finds every case-insensitive occurrence of `query` in `content`, returning
+/// a page of `limit` matches starting at match number `offset`. Matches are
+/// byte ranges of `content`, always on character boundaries; `offset` and
+/// `limit` count matches.
+pub fn search_document(
+    content: &str,
+    chapters: &[Chapter],
+    pages: &[Page],
+    query: &str,
+    offset: usize,
+    limit: usize,
+) -> DocumentSearchResults {
+    let ranges = find_ignoring_case(content, query);
+
+    let matches = ranges
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|&(start, end)| {
+            let context_start = content[..start]
+                .char_indices()
+                .rev()
+                .nth(CONTEXT_CHARS - 1)
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            let context_end = content[end..]
+                .char_indices()
+                .nth(CONTEXT_CHARS)
+                .map(|(i, _)| end + i)
+                .unwrap_or(content.len());
+            let chapter = chapter_at(chapters, start);
+            let page = page_at(pages, start);
+
+            DocumentSearchMatch {
+                start_position: start,
+                end_position: end,
+                chapter_id: chapter.map(|c| c.id.clone()),
+                chapter_title: chapter.map(|c| c.title.clone()),
+                page: page.map(|p| p.index),
+                page_label: page.map(|p| p.label.clone()),
+                context: content[context_start..context_end].to_string(),
+                context_start: start - context_start,
+                context_end: end - context_start,
+            }
+        })
+        .collect();
+
+    DocumentSearchResults {
+        total: ranges.len(),
+        offset,
+        matches,
+    }
+}
+
+/// Case-insensitive, non-overlapping matches of `query` as ranges of the
+/// original text. Lowercasing can change byte lengths, so matching happens on
+/// a lowered copy that remembers where each of its bytes came from.
+fn find_ignoring_case(content: &str, query: &str) -> Vec<(usize, usize)> {
+    let query = query.to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lowered = String::with_capacity(content.len());
+    let mut origins = Vec::with_capacity(content.len());
+    for (index, ch) in content.char_indices() {
+        for lower in ch.to_lowercase() {
+            lowered.push(lower);
+            origins.resize(lowered.len(), index);
+        }
+    }
+
+    lowered
+        .match_indices(&query)
+        .map(|(i, matched)| {
+            let start = origins[i];
+            let last = origins[i + matched.len() - 1];
+            let end = last + content[last..].chars().next().map_or(0, char::len_utf8);
+            (start, end)
+        })
+        .collect()
+}
+
+/// Splits content into line-aligned passages for the search index. A passage
+/// never crosses the start of a chapter or page, so each hit maps to one of
+/// each.
+pub fn passages<'a>(content: &'a str, chapters: &[Chapter], pages: &[Page]) -> Vec<Passage<'a>> {
+    let mut boundaries: Vec<usize> = chapters
+        .iter()
+        .map(|c| c.start_position)
+        .chain(pages.iter().map(|p| p.start_position))
+        .filter(|&p| p > 0 && p < content.len())
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut passages = Vec::new();
+    let mut start = 0;
+    while start < content.len() {
+        let next_boundary = boundaries
+            .iter()
+            .copied()
+            .find(|&p| p > start)
+            .unwrap_or(content.len());
+        let limit = char_boundary(content, (start + PASSAGE_BYTES).min(next_boundary));
+
+        let end = if limit == next_boundary {
+            limit
+        } else {
+            // Prefer to cut after a line break, then after any whitespace
+            let window = &content[start..limit];
+            window
+                .rfind('\n')
+                .or_else(|| window.rfind(char::is_whitespace))
+                .map(|i| start + i + window[i..].chars().next().map_or(1, char::len_utf8))
+                .filter(|&end| end > start)
+                .unwrap_or(limit)
+        };
+
+        let text = &content[start..end];
+        if !text.trim().is_empty() {
+            let chapter = chapter_at(chapters, start);
+            passages.push(Passage {
+                start_position: start,
+                chapter_id: chapter.map(|c| c.id.clone()),
+                chapter_title: chapter.map(|c| c.title.clone()),
+                page_label: page_at(pages, start).map(|p| p.label.clone()),
+                text,
+            });
+        }
+        start = end;
+    }
+
+    passages
+}
+
+/// Turns user input into an FTS5 MATCH expression. Every term is quoted so
+/// punctuation can't cause syntax errors; `"quoted phrases"` stay phrases, a
+/// trailing `*` makes a prefix search and a bare `OR` is kept as an operator.
+pub fn match_query(input: &str) -> Option<String> {
+    let mut terms = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&ch) = chars.peek() {
+        if ch.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut term = String::new();
+        let phrase = ch == '"';
+        if phrase {
+            chars.next();
+            for c in chars.by_ref() {
+                if c == '"' {
+                    break;
+                }
+                term.push(c);
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                term.push(c);
+                chars.next();
+            }
+        }
+
+        let prefix = if chars.peek() == Some(&'*') {
+            chars.next();
+            true
+        } else if let Some(stripped) = term.strip_suffix('*') {
+            term = stripped.to_string();
+            true
+        } else {
+            false
+        };
+
+        let term = term.trim();
+        if term.is_empty() {
+            continue;
+        }
+        if term == "OR" && !phrase && !prefix {
+            // "a OR OR b" is a syntax error too; one operator is meant
+            if terms.last().is_some_and(|t| t != "OR") {
+                terms.push("OR".to_string());
+            }
+            continue;
+        }
+        let quoted = format!("\"{}\"", term.replace('"', "\"\""));
+        terms.push(if prefix { format!("{}*", quoted) } else { quoted });
+    }
+
+    // A dangling operator is a syntax error
+    if terms.last().is_some_and(|t| t == "OR") {
+        terms.pop();
+    }
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Removes the match markers FTS5 put into a snippet, returning the clean
+/// text and the marked ranges within it.
+pub fn split_highlights(marked: &str, open: char, close: char) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::with_capacity(marked.len());
+    let mut highlights = Vec::new();
+    let mut open_at = None;
+
+    for ch in marked.chars() {
+        if ch == open {
+            open_at = Some(text.len());
+        } else if ch == close {
+            if let Some(start) = open_at.take() {
+                highlights.push((start, text.len()));
+            }
+        } else {
+            text.push(ch);
+        }
+    }
+
+    (text, highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terms_are_quoted() {
+        assert_eq!(match_query("white whale").as_deref(), Some("\"white\" \"whale\""));
+        assert_eq!(match_query("C++ (3rd ed.)").as_deref(), Some("\"C++\" \"(3rd\" \"ed.)\""));
+        assert_eq!(match_query("  \t\n"), None);
+        assert_eq!(match_query("\"\" *"), None);
+    }
+
+    #[test]
+    fn phrases_and_prefixes() {
+        assert_eq!(match_query("\"call me ishmael\" whale").as_deref(), Some("\"call me ishmael\" \"whale\""));
+        assert_eq!(match_query("whal*").as_deref(), Some("\"whal\"*"));
+        assert_eq!(match_query("\"white wha\"*").as_deref(), Some("\"white wha\"*"));
+        // An unclosed phrase runs to the end
+        assert_eq!(match_query("\"call me").as_deref(), Some("\"call me\""));
+    }
+
+    #[test]
+    fn embedded_quotes_start_a_phrase() {
+        assert_eq!(match_query("O\"Brien").as_deref(), Some("\"O\" \"Brien\""));
+        assert_eq!(match_query("say \"hi\"there").as_deref(), Some("\"say\" \"hi\" \"there\""));
+    }
+
+    #[test]
+    fn or_needs_a_term_on_each_side() {
+        assert_eq!(match_query("whale OR shark").as_deref(), Some("\"whale\" OR \"shark\""));
+        assert_eq!(match_query("whale OR OR  OR shark").as_deref(), Some("\"whale\" OR \"shark\""));
+        assert_eq!(match_query("OR whale OR").as_deref(), Some("\"whale\""));
+        assert_eq!(match_query("OR OR"), None);
+        // Only the bare, capitalised word is an operator
+        assert_eq!(match_query("whale or \"OR\" OR*").as_deref(), Some("\"whale\" \"or\" \"OR\" \"OR\"*"));
+    }
+
+    #[test]
+    fn highlights_are_byte_ranges_of_the_clean_text() {
+        let (text, highlights) = split_highlights("Ça \u{1}mène\u{2} à \u{1}東京\u{2}!", '\u{1}', '\u{2}');
+
+        assert_eq!(text, "Ça mène à 東京!");
+        assert_eq!(highlights, [(4, 9), (13, 19)]);
+        assert_eq!(&text[4..9], "mène");
+        assert_eq!(&text[13..19], "東京");
+    }
+
+    #[test]
+    fn unmatched_markers_are_dropped() {
+        let (text, highlights) = split_highlights("]a [b [c] d]", '[', ']');
+        assert_eq!(text, "a b c d");
+        assert_eq!(highlights, [(4, 5)]);
+    }
+
+    fn chapter(id: &str, range: std::ops::Range<usize>, level: usize, parent_id: Option<&str>) -> Chapter {
+        Chapter {
+            id: id.to_string(),
+            title: id.to_uppercase(),
+            start_position: range.start,
+            end_position: range.end,
+            level,
+            parent_id: parent_id.map(str::to_string),
+            href: None,
+        }
+    }
+
+    fn matched<'a>(content: &'a str, query: &str) -> Vec<&'a str> {
+        find_ignoring_case(content, query).into_iter().map(|(start, end)| &content[start..end]).collect()
+    }
+
+    #[test]
+    fn matches_are_ranges_of_the_original_text() {
+        // ẞ lowers to the shorter ß, and İ to the longer i + combining dot
+        let content = "ẞ und İSTANBUL, Straße, ISTANBUL";
+        assert_eq!(matched(content, "ß"), ["ẞ", "ß"]);
+        assert_eq!(matched(content, "STRASSE"), Vec::<&str>::new());
+        assert_eq!(matched(content, "straße"), ["Straße"]);
+        assert_eq!(matched(content, "İstanbul"), ["İSTANBUL"]);
+        assert_eq!(matched(content, "stanbul"), ["STANBUL", "STANBUL"]);
+        assert_eq!(matched(content, ""), Vec::<&str>::new());
+        // Matches don't overlap
+        assert_eq!(matched("aaaa", "aa"), ["aa", "aa"]);
+    }
+
+    #[test]
+    fn matches_name_their_deepest_chapter() {
+        let content = "Whale. Part one, a whale. Chapter two, the whale. Epilogue, whale.";
+        let chapters = [
+            chapter("part", 7..49, 0, None),
+            chapter("two", 26..49, 1, Some("part")),
+            chapter("epilogue", 50..content.len(), 0, None),
+        ];
+        let results = search_document(content, &chapters, &[], "WHALE", 0, 10);
+
+        let chapter_ids: Vec<_> = results.matches.iter().map(|m| m.chapter_id.as_deref()).collect();
+        assert_eq!(chapter_ids, [None, Some("part"), Some("two"), Some("epilogue")]);
+        assert_eq!(results.matches[2].chapter_title.as_deref(), Some("TWO"));
+
+        // A page of the matches, with the total of all of them
+        let page = search_document(content, &chapters, &[], "whale", 1, 2);
+        assert_eq!((page.total, page.offset, page.matches.len()), (4, 1, 2));
+        assert_eq!(page.matches[0].chapter_id.as_deref(), Some("part"));
+    }
+
+    #[test]
+    fn context_is_cut_on_character_boundaries() {
+        let before = "é".repeat(CONTEXT_CHARS + 10);
+        let after = "日本".repeat(CONTEXT_CHARS);
+        let content = format!("{}Moby{}", before, after);
+        let results = search_document(&content, &[], &[], "moby", 0, 10);
+
+        let found = &results.matches[0];
+        assert_eq!(&content[found.start_position..found.end_position], "Moby");
+        assert_eq!(&found.context[found.context_start..found.context_end], "Moby");
+        assert_eq!(found.context[..found.context_start].chars().count(), CONTEXT_CHARS);
+        assert_eq!(found.context[found.context_end..].chars().count(), CONTEXT_CHARS);
+
+        // Near the start there's only as much context as there is text
+        let results = search_document("Call me Ishmael.", &[], &[], "me", 0, 10);
+        let found = &results.matches[0];
+        assert_eq!((found.context.as_str(), found.context_start, found.context_end), ("Call me Ishmael.", 5, 7));
+    }
+}
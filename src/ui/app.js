@@ -1,5 +1,5 @@
 // Import Tauri API functions with error handling
-let invoke, open;
+let invoke, open, listen;
 
 function initTauriAPI() {
     try {
@@ -7,6 +7,7 @@ function initTauriAPI() {
             // Tauri v2 API structure
             invoke = window.__TAURI__.core.invoke;
             open = window.__TAURI__.dialog ? window.__TAURI__.dialog.open : null;
+            listen = window.__TAURI__.event ? window.__TAURI__.event.listen : null;
             console.log('✅ Tauri v2 API loaded successfully');
             return true;
         } else if (window.__TAURI__ && window.__TAURI__.tauri && window.__TAURI__.tauri.invoke) {
@@ -43,10 +44,28 @@ let fontFamily = 'georgia';
 let lineHeight = 1.6;
 let theme = 'light';
 let readingMode = '2d'; // '2d' or '3d'
+let chapterDetection = { // Plain-text chapter heuristics, kept as loaded from settings
+    detect_numbered_chapters: true,
+    detect_part_headings: true,
+    detect_caps_headings: true,
+    detect_markdown_headings: true
+};
 let library = [];
 let documentPages = [];
 let chapters = [];
+let appendices = []; // Boilerplate kept out of the reading flow
 let is3DFlipping = false; // Prevent multiple 3D flips
+let currentImportId = null; // Job id of the import in progress, for cancelling
+let currentImportJob = null; // Bulk import running in the background, as last reported
+
+// Backend errors arrive as {kind, message, details}; see error.rs for the kinds
+function errorMessage(error) {
+    return error && error.message ? error.message : String(error);
+}
+
+function errorKind(error) {
+    return error && error.kind ? error.kind : 'internal';
+}
 
 // DOM elements
 const libraryView = document.getElementById('library-view');
@@ -92,6 +111,25 @@ async function init() {
     });
     
     setupEventListeners();
+
+    try {
+        await listenForImportProgress();
+    } catch (error) {
+        console.error('Error listening for import progress:', error);
+    }
+
+    try {
+        await listenForFolderImports();
+    } catch (error) {
+        console.error('Error listening for library folder imports:', error);
+    }
+
+    try {
+        await listenForImportJobs();
+        await listenForFileDrops();
+    } catch (error) {
+        console.error('Error listening for import jobs:', error);
+    }
     
     try {
         await loadSettings();
@@ -103,10 +141,13 @@ async function init() {
     updateSettingsUI();
     
     try {
+        await loadGroups();
         await loadLibrary();
     } catch (error) {
         console.error('Error loading library:', error);
     }
+
+    await offerInterruptedImports();
     
     // Test button functionality
     testButtons();
@@ -151,6 +192,14 @@ function setupEventListeners() {
     } else {
         console.log('back-to-library button not found');
     }
+
+    const cancelImportBtn = document.getElementById('cancel-import-btn');
+    if (cancelImportBtn) {
+        cancelImportBtn.addEventListener('click', (e) => {
+            e.preventDefault();
+            cancelImport();
+        });
+    }
     
     if (readingSettingsBtn) {
         readingSettingsBtn.addEventListener('click', (e) => {
@@ -188,6 +237,85 @@ function setupEventListeners() {
         console.log('add-book-btn button not found');
     }
     
+    document.querySelectorAll('.group-filter').forEach(select => {
+        select.addEventListener('change', () => {
+            updateGroupButtons(select.dataset.kind);
+            loadLibrary();
+        });
+    });
+    GROUP_KINDS.forEach(kind => {
+        const renameBtn = document.getElementById(`rename-${kind}-btn`);
+        const deleteBtn = document.getElementById(`delete-${kind}-btn`);
+        if (renameBtn) renameBtn.addEventListener('click', () => renameSelectedGroup(kind));
+        if (deleteBtn) deleteBtn.addEventListener('click', () => deleteSelectedGroup(kind));
+    });
+
+    const shelfFilter = document.getElementById('shelf-filter');
+    if (shelfFilter) {
+        shelfFilter.addEventListener('change', () => {
+            updateShelfButtons();
+            loadLibrary();
+        });
+    }
+    const shelfButtons = {
+        'new-shelf-btn': () => openShelfDialog(null),
+        'edit-shelf-btn': () => openShelfDialog(shelves.find(shelf => shelf.id === selectedShelf())),
+        'delete-shelf-btn': deleteSelectedShelf,
+        'close-shelf-dialog': closeShelfDialog,
+        'add-shelf-rule-btn': () => addShelfRuleRow(),
+        'save-shelf-btn': saveShelf
+    };
+    Object.entries(shelfButtons).forEach(([id, handler]) => {
+        const button = document.getElementById(id);
+        if (button) button.addEventListener('click', (e) => {
+            e.preventDefault();
+            handler();
+        });
+    });
+
+    const locateMissingBtn = document.getElementById('locate-missing-btn');
+    if (locateMissingBtn) {
+        locateMissingBtn.addEventListener('click', (e) => {
+            e.preventDefault();
+            locateMissingBooks();
+        });
+    }
+
+    const folderButtons = {
+        'library-folders-btn': openFoldersDialog,
+        'close-folders-dialog': closeFoldersDialog,
+        'add-folder-btn': addLibraryFolder,
+        'scan-folders-btn': scanLibraryFolders
+    };
+    const metadataButtons = {
+        'close-metadata-dialog': closeMetadataDialog,
+        'revert-metadata-btn': revertMetadata,
+        'save-metadata-btn': saveMetadata
+    };
+    Object.entries(metadataButtons).forEach(([id, handler]) => {
+        const button = document.getElementById(id);
+        if (button) button.addEventListener('click', (e) => {
+            e.preventDefault();
+            handler();
+        });
+    });
+
+    const cancelImportJobBtn = document.getElementById('cancel-import-job-btn');
+    if (cancelImportJobBtn) {
+        cancelImportJobBtn.addEventListener('click', (e) => {
+            e.preventDefault();
+            cancelImportJob();
+        });
+    }
+
+    Object.entries(folderButtons).forEach(([id, handler]) => {
+        const button = document.getElementById(id);
+        if (button) button.addEventListener('click', (e) => {
+            e.preventDefault();
+            handler();
+        });
+    });
+
     if (addFirstBookBtn) {
         addFirstBookBtn.addEventListener('click', (e) => {
             console.log('Add first book clicked');
@@ -385,32 +513,46 @@ async function openFileDialog() {
     isOpeningFile = true;
     console.log('Opening file dialog...');
     try {
-        const filePath = await open({
-            multiple: false,
+        const filePaths = await open({
+            multiple: true,
             filters: [
                 {
                     name: 'E-books',
-                    extensions: ['epub', 'pdf', 'txt']
+                    extensions: ['epub', 'mobi', 'azw3', 'azw', 'fb2', 'zip', 'pdf', 'txt']
+                },
+                {
+                    name: 'Comics',
+                    extensions: ['cbz', 'cbr', 'cb7']
+                },
+                {
+                    name: 'All files',
+                    extensions: ['*']
                 }
             ]
         });
         
-        console.log('File selected:', filePath);
-        if (filePath) {
-            await openDocument(filePath);
+        console.log('Files selected:', filePaths);
+        // One book is opened to read; several are imported in the background
+        if (filePaths && filePaths.length === 1) {
+            await openDocument(filePaths[0]);
+        } else if (filePaths && filePaths.length > 1) {
+            await startImportJob(filePaths);
         }
     } catch (error) {
         console.error('Error opening file:', error);
-        alert('Failed to open file: ' + error);
+        alert('Failed to open file: ' + errorMessage(error));
     } finally {
         isOpeningFile = false;
     }
 }
 
 async function openDocument(filePath) {
+    const jobId = crypto.randomUUID();
+    currentImportId = jobId;
     try {
         showLoading(true);
-        const document = await invoke('open_document', { filePath });
+        showImportProgress(null);
+        const document = await invoke('open_document', { filePath, jobId });
         
         currentDocument = document;
         paginateDocument(document.content);
@@ -419,16 +561,67 @@ async function openDocument(filePath) {
         showReadingView();
         
         // Document is automatically saved to database in backend
-        await updateLibraryUI();
+        await loadLibrary();
         
     } catch (error) {
-        console.error('Error opening document:', error);
-        alert('Failed to parse document: ' + error);
+        switch (errorKind(error)) {
+            case 'cancelled':
+                console.log('Import cancelled:', filePath);
+                break;
+            case 'unsupported_format':
+                alert(`${filePath} isn't a format the reader can open.`);
+                break;
+            case 'drm_protected':
+            case 'file_not_found':
+            case 'permission_denied':
+                alert(errorMessage(error));
+                break;
+            default:
+                console.error('Error opening document:', error);
+                alert('Failed to parse document: ' + errorMessage(error));
+        }
     } finally {
+        currentImportId = null;
+        showImportProgress(null);
         showLoading(false);
     }
 }
 
+// Import progress, sent by open_document while it parses
+async function listenForImportProgress() {
+    if (!listen) return;
+
+    await listen('import-progress', (event) => {
+        const progress = event.payload;
+        if (progress.job_id === currentImportId) {
+            showImportProgress(progress);
+        }
+    });
+}
+
+function showImportProgress(progress) {
+    const label = document.getElementById('import-progress');
+    const cancelButton = document.getElementById('cancel-import-btn');
+
+    if (label) {
+        label.textContent = progress ? `${progress.stage}… ${progress.percent}%` : '';
+    }
+    if (cancelButton) {
+        cancelButton.classList.toggle('hidden', !currentImportId);
+    }
+}
+
+async function cancelImport() {
+    if (!currentImportId) return;
+
+    try {
+        await invoke('cancel_import', { jobId: currentImportId });
+    } catch (error) {
+        // The import finished before the request arrived
+        console.log('Nothing to cancel:', error);
+    }
+}
+
 // Document display
 function displayDocument() {
     if (!currentDocument) return;
@@ -445,6 +638,19 @@ function displayDocument() {
 }
 
 function paginateDocument(content) {
+    // Comics come paged already; each image is fetched when its page is shown
+    if (isComic(currentDocument)) {
+        documentPages = currentDocument.pages.map(page => {
+            // Built as an element so entry names from the archive are escaped
+            const img = document.createElement('img');
+            img.className = 'comic-page';
+            img.dataset.resource = page.image;
+            img.alt = `Page ${page.label}`;
+            return img.outerHTML;
+        });
+        return;
+    }
+
     const words = content.trim().split(/\s+/);
     documentPages = [];
     
@@ -472,16 +678,22 @@ function displayCurrentPage() {
     
     currentPage = Math.max(0, Math.min(currentPage, documentPages.length - 1));
     
-    // Update dual-page layout
-    if (leftContent) {
-        // Show current page on left, next page on right (if exists)
-        leftContent.innerHTML = documentPages[currentPage] || '';
+    // Update dual-page layout; right-to-left comics put the next page on the left
+    const rightToLeft = isRightToLeft();
+    const currentContent = rightToLeft ? rightContent : leftContent;
+    const nextContent = rightToLeft ? leftContent : rightContent;
+
+    if (currentContent) {
+        currentContent.innerHTML = documentPages[currentPage] || '';
     }
     
-    if (rightContent) {
+    if (nextContent) {
         const nextPageIndex = currentPage + 1;
-        rightContent.innerHTML = nextPageIndex < documentPages.length ? documentPages[nextPageIndex] : '';
+        nextContent.innerHTML = nextPageIndex < documentPages.length ? documentPages[nextPageIndex] : '';
     }
+
+    loadComicImages(leftContent);
+    loadComicImages(rightContent);
     
     // Update page numbers
     const leftPageNum = document.getElementById('left-page-num');
@@ -502,6 +714,30 @@ function displayCurrentPage() {
     updateAllPageCounters();
 }
 
+function isComic(doc) {
+    return Boolean(doc && doc.kind === 'comic');
+}
+
+function isRightToLeft() {
+    return isComic(currentDocument) && currentDocument.reading_direction === 'rtl';
+}
+
+// Page images are read from the archive one at a time, as they're shown
+async function loadComicImages(container) {
+    if (!container || !currentDocument) return;
+
+    for (const img of container.querySelectorAll('img.comic-page[data-resource]')) {
+        try {
+            img.src = await invoke('get_document_resource', {
+                filePath: currentDocument.file_path,
+                resourcePath: img.dataset.resource
+            });
+        } catch (error) {
+            console.error('Error loading comic page:', error);
+        }
+    }
+}
+
 // Navigation with Animation
 function nextPage() {
     if (currentPage < documentPages.length - 1) {
@@ -1174,8 +1410,14 @@ function saveReadingProgress() {
     clearTimeout(progressSaveTimeout);
     progressSaveTimeout = setTimeout(async () => {
         try {
-            const position = documentPages.length > 0 ? 
-                Math.floor((currentPage / documentPages.length) * currentDocument.total_pages) : 0;
+            // Pages are saved by where they start, except the last: reaching it
+            // saves total_pages, which is how the library knows a book is finished
+            let position = 0;
+            if (currentPage >= documentPages.length - 1 && documentPages.length > 1) {
+                position = currentDocument.total_pages;
+            } else if (documentPages.length > 0) {
+                position = Math.floor((currentPage / documentPages.length) * currentDocument.total_pages);
+            }
             
             await invoke('update_reading_progress', {
                 documentId: currentDocument.id,
@@ -1334,12 +1576,19 @@ async function loadChapters(documentId) {
         console.log('Loading chapters for document:', documentId);
         chapters = await invoke('get_chapters', { documentId });
         console.log('Chapters loaded:', chapters);
-        updateTOCUI();
     } catch (error) {
         console.error('Error loading chapters:', error);
         chapters = [];
-        updateTOCUI();
     }
+    try {
+        appendices = currentDocument
+            ? await invoke('get_document_appendices', { filePath: currentDocument.file_path })
+            : [];
+    } catch (error) {
+        console.error('Error loading appendices:', error);
+        appendices = [];
+    }
+    updateTOCUI();
 }
 
 function updateTOCUI() {
@@ -1352,12 +1601,15 @@ function updateTOCUI() {
         noChapters.className = 'chapter-item';
         noChapters.innerHTML = '<div class="chapter-link" style="opacity: 0.6;">No chapters found</div>';
         chapterList.appendChild(noChapters);
+        appendAppendicesToTOC();
         return;
     }
     
     chapters.forEach((chapter, index) => {
         const listItem = document.createElement('li');
         listItem.className = 'chapter-item';
+        // Indent nested sections under their parent entry
+        listItem.style.marginLeft = `${(chapter.level || 0) * 1.25}rem`;
         
         const link = document.createElement('a');
         link.href = '#';
@@ -1375,6 +1627,56 @@ function updateTOCUI() {
         listItem.appendChild(link);
         chapterList.appendChild(listItem);
     });
+
+    appendAppendicesToTOC();
+}
+
+// Appendices (e.g. Project Gutenberg licence text) are listed after the
+// chapters and shown in the TOC panel instead of the reading flow
+function appendAppendicesToTOC() {
+    appendices.forEach((appendix) => {
+        const listItem = document.createElement('li');
+        listItem.className = 'chapter-item appendix-item';
+
+        const link = document.createElement('a');
+        link.href = '#';
+        link.className = 'chapter-link';
+        link.innerHTML = `
+            <span class="chapter-number">Appendix</span>
+            <span class="chapter-title"></span>
+        `;
+        link.querySelector('.chapter-title').textContent = appendix.title;
+
+        link.addEventListener('click', (e) => {
+            e.preventDefault();
+            showAppendix(appendix);
+        });
+
+        listItem.appendChild(link);
+        chapterList.appendChild(listItem);
+    });
+}
+
+function showAppendix(appendix) {
+    chapterList.innerHTML = '';
+
+    const backItem = document.createElement('li');
+    backItem.className = 'chapter-item';
+    const back = document.createElement('a');
+    back.href = '#';
+    back.className = 'chapter-link';
+    back.textContent = '← Back to contents';
+    back.addEventListener('click', (e) => {
+        e.preventDefault();
+        updateTOCUI();
+    });
+    backItem.appendChild(back);
+    chapterList.appendChild(backItem);
+
+    const textItem = document.createElement('li');
+    textItem.className = 'appendix-text';
+    textItem.textContent = appendix.content;
+    chapterList.appendChild(textItem);
 }
 
 function goToChapter(chapter) {
@@ -1476,12 +1778,641 @@ function formatDate(dateString) {
 // Library management
 async function loadLibrary() {
     try {
-        library = await invoke('get_library');
+        library = await invoke('get_library', {
+            collectionId: selectedGroup('collection'),
+            tagId: selectedGroup('tag'),
+            shelfId: selectedShelf(),
+            sort: document.getElementById('library-sort')?.value || 'recent'
+        });
         updateLibraryUI();
     } catch (error) {
         console.error('Error loading library:', error);
         library = [];
     }
+    await loadShelves(); // Shelf counts follow the library
+    await checkLibraryHealth();
+}
+
+// Collections and tags. Both are "groups" to the backend, told apart by kind.
+const GROUP_KINDS = ['collection', 'tag'];
+
+function selectedGroup(kind) {
+    const select = document.getElementById(`${kind}-filter`);
+    return select && select.value ? select.value : null;
+}
+
+async function loadGroups() {
+    for (const kind of GROUP_KINDS) {
+        const select = document.getElementById(`${kind}-filter`);
+        if (!select) continue;
+
+        try {
+            const groups = await invoke('get_groups', { kind });
+            const selected = select.value;
+            select.length = 1; // Keep "All ..."
+            groups.forEach(group => {
+                const option = document.createElement('option');
+                option.value = group.id;
+                option.textContent = `${group.name} (${group.document_count})`;
+                select.appendChild(option);
+            });
+            select.value = groups.some(group => group.id === selected) ? selected : '';
+        } catch (error) {
+            console.error(`Error loading ${kind}s:`, error);
+        }
+        updateGroupButtons(kind);
+    }
+}
+
+function updateGroupButtons(kind) {
+    const hasSelection = !!selectedGroup(kind);
+    ['rename', 'delete'].forEach(action => {
+        const button = document.getElementById(`${action}-${kind}-btn`);
+        if (button) button.classList.toggle('hidden', !hasSelection);
+    });
+}
+
+async function renameSelectedGroup(kind) {
+    const groupId = selectedGroup(kind);
+    const select = document.getElementById(`${kind}-filter`);
+    if (!groupId || !select) return;
+
+    const currentName = select.selectedOptions[0].textContent.replace(/ \(\d+\)$/, '');
+    const name = prompt(`Rename ${kind}:`, currentName);
+    if (!name) return;
+
+    try {
+        await invoke('rename_group', { kind, groupId, name });
+        await loadGroups();
+    } catch (error) {
+        alert(`Failed to rename ${kind}: ` + errorMessage(error));
+    }
+}
+
+async function deleteSelectedGroup(kind) {
+    const groupId = selectedGroup(kind);
+    if (!groupId) return;
+    if (!confirm(`Delete this ${kind}? The books in it stay in your library.`)) return;
+
+    try {
+        await invoke('delete_group', { kind, groupId });
+        await loadGroups();
+        await loadLibrary();
+    } catch (error) {
+        alert(`Failed to delete ${kind}: ` + errorMessage(error));
+    }
+}
+
+// Sets a book's collections or tags from a comma-separated list of names,
+// creating any that don't exist yet
+async function editBookGroups(doc, kind) {
+    try {
+        const current = await invoke('get_document_groups', { kind, documentId: doc.id });
+        const label = kind === 'tag' ? 'Tags' : 'Collections';
+        const input = prompt(`${label} for "${doc.title}" (comma-separated):`, current.map(group => group.name).join(', '));
+        if (input === null) return;
+
+        const names = input.split(',').map(name => name.trim()).filter(name => name);
+        const sameName = (group, name) => group.name.toLowerCase() === name.toLowerCase();
+        const groups = await invoke('get_groups', { kind });
+
+        for (const name of names) {
+            const group = groups.find(group => sameName(group, name))
+                || await invoke('create_group', { kind, name });
+            groups.push(group);
+            if (!current.some(existing => existing.id === group.id)) {
+                await invoke('add_to_group', { kind, groupId: group.id, documentIds: [doc.id] });
+            }
+        }
+        for (const group of current) {
+            if (!names.some(name => sameName(group, name))) {
+                await invoke('remove_from_group', { kind, groupId: group.id, documentIds: [doc.id] });
+            }
+        }
+    } catch (error) {
+        console.error(`Error updating ${kind}s:`, error);
+        alert(`Failed to update ${kind}s: ` + errorMessage(error));
+    }
+
+    await loadGroups();
+    await loadLibrary();
+}
+
+// Smart shelves: saved queries, re-run by the backend on every load
+const SHELF_FIELDS = {
+    title: { label: 'Title', ops: ['eq', 'ne', 'contains'] },
+    author: { label: 'Author', ops: ['eq', 'ne', 'contains'] },
+    file_type: { label: 'Format', ops: ['eq', 'ne'] },
+    status: { label: 'Status', ops: ['eq', 'ne'], values: ['unread', 'in_progress', 'finished'] },
+    progress: { label: 'Progress (%)', ops: ['lt', 'gt', 'eq'], number: true },
+    added_date: { label: 'Added', ops: ['within_days', 'older_than_days'], number: true },
+    last_read: { label: 'Last read', ops: ['within_days', 'older_than_days'], number: true },
+    collection: { label: 'Collection', ops: ['eq', 'ne'] },
+    tag: { label: 'Tag', ops: ['eq', 'ne'] }
+};
+
+const SHELF_OPS = {
+    eq: 'is',
+    ne: 'is not',
+    contains: 'contains',
+    lt: 'is under',
+    gt: 'is over',
+    within_days: 'in the last N days',
+    older_than_days: 'more than N days ago'
+};
+
+let shelves = [];
+let editingShelfId = null; // Shelf open in the dialog, null for a new one
+
+function selectedShelf() {
+    const select = document.getElementById('shelf-filter');
+    return select && select.value ? select.value : null;
+}
+
+async function loadShelves() {
+    const select = document.getElementById('shelf-filter');
+    if (!select) return;
+
+    try {
+        shelves = await invoke('get_shelves');
+    } catch (error) {
+        console.error('Error loading shelves:', error);
+        return;
+    }
+
+    const selected = select.value;
+    select.length = 1; // Keep "All shelves"
+    shelves.forEach(shelf => {
+        const option = document.createElement('option');
+        option.value = shelf.id;
+        option.textContent = `${shelf.name} (${shelf.document_count})`;
+        select.appendChild(option);
+    });
+    select.value = shelves.some(shelf => shelf.id === selected) ? selected : '';
+    updateShelfButtons();
+}
+
+function updateShelfButtons() {
+    const hasSelection = !!selectedShelf();
+    ['edit-shelf-btn', 'delete-shelf-btn'].forEach(id => {
+        const button = document.getElementById(id);
+        if (button) button.classList.toggle('hidden', !hasSelection);
+    });
+}
+
+function openShelfDialog(shelf) {
+    const dialog = document.getElementById('shelf-dialog');
+    const rules = document.getElementById('shelf-rules');
+    if (!dialog || !rules) return;
+
+    // The dialog edits a flat list of rules; nested filters can only be replaced
+    const match = shelf && shelf.filter.any ? 'any' : 'all';
+    let ruleList = shelf ? (shelf.filter.all || shelf.filter.any || [shelf.filter]) : [];
+    if (ruleList.some(rule => !rule.field)) {
+        if (!confirm('This shelf uses nested rules that can\'t be shown here. Start its rules over?')) return;
+        ruleList = [];
+    }
+
+    editingShelfId = shelf ? shelf.id : null;
+    document.getElementById('shelf-dialog-title').textContent = shelf ? 'Edit Smart Shelf' : 'New Smart Shelf';
+    document.getElementById('shelf-name').value = shelf ? shelf.name : '';
+    document.getElementById('shelf-match').value = match;
+    rules.innerHTML = '';
+    ruleList.forEach(rule => addShelfRuleRow(rule));
+    if (ruleList.length === 0) addShelfRuleRow();
+
+    dialog.classList.remove('hidden');
+}
+
+function closeShelfDialog() {
+    const dialog = document.getElementById('shelf-dialog');
+    if (dialog) dialog.classList.add('hidden');
+    editingShelfId = null;
+}
+
+function addShelfRuleRow(rule = { field: 'status', op: 'eq', value: 'unread' }) {
+    const rules = document.getElementById('shelf-rules');
+    const row = document.createElement('div');
+    row.className = 'shelf-rule';
+
+    const fieldSelect = document.createElement('select');
+    fieldSelect.className = 'shelf-rule-field';
+    Object.entries(SHELF_FIELDS).forEach(([field, { label }]) => {
+        fieldSelect.add(new Option(label, field));
+    });
+    const opSelect = document.createElement('select');
+    opSelect.className = 'shelf-rule-op';
+    const valueContainer = document.createElement('span');
+    const removeButton = document.createElement('button');
+    removeButton.className = 'group-btn';
+    removeButton.textContent = '✕';
+    removeButton.addEventListener('click', () => row.remove());
+
+    // Ops and the value input depend on the field
+    const showField = (value) => {
+        const field = SHELF_FIELDS[fieldSelect.value];
+        const op = opSelect.value;
+        opSelect.length = 0;
+        field.ops.forEach(fieldOp => opSelect.add(new Option(SHELF_OPS[fieldOp], fieldOp)));
+        if (field.ops.includes(op)) opSelect.value = op;
+
+        let input;
+        if (field.values) {
+            input = document.createElement('select');
+            field.values.forEach(option => input.add(new Option(option.replace('_', ' '), option)));
+        } else {
+            input = document.createElement('input');
+            input.type = field.number ? 'number' : 'text';
+            if (field.number) input.min = 0;
+        }
+        input.className = 'shelf-rule-value';
+        if (value !== undefined) input.value = value;
+        valueContainer.replaceChildren(input);
+    };
+
+    fieldSelect.value = rule.field;
+    opSelect.add(new Option(SHELF_OPS[rule.op], rule.op));
+    opSelect.value = rule.op;
+    showField(rule.value);
+    fieldSelect.addEventListener('change', () => showField());
+
+    row.append(fieldSelect, opSelect, valueContainer, removeButton);
+    rules.appendChild(row);
+}
+
+function readShelfFilter() {
+    const rules = [...document.querySelectorAll('#shelf-rules .shelf-rule')].map(row => {
+        const field = row.querySelector('.shelf-rule-field').value;
+        const value = row.querySelector('.shelf-rule-value').value;
+        return {
+            field,
+            op: row.querySelector('.shelf-rule-op').value,
+            value: SHELF_FIELDS[field].number ? Number(value) : value
+        };
+    });
+    return { [document.getElementById('shelf-match').value]: rules };
+}
+
+async function saveShelf() {
+    const name = document.getElementById('shelf-name').value;
+    const filter = readShelfFilter();
+
+    try {
+        if (editingShelfId) {
+            await invoke('update_shelf', { shelfId: editingShelfId, name, filter });
+        } else {
+            const shelf = await invoke('create_shelf', { name, filter });
+            await loadShelves();
+            document.getElementById('shelf-filter').value = shelf.id;
+        }
+        closeShelfDialog();
+        await loadLibrary();
+    } catch (error) {
+        alert('Failed to save shelf: ' + errorMessage(error));
+    }
+}
+
+async function deleteSelectedShelf() {
+    const shelfId = selectedShelf();
+    if (!shelfId || !confirm('Delete this shelf? The books on it stay in your library.')) return;
+
+    try {
+        await invoke('delete_shelf', { shelfId });
+        document.getElementById('shelf-filter').value = '';
+        await loadLibrary();
+    } catch (error) {
+        alert('Failed to delete shelf: ' + errorMessage(error));
+    }
+}
+
+// Flags books whose files have moved or been deleted since they were added
+async function checkLibraryHealth() {
+    try {
+        const missing = await invoke('check_library_health');
+        const missingIds = new Set(missing.map(doc => doc.id));
+        library.forEach(doc => {
+            doc.file_missing = missingIds.has(doc.id);
+        });
+        updateLibraryUI();
+    } catch (error) {
+        console.error('Error checking library files:', error);
+    }
+}
+
+// Asks for a folder and relinks missing books to files there with the same content
+async function locateMissingBooks() {
+    const directory = await open({ directory: true, multiple: false });
+    if (!directory) return;
+
+    try {
+        showLoading(true);
+        const report = await invoke('relocate_missing_documents', { directory });
+        let message = `Found ${report.relocated.length} missing book(s).`;
+        if (report.still_missing.length > 0) {
+            const titles = report.still_missing.map(doc => doc.title).join('\n');
+            message += `\n\nStill missing:\n${titles}`;
+        }
+        if (report.conflicts.length > 0) {
+            const titles = report.conflicts.map(doc => doc.title).join('\n');
+            message += `\n\nFound where another book in the library is stored, so not moved:\n${titles}`;
+        }
+        alert(message);
+        await loadLibrary();
+    } catch (error) {
+        console.error('Error locating missing books:', error);
+        alert('Failed to search for missing books: ' + errorMessage(error));
+    } finally {
+        showLoading(false);
+    }
+}
+
+// Library folders, which the backend imports from and watches
+async function openFoldersDialog() {
+    const dialog = document.getElementById('folders-dialog');
+    if (!dialog) return;
+    dialog.classList.remove('hidden');
+    await loadLibraryFolders();
+}
+
+function closeFoldersDialog() {
+    const dialog = document.getElementById('folders-dialog');
+    if (dialog) dialog.classList.add('hidden');
+}
+
+async function loadLibraryFolders() {
+    const list = document.getElementById('folder-list');
+    if (!list) return;
+
+    try {
+        const folders = await invoke('get_library_folders');
+        list.innerHTML = '';
+        if (folders.length === 0) {
+            const empty = document.createElement('li');
+            empty.textContent = 'No folders yet.';
+            list.appendChild(empty);
+        }
+        folders.forEach(path => {
+            const item = document.createElement('li');
+            const label = document.createElement('span');
+            label.textContent = path;
+            const removeButton = document.createElement('button');
+            removeButton.className = 'group-btn';
+            removeButton.title = 'Stop importing from this folder';
+            removeButton.textContent = '✕';
+            removeButton.addEventListener('click', () => removeLibraryFolder(path));
+            item.append(label, removeButton);
+            list.appendChild(item);
+        });
+    } catch (error) {
+        console.error('Error loading library folders:', error);
+    }
+}
+
+async function addLibraryFolder() {
+    const path = await open({ directory: true, multiple: false });
+    if (!path) return;
+
+    try {
+        showLoading(true);
+        const summary = await invoke('add_library_folder', { path });
+        await loadLibraryFolders();
+        await loadLibrary();
+        alert(importSummaryMessage(summary));
+    } catch (error) {
+        console.error('Error adding library folder:', error);
+        alert('Failed to add folder: ' + errorMessage(error));
+    } finally {
+        showLoading(false);
+    }
+}
+
+async function removeLibraryFolder(path) {
+    if (!confirm(`Stop importing from ${path}?\n\nBooks already imported from it stay in the library.`)) return;
+
+    try {
+        await invoke('remove_library_folder', { path });
+        await loadLibraryFolders();
+    } catch (error) {
+        console.error('Error removing library folder:', error);
+        alert('Failed to remove folder: ' + errorMessage(error));
+    }
+}
+
+async function scanLibraryFolders() {
+    try {
+        showLoading(true);
+        const summary = await invoke('scan_library_folders');
+        await loadLibrary();
+        alert(importSummaryMessage(summary));
+    } catch (error) {
+        console.error('Error scanning library folders:', error);
+        alert('Failed to scan folders: ' + errorMessage(error));
+    } finally {
+        showLoading(false);
+    }
+}
+
+// Book details edited by the user. They're kept apart from what the file
+// says, so a blank field shows the book's own value again.
+let editingMetadataDoc = null; // Library document open in the dialog
+
+const METADATA_INPUTS = {
+    title: 'meta-title',
+    series: 'meta-series',
+    publisher: 'meta-publisher',
+    language: 'meta-language',
+    published_date: 'meta-published-date',
+    isbn: 'meta-isbn',
+    title_sort: 'meta-title-sort',
+    author_sort: 'meta-author-sort',
+    description: 'meta-description'
+};
+
+async function openMetadataDialog(doc) {
+    const dialog = document.getElementById('metadata-dialog');
+    if (!dialog) return;
+
+    try {
+        const metadata = await invoke('get_document_metadata', { documentId: doc.id });
+        editingMetadataDoc = doc;
+
+        Object.entries(METADATA_INPUTS).forEach(([field, id]) => {
+            document.getElementById(id).value = metadata[field] || '';
+        });
+        document.getElementById('meta-authors').value = (metadata.authors || []).join('\n');
+        document.getElementById('meta-series-index').value = metadata.series_index ?? '';
+        document.getElementById('meta-tags').value = (metadata.tags || []).join(', ');
+
+        // Without an edit the library shows the book's own title and author
+        document.getElementById('meta-title').placeholder = metadata.title ? '' : doc.title;
+        document.getElementById('meta-authors').placeholder = metadata.authors ? '' : (doc.author || 'One per line');
+
+        dialog.classList.remove('hidden');
+    } catch (error) {
+        console.error('Error loading book details:', error);
+        alert('Failed to load book details: ' + errorMessage(error));
+    }
+}
+
+function closeMetadataDialog() {
+    const dialog = document.getElementById('metadata-dialog');
+    if (dialog) dialog.classList.add('hidden');
+    editingMetadataDoc = null;
+}
+
+async function saveMetadata() {
+    if (!editingMetadataDoc) return;
+
+    const metadata = {};
+    Object.entries(METADATA_INPUTS).forEach(([field, id]) => {
+        metadata[field] = document.getElementById(id).value.trim() || null;
+    });
+    const authors = document.getElementById('meta-authors').value.split('\n').map(name => name.trim()).filter(Boolean);
+    metadata.authors = authors.length > 0 ? authors : null;
+    const seriesIndex = document.getElementById('meta-series-index').value;
+    metadata.series_index = seriesIndex === '' ? null : Number(seriesIndex);
+    metadata.tags = document.getElementById('meta-tags').value.split(',').map(tag => tag.trim()).filter(Boolean);
+
+    await updateMetadata(metadata);
+}
+
+async function revertMetadata() {
+    if (!editingMetadataDoc) return;
+    if (!confirm(`Forget your edits to "${editingMetadataDoc.title}" and show the book's own details?\n\nTags are kept.`)) return;
+
+    await updateMetadata({});
+}
+
+async function updateMetadata(metadata) {
+    try {
+        await invoke('update_document_metadata', { documentId: editingMetadataDoc.id, metadata });
+        closeMetadataDialog();
+        await loadGroups();
+        await loadLibrary();
+    } catch (error) {
+        console.error('Error saving book details:', error);
+        alert('Failed to save book details: ' + errorMessage(error));
+    }
+}
+
+function importSummaryMessage(summary) {
+    const lines = [`Imported ${summary.imported.length} book(s), skipped ${summary.skipped.length} file(s).`];
+    if (summary.relocated.length > 0) {
+        lines.push(`Found ${summary.relocated.length} moved book(s).`);
+    }
+    if (summary.failed.length > 0) {
+        lines.push('', 'Failed:', ...summary.failed.map(failure => `${failure.file_path}: ${failure.message}`));
+    }
+    return lines.join('\n');
+}
+
+// Changes the backend picked up in the library folders on its own
+async function listenForFolderImports() {
+    if (!listen) return;
+
+    await listen('library-folder-import', async (event) => {
+        const summary = event.payload;
+        summary.failed.forEach(failure => {
+            console.warn(`Couldn't import ${failure.file_path}: ${failure.message}`);
+        });
+        await loadLibrary();
+    });
+}
+
+// Bulk imports, run by the backend as jobs that survive a restart
+async function startImportJob(paths) {
+    try {
+        const job = await invoke('start_import_job', { paths });
+        showImportJob(job, 0, job.pending);
+    } catch (error) {
+        console.error('Error starting import:', error);
+        alert('Failed to start import: ' + errorMessage(error));
+    }
+}
+
+async function cancelImportJob() {
+    if (!currentImportJob) return;
+
+    try {
+        await invoke('cancel_import_job', { jobId: currentImportJob.id });
+    } catch (error) {
+        console.error('Error cancelling import:', error);
+    }
+}
+
+function showImportJob(job, done, remaining) {
+    currentImportJob = job;
+    const status = document.getElementById('import-job-status');
+    const text = document.getElementById('import-job-text');
+    if (!status || !text) return;
+
+    status.classList.toggle('hidden', !job);
+    if (job) {
+        text.textContent = `Importing books… ${done} of ${remaining}`;
+    }
+}
+
+async function listenForImportJobs() {
+    if (!listen) return;
+
+    await listen('import-job-progress', (event) => {
+        const progress = event.payload;
+        if (progress.error) {
+            console.warn(`Couldn't import ${progress.file_path}: ${progress.error.message}`);
+        }
+        showImportJob(currentImportJob || { id: progress.job_id }, progress.done, progress.remaining);
+    });
+
+    await listen('import-job-finished', async (event) => {
+        const job = event.payload;
+        showImportJob(null);
+        await loadLibrary();
+        await loadShelves();
+
+        const lines = [
+            job.status === 'finished' ? 'Import finished.' : `Import stopped with ${job.pending} file(s) left.`,
+            `Imported ${job.imported}, found ${job.relocated} moved, skipped ${job.skipped}, failed ${job.failed}.`
+        ];
+        if (job.failed > 0) {
+            try {
+                const failures = await invoke('get_import_job_failures', { jobId: job.id });
+                lines.push('', ...failures.slice(0, 10).map(failure => `${failure.file_path}: ${failure.message}`));
+                if (failures.length > 10) lines.push(`…and ${failures.length - 10} more`);
+            } catch (error) {
+                console.error('Error loading import failures:', error);
+            }
+        }
+        alert(lines.join('\n'));
+    });
+}
+
+// Files and folders dropped on the window are imported as a job
+async function listenForFileDrops() {
+    if (!listen) return;
+
+    await listen('tauri://drag-drop', async (event) => {
+        const paths = event.payload.paths || [];
+        if (paths.length > 0) {
+            await startImportJob(paths);
+        }
+    });
+}
+
+// Jobs cut short when the app last closed
+async function offerInterruptedImports() {
+    try {
+        const jobs = await invoke('get_import_jobs');
+        for (const job of jobs.filter(job => job.status === 'interrupted' && job.pending > 0)) {
+            if (confirm(`An import of ${job.total} file(s) was interrupted with ${job.pending} left.\n\nResume it?`)) {
+                const resumed = await invoke('resume_import_job', { jobId: job.id });
+                showImportJob(resumed, 0, resumed.pending);
+            } else {
+                await invoke('cancel_import_job', { jobId: job.id });
+            }
+        }
+    } catch (error) {
+        console.error('Error checking for interrupted imports:', error);
+    }
 }
 
 function addToLibrary(document) {
@@ -1501,6 +2432,7 @@ async function deleteBook(bookId) {
         
         // Update UI
         updateLibraryUI();
+        await loadShelves();
         
         console.log('Book deleted successfully:', bookId);
     } catch (error) {
@@ -1512,7 +2444,8 @@ async function deleteBook(bookId) {
 function updateLibraryUI() {
     if (!libraryGrid || !libraryEmpty) return;
     
-    if (library.length === 0) {
+    const filtered = GROUP_KINDS.some(kind => selectedGroup(kind)) || !!selectedShelf();
+    if (library.length === 0 && !filtered) {
         libraryGrid.style.display = 'none';
         libraryEmpty.style.display = 'flex';
     } else {
@@ -1525,11 +2458,16 @@ function updateLibraryUI() {
             libraryGrid.appendChild(item);
         });
     }
+
+    const locateMissingBtn = document.getElementById('locate-missing-btn');
+    if (locateMissingBtn) {
+        locateMissingBtn.classList.toggle('hidden', !library.some(doc => doc.file_missing));
+    }
 }
 
 function createLibraryItem(doc) {
     const item = document.createElement('div');
-    item.className = 'library-item';
+    item.className = doc.file_missing ? 'library-item missing' : 'library-item';
     
     // Add progress indicator
     const progressPercent = doc.total_pages > 0 ? (doc.current_position / doc.total_pages * 100).toFixed(1) : 0;
@@ -1538,7 +2476,11 @@ function createLibraryItem(doc) {
         <div class="library-item-header">
             <h4>${doc.title}</h4>
             <div class="library-item-actions">
+                ${doc.file_missing ? '<span class="missing-badge" title="The file has moved or been deleted">Missing</span>' : ''}
                 <span class="progress-badge">${progressPercent}%</span>
+                <button class="group-book-btn" data-kind="collection" title="Collections">📁</button>
+                <button class="group-book-btn" data-kind="tag" title="Tags">🏷️</button>
+                <button class="metadata-book-btn" title="Edit details">✎</button>
                 <button class="delete-book-btn" data-book-id="${doc.id}" title="Delete book">✕</button>
             </div>
         </div>
@@ -1550,6 +2492,19 @@ function createLibraryItem(doc) {
     `;
     
     item.addEventListener('click', async () => {
+        if (doc.file_missing) {
+            if (confirm(`The file for "${doc.title}" can't be found.\n\nSearch a folder for it?`)) {
+                await locateMissingBooks();
+            }
+            return;
+        }
+
+        // Comics need their page list, which only a full open provides
+        if (['cbz', 'cbr', 'cb7'].includes(doc.file_type)) {
+            await openDocument(doc.file_path);
+            return;
+        }
+
         try {
             showLoading(true);
             // Load document content from file since database only stores metadata
@@ -1578,12 +2533,35 @@ function createLibraryItem(doc) {
             
         } catch (error) {
             console.error('Error opening library document:', error);
-            alert('Failed to load document: ' + error);
+            if (errorKind(error) === 'file_not_found') {
+                doc.file_missing = true;
+                updateLibraryUI();
+                if (confirm(`The file for "${doc.title}" can't be found.\n\nSearch a folder for it?`)) {
+                    await locateMissingBooks();
+                }
+            } else {
+                alert('Failed to load document: ' + errorMessage(error));
+            }
         } finally {
             showLoading(false);
         }
     });
     
+    item.querySelectorAll('.group-book-btn').forEach(button => {
+        button.addEventListener('click', async (e) => {
+            e.stopPropagation(); // Prevent opening the book
+            await editBookGroups(doc, button.dataset.kind);
+        });
+    });
+
+    const metadataBtn = item.querySelector('.metadata-book-btn');
+    if (metadataBtn) {
+        metadataBtn.addEventListener('click', async (e) => {
+            e.stopPropagation(); // Prevent opening the book
+            await openMetadataDialog(doc);
+        });
+    }
+
     // Add delete button event listener
     const deleteBtn = item.querySelector('.delete-book-btn');
     if (deleteBtn) {
@@ -1620,11 +2598,11 @@ async function performSearch() {
         
         console.log('Search results:', results);
         // You could implement search result highlighting here
-        alert(`Found ${results.length} results for "${query}"`);
+        alert(`Found ${results.total} results for "${query}"`);
         
     } catch (error) {
         console.error('Search error:', error);
-        alert('Search failed: ' + error);
+        alert('Search failed: ' + errorMessage(error));
     }
 }
 
@@ -1653,12 +2631,18 @@ function handleKeyboard(e) {
     } else {
         switch (e.key) {
             case 'ArrowLeft':
+                e.preventDefault();
+                isRightToLeft() ? nextPage() : previousPage();
+                break;
+            case 'ArrowRight':
+                e.preventDefault();
+                isRightToLeft() ? previousPage() : nextPage();
+                break;
             case 'PageUp':
                 e.preventDefault();
                 console.log('Previous page');
                 previousPage();
                 break;
-            case 'ArrowRight':
             case 'PageDown':
             case ' ':
                 e.preventDefault();
@@ -1699,7 +2683,8 @@ async function saveSettings() {
         justify_text: true, // Not implemented in frontend yet
         hyphenation: true, // Not implemented in frontend yet
         animation_speed: 'normal', // Not implemented in frontend yet
-        page_curl: true // Not implemented in frontend yet
+        page_curl: true, // Not implemented in frontend yet
+        ...chapterDetection
     };
     
     try {
@@ -1724,6 +2709,12 @@ async function loadSettings() {
         theme = settings.theme || theme;
         readingMode = settings.reading_mode || readingMode;
         wordsPerPage = settings.words_per_page || wordsPerPage;
+        chapterDetection = {
+            detect_numbered_chapters: settings.detect_numbered_chapters ?? true,
+            detect_part_headings: settings.detect_part_headings ?? true,
+            detect_caps_headings: settings.detect_caps_headings ?? true,
+            detect_markdown_headings: settings.detect_markdown_headings ?? true
+        };
         
         console.log('Settings loaded from database:', settings);
         